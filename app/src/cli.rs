@@ -0,0 +1,289 @@
+//! Command-line interface of the execution service.
+
+use clap::{ArgEnum, Args, Parser, Subcommand};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[clap(name = "app", version, about = "Runs WASM modules on datasets inside the execution enclave")]
+pub struct Cli {
+    /// Signed enclave to load.
+    #[clap(long, global = true, default_value = "enclave.signed.so")]
+    pub enclave: PathBuf,
+
+    /// Launch the enclave in debug mode.
+    #[clap(long, global = true)]
+    pub debug: bool,
+
+    /// Directory holding the module registry.
+    #[clap(long, global = true, default_value = "modules")]
+    pub registry: PathBuf,
+
+    /// Append-only log recording the signed receipt of every job that `run` and `serve` execute.
+    #[clap(long, global = true, default_value = "receipts.log")]
+    pub receipt_log: PathBuf,
+
+    /// Output format.
+    #[clap(long, global = true, arg_enum, default_value = "text")]
+    pub format: OutputFormat,
+
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run a module against a dataset.
+    Run(RunArgs),
+    /// List the modules in the registry.
+    ListModules,
+    /// Add a module binary to the registry.
+    AddModule {
+        name: String,
+        version: String,
+        file: PathBuf,
+    },
+    /// Remove a module from the registry.
+    RemoveModule {
+        /// Name, name@version, or (a prefix of) the module's SHA-256.
+        module: String,
+    },
+    /// Send a dataset to the enclave encrypted, and store it sealed so that only the enclave can read it.
+    ImportDataset(ImportDatasetArgs),
+    /// Print an SGX report of the enclave that commits to its encryption and receipt signing keys.
+    Attest(AttestArgs),
+    /// Check the signatures and numbering of the receipts in the receipt log.
+    VerifyReceipts,
+    /// Check that the enclave accepts a module, without running it.
+    Validate(ModuleArgs),
+    /// Show a module's hash, ABI declaration, imports and exports.
+    Inspect(ModuleArgs),
+    /// Keep the enclave running and accept jobs over a local HTTP/JSON API.
+    Serve {
+        /// Address to listen on.
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Jobs run concurrently; defaults to, and is capped at, one less than the enclave's TCS count.
+        #[clap(long)]
+        workers: Option<usize>,
+        /// Jobs that may wait for a worker before new submissions are refused.
+        #[clap(long, default_value = "64")]
+        queue_capacity: usize,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct AttestArgs {
+    /// File holding the raw `sgx_target_info_t` of the enclave the report is for, such as the quoting
+    /// enclave. Without it the report targets no enclave, and only shows the measurements and keys.
+    #[clap(long)]
+    pub target_info: Option<PathBuf>,
+
+    /// File to write the raw `sgx_report_t` to.
+    #[clap(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ModuleArgs {
+    /// Name, name@version or (a prefix of) the SHA-256 of a registered module, or a path to a `.wasm` file.
+    #[clap(long, short)]
+    pub module: String,
+}
+
+#[derive(Debug, Args)]
+pub struct DatasetArgs {
+    /// Dataset file.
+    #[clap(long, short)]
+    pub data: PathBuf,
+
+    /// Encoding of the dataset: `json`, `csv` with a header row naming the columns, binary `columnar`
+    /// (given as a `.ntcc` file, or as JSON that is sent as such for the enclave to encode), or `sealed` by
+    /// `import-dataset`. Defaults to `csv` for `.csv` files, `columnar` for `.ntcc` files, `sealed` for
+    /// `.sealed` files and `json` otherwise.
+    #[clap(long, possible_values = &["json", "csv", "columnar", "sealed"])]
+    pub data_format: Option<String>,
+
+    /// Field delimiter of a CSV dataset.
+    #[clap(long, default_value = ",")]
+    pub csv_delimiter: char,
+
+    /// Quote character of a CSV dataset.
+    #[clap(long, default_value = "\"")]
+    pub csv_quote: char,
+
+    /// The data owner's policy, as a JSON object with optional `modules` (the SHA-256 of each module allowed
+    /// to run on the dataset, in hex), `max_output_bytes` and `min_rows`. `import-dataset` seals it with the
+    /// dataset; the enclave enforces it, and the policy sealed with a dataset, on every job.
+    #[clap(long)]
+    pub policy: Option<PathBuf>,
+}
+
+impl DatasetArgs {
+    /// The dataset's encoding, as given or inferred from its file extension.
+    pub fn format(&self) -> Result<DataFormat, String> {
+        let extension = self.data.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase);
+        let name = match (&self.data_format, extension.as_deref()) {
+            (Some(name), _) => name.as_str(),
+            (None, Some("csv")) => "csv",
+            (None, Some("ntcc")) => "columnar",
+            (None, Some("sealed")) => "sealed",
+            (None, _) => "json",
+        };
+        DataFormat::new(name, self.csv_delimiter, self.csv_quote)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    #[clap(flatten)]
+    pub module: ModuleArgs,
+
+    #[clap(flatten)]
+    pub dataset: DatasetArgs,
+
+    /// JSON schema of the dataset; defaults to an empty schema.
+    #[clap(long, short)]
+    pub schema: Option<PathBuf>,
+
+    /// Also write the result to this file.
+    #[clap(long, short)]
+    pub output: Option<PathBuf>,
+
+    /// Job limits as comma-separated `key=value` pairs: `fuel` (0 lets the enclave choose),
+    /// `log-bytes`, `result-bytes` and, for queued jobs, `timeout-ms`.
+    #[clap(long, default_value = "")]
+    pub limits: Limits,
+
+    /// Send the module, data and schema encrypted to the enclave, which encrypts the result and log to a key
+    /// generated for the job, as it would for a remote requester.
+    #[clap(long)]
+    pub encrypt: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportDatasetArgs {
+    #[clap(flatten)]
+    pub dataset: DatasetArgs,
+
+    /// Which enclaves may unseal the dataset: only this enclave build (`mrenclave`), or any enclave signed
+    /// with the same key (`mrsigner`).
+    #[clap(long, arg_enum, default_value = "mrenclave")]
+    pub seal_policy: SealPolicy,
+
+    /// File to store the sealed dataset in; run jobs on it with `run --data <file>`.
+    #[clap(long, short)]
+    pub output: PathBuf,
+}
+
+/// Key a sealed dataset is bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ArgEnum)]
+pub enum SealPolicy {
+    #[clap(name = "mrenclave")]
+    MrEnclave,
+    #[clap(name = "mrsigner")]
+    MrSigner,
+}
+
+/// Encoding of a dataset. The enclave converts CSV into the JSON column arrays modules take, typing each
+/// column as the dataset schema declares it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DataFormat {
+    #[default]
+    Json,
+    /// CSV with a header row.
+    Csv { delimiter: u8, quote: u8 },
+    /// The binary columnar encoding of `ntc_guest::columnar`.
+    Columnar,
+    /// A dataset sealed by the enclave, carrying its own encoding.
+    Sealed,
+}
+
+impl DataFormat {
+    /// The format called `name` (`json`, `csv`, `columnar` or `sealed`); the delimiter and quote only apply
+    /// to CSV.
+    pub fn new(name: &str, delimiter: char, quote: char) -> Result<Self, String> {
+        match name {
+            "json" => Ok(DataFormat::Json),
+            "csv" => {
+                let byte = |what: &str, c: char| match c {
+                    '\n' | '\r' => Err(format!("unsupported CSV {} {:?}", what, c)),
+                    c if c.is_ascii() => Ok(c as u8),
+                    c => Err(format!("unsupported CSV {} {:?}", what, c)),
+                };
+                let (delimiter, quote) = (byte("delimiter", delimiter)?, byte("quote", quote)?);
+                if delimiter == quote {
+                    return Err("CSV delimiter and quote must differ".to_string());
+                }
+                Ok(DataFormat::Csv { delimiter, quote })
+            }
+            "columnar" => Ok(DataFormat::Columnar),
+            "sealed" => Ok(DataFormat::Sealed),
+            _ => Err(format!("unknown data format `{}`; expected `json`, `csv`, `columnar` or `sealed`", name)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ArgEnum)]
+pub enum OutputFormat {
+    /// Progress and results for people.
+    Text,
+    /// A single JSON document on stdout.
+    Json,
+}
+
+/// Initial size of the buffer the enclave writes the JSON result into.
+pub const RESULT_BUFFER_SIZE: usize = 4096;
+
+/// Size of the buffer that receives the module's log; matches the enclave's per-job log limit.
+pub const LOG_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Limits applied to a job; the enclave caps them further.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub fuel: u64,
+    /// Capacity of the buffer receiving the module's log.
+    pub log_bytes: usize,
+    /// Initial capacity of the result buffer; it grows if the enclave asks for more.
+    pub result_bytes: usize,
+    /// How long a queued job may take from submission to completion.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            fuel: 0,
+            log_bytes: LOG_BUFFER_SIZE,
+            result_bytes: RESULT_BUFFER_SIZE,
+            timeout: None,
+        }
+    }
+}
+
+impl FromStr for Limits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = Limits::default();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = match pair.find('=') {
+                Some(eq) => (&pair[..eq], &pair[eq + 1..]),
+                None => return Err(format!("expected key=value, found `{}`", pair)),
+            };
+            let invalid = |_| format!("invalid value for `{}`: `{}`", key, value);
+            match key {
+                "fuel" => limits.fuel = value.parse().map_err(invalid)?,
+                "log-bytes" => limits.log_bytes = value.parse().map_err(invalid)?,
+                "result-bytes" => limits.result_bytes = value.parse().map_err(invalid)?,
+                "timeout-ms" => limits.timeout = Some(Duration::from_millis(value.parse().map_err(invalid)?)),
+                _ => return Err(format!("unknown limit `{}`", key)),
+            }
+        }
+        if limits.log_bytes == 0 || limits.result_bytes == 0 {
+            return Err("buffer sizes must be positive".to_string());
+        }
+        Ok(limits)
+    }
+}
@@ -0,0 +1,124 @@
+//! Envelopes that only the enclave can open, for sending it data the host must not read, and the envelopes
+//! it answers encrypted jobs with.
+//!
+//! Mirrors `envelope.rs` in the enclave: an ephemeral P-256 key agrees a secret with the recipient's key, the
+//! first 16 bytes of SHA-256(`KDF_LABEL` || x) key AES-128-GCM, and the envelope's context is authenticated as
+//! additional data. The envelope is the SEC1 uncompressed ephemeral key, the 12-byte nonce, and the ciphertext
+//! followed by its 16-byte tag.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Nonce};
+use crate::cli::DataFormat;
+use crate::executor::ecall_format;
+use p256::ecdh::{self, EphemeralSecret, SharedSecret};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::{PublicKey, SecretKey};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// Context of the envelopes in which datasets are sent to `import_dataset`.
+pub const DATASET_CONTEXT: &[u8] = b"ntc-dataset";
+
+/// Context of the envelopes in which jobs are sent to `exec_encrypted`.
+pub const JOB_CONTEXT: &[u8] = b"ntc-job";
+
+/// Context of the envelope holding an encrypted job's result or error message.
+pub const RESULT_CONTEXT: &[u8] = b"ntc-result";
+
+/// Context of the envelope holding an encrypted job's log.
+pub const LOG_CONTEXT: &[u8] = b"ntc-log";
+
+/// Prefix of the hashed shared secret from which envelope keys are derived.
+const KDF_LABEL: &[u8] = b"ntc-envelope-v1";
+
+const PUBLIC_KEY_LEN: usize = 65;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Encrypts `plaintext` for `context` to a SEC1-encoded public key, such as the enclave's.
+pub fn seal(recipient: &[u8], plaintext: &[u8], context: &[u8]) -> Result<Vec<u8>, String> {
+    let recipient =
+        PublicKey::from_sec1_bytes(recipient).map_err(|_| "recipient key is not a P-256 public key".to_string())?;
+    let secret = EphemeralSecret::random(&mut OsRng);
+    let cipher = cipher(&secret.diffie_hellman(&recipient));
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: context })
+        .map_err(|_| "encryption failed".to_string())?;
+
+    let mut envelope = secret.public_key().to_encoded_point(false).as_bytes().to_vec();
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+
+/// Decrypts an envelope sealed to `secret` for `context`.
+pub fn open(secret: &SecretKey, envelope: &[u8], context: &[u8]) -> Result<Vec<u8>, String> {
+    if envelope.len() < PUBLIC_KEY_LEN + NONCE_LEN + TAG_LEN {
+        return Err("envelope is truncated".to_string());
+    }
+    let (sender, rest) = envelope.split_at(PUBLIC_KEY_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let sender = PublicKey::from_sec1_bytes(sender)
+        .map_err(|_| "envelope does not start with a P-256 public key".to_string())?;
+    let cipher = cipher(&ecdh::diffie_hellman(secret.to_nonzero_scalar(), sender.as_affine()));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: context })
+        .map_err(|_| "envelope cannot be decrypted with this key".to_string())
+}
+
+fn cipher(shared: &SharedSecret) -> Aes128Gcm {
+    let digest = Sha256::new().chain_update(KDF_LABEL).chain_update(shared.raw_secret_bytes()).finalize();
+    Aes128Gcm::new_from_slice(&digest[..16]).expect("AES-128 takes a 16-byte key")
+}
+
+/// Encodes a job for `exec_encrypted`, whose result and log are to be encrypted to `reply_key`, under the data
+/// owner's `policy` (JSON, or empty for none). The layout is described in `job_request.rs` in the enclave.
+pub fn job_request(
+    reply_key: &PublicKey,
+    binary: &[u8],
+    data: &[u8],
+    format: DataFormat,
+    schema: &[u8],
+    policy: &[u8],
+) -> Vec<u8> {
+    let (data_format, csv_delimiter, csv_quote) = ecall_format(format);
+    let mut request = b"NTCJ\x01".to_vec();
+    request.extend_from_slice(reply_key.to_encoded_point(false).as_bytes());
+    request.extend_from_slice(&[data_format as u8, csv_delimiter, csv_quote]);
+    for field in &[binary, data, schema, policy] {
+        request.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        request.extend_from_slice(field);
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelopes_open_only_with_their_key_and_context() {
+        let secret = SecretKey::random(&mut OsRng);
+        let public = secret.public_key().to_encoded_point(false);
+        let envelope = seal(public.as_bytes(), b"{\"x\": [1, 2]}", RESULT_CONTEXT).unwrap();
+        assert_eq!(envelope.len(), PUBLIC_KEY_LEN + NONCE_LEN + 13 + TAG_LEN);
+        assert_eq!(open(&secret, &envelope, RESULT_CONTEXT).unwrap(), b"{\"x\": [1, 2]}");
+
+        assert!(open(&secret, &envelope, LOG_CONTEXT).is_err());
+        assert!(open(&SecretKey::random(&mut OsRng), &envelope, RESULT_CONTEXT).is_err());
+        let mut tampered = envelope.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(&secret, &tampered, RESULT_CONTEXT).is_err());
+        assert!(seal(b"not a key", b"", RESULT_CONTEXT).is_err());
+
+        let csv = DataFormat::new("csv", ';', '"').unwrap();
+        let request = job_request(&secret.public_key(), b"\0asm", b"a,b\n1,2\n", csv, b"{}", b"");
+        assert_eq!(&request[..5], b"NTCJ\x01");
+        assert_eq!(&request[5..70], public.as_bytes());
+        assert_eq!(&request[70..73], b"\x01;\"");
+        assert_eq!(&request[73..81], b"\x04\0\0\0\0asm");
+        assert_eq!(request.len(), 81 + 4 + 8 + 4 + 2 + 4);
+    }
+}
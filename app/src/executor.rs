@@ -0,0 +1,774 @@
+//! ECALL plumbing for running jobs in the execution enclave.
+
+use crate::cli::{DataFormat, Limits, SealPolicy};
+use crate::envelope;
+use crate::receipt_log::ReceiptLog;
+use crate::registry;
+use ntc_receipt::verify as receipt_verify;
+use ntc_receipt::{encode_hex, Receipt, SIGNED_RECEIPT_LEN};
+use p256::SecretKey;
+use serde_json::{json, Value};
+use sgx_types::*;
+use sgx_urts::SgxEnclave;
+use sha2::{Digest, Sha256};
+use std::error;
+use std::fmt;
+use std::mem;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// SHA-256 of a module binary, as returned by `register_module`.
+pub type ModuleHash = [u8; 32];
+
+/// `ExecErrorCode::OutputDecoding` in the enclave.
+const OUTPUT_DECODING: u32 = 8;
+
+/// `ExecErrorCode::InvalidInput` in the enclave.
+const INVALID_INPUT: u32 = 14;
+
+/// `ExecErrorCode::InvalidRequest` in the enclave, the one failure of an encrypted job reported in plaintext.
+const INVALID_REQUEST: u32 = 16;
+
+/// The enclave's configuration, as signed into the enclave this app is built with.
+static ENCLAVE_CONFIG: &str = include_str!("../../enclave/Enclave.config.xml");
+
+/// Capacity of the buffer receiving the enclave's reason for rejecting a module.
+const ERROR_BUFFER_SIZE: usize = 4096;
+
+/// Size of the enclave's SEC1 uncompressed public key.
+pub const PUBLIC_KEY_LEN: usize = 65;
+
+/// Bytes a sealed dataset takes beyond the dataset itself and its policy: the sealed data header and the
+/// authenticated format. The buffer grows if this falls short.
+const SEAL_OVERHEAD: usize = 1024;
+
+extern "C" {
+    fn register_module(eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        binary: *const u8,
+        binary_len: usize,
+        module_hash_out: *mut u8,
+        error_out: *mut u8,
+        error_capacity: usize,
+        error_len: *mut usize,
+        exec_error: *mut u32,
+    ) -> sgx_status_t;
+
+    fn exec_module(eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        module_hash: *const u8,
+        data_in: *const u8,
+        data_len: usize,
+        data_format: u32,
+        csv_delimiter: u8,
+        csv_quote: u8,
+        schema_in: *const u8,
+        schema_len: usize,
+        policy_in: *const u8,
+        policy_len: usize,
+        fuel_limit: u64,
+        timestamp: u64,
+        result_out: *mut u8,
+        result_capacity: usize,
+        result_len: *mut usize,
+        log_out: *mut u8,
+        log_capacity: usize,
+        log_len: *mut usize,
+        receipt_out: *mut u8,
+        receipt_capacity: usize,
+        receipt_len: *mut usize,
+        fuel_used: *mut u64,
+        exec_error: *mut u32,
+    ) -> sgx_status_t;
+
+    fn exec_encrypted(eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        request_in: *const u8,
+        request_len: usize,
+        fuel_limit: u64,
+        timestamp: u64,
+        result_out: *mut u8,
+        result_capacity: usize,
+        result_len: *mut usize,
+        log_out: *mut u8,
+        log_capacity: usize,
+        log_len: *mut usize,
+        receipt_out: *mut u8,
+        receipt_capacity: usize,
+        receipt_len: *mut usize,
+        fuel_used: *mut u64,
+        exec_error: *mut u32,
+    ) -> sgx_status_t;
+
+    fn enclave_public_key(eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        public_key_out: *mut u8,
+    ) -> sgx_status_t;
+
+    fn enclave_report(eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        target_info: *const sgx_target_info_t,
+        report_out: *mut sgx_report_t,
+        encryption_key_out: *mut u8,
+        signing_key_out: *mut u8,
+    ) -> sgx_status_t;
+
+    fn import_dataset(eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        envelope_in: *const u8,
+        envelope_len: usize,
+        data_format: u32,
+        csv_delimiter: u8,
+        csv_quote: u8,
+        policy_in: *const u8,
+        policy_len: usize,
+        seal_policy: u32,
+        sealed_out: *mut u8,
+        sealed_capacity: usize,
+        sealed_len: *mut usize,
+        exec_error: *mut u32,
+    ) -> sgx_status_t;
+
+    fn module_cache_stats(eid: sgx_enclave_id_t,
+        retval: *mut sgx_status_t,
+        hits: *mut u64,
+        misses: *mut u64,
+        entries: *mut u64,
+        bytes: *mut u64,
+    ) -> sgx_status_t;
+}
+
+
+/// Describes an `exec_error` code reported by the enclave. Mirrors `ExecErrorCode` in the enclave.
+pub fn exec_error_name(code: u32) -> &'static str {
+    match code {
+        0 => "success",
+        1 => "invalid module",
+        2 => "unresolved import",
+        3 => "invalid export",
+        4 => "trap",
+        5 => "out of fuel",
+        6 => "memory limit exceeded",
+        7 => "memory access error",
+        8 => "output decoding error",
+        9 => "internal error",
+        10 => "aborted",
+        11 => "exited",
+        12 => "start function failed",
+        13 => "module not found",
+        14 => "invalid input",
+        15 => "output rejected",
+        16 => "invalid request",
+        17 => "policy violation",
+        _ => "unknown error",
+    }
+}
+
+
+/// Why the enclave did not accept a module or complete a job.
+#[derive(Debug)]
+pub enum EnclaveError {
+    /// The ECALL itself failed.
+    Ecall(sgx_status_t),
+    /// The enclave reported an `exec_error` code, with its message.
+    Job { code: u32, message: String },
+    /// The enclave identified the module by a different hash than the host computed.
+    HashMismatch { expected: String, actual: String },
+    /// The job ran, but its receipt could not be added to the receipt log.
+    ReceiptLog(String),
+}
+
+impl EnclaveError {
+    /// The error as reported in JSON output.
+    pub fn to_json(&self) -> Value {
+        match self {
+            EnclaveError::Job { code, message } => {
+                json!({ "code": code, "kind": exec_error_name(*code), "message": message })
+            }
+            _ => json!({ "kind": "enclave error", "message": self.to_string() }),
+        }
+    }
+}
+
+impl fmt::Display for EnclaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnclaveError::Ecall(status) => write!(f, "ECALL failed: {}", status.as_str()),
+            EnclaveError::Job { code, message } => write!(f, "{}: {}", exec_error_name(*code), message),
+            EnclaveError::HashMismatch { expected, actual } => {
+                write!(f, "enclave hashed the module as {}, expected {}", actual, expected)
+            }
+            EnclaveError::ReceiptLog(message) => write!(f, "cannot record the job's receipt: {}", message),
+        }
+    }
+}
+
+impl error::Error for EnclaveError {}
+
+/// What a completed `exec_module` call returned.
+pub struct JobOutput {
+    pub result: Vec<u8>,
+    pub log: Vec<u8>,
+    pub fuel_used: u64,
+    pub exec_error: u32,
+    /// The job's receipt, signed by the enclave; empty if the job failed before it had one.
+    pub receipt: Vec<u8>,
+}
+
+impl JobOutput {
+    /// The job's JSON result, or why it has none. On failure the enclave returns an error message instead.
+    pub fn result(&self) -> Result<Value, EnclaveError> {
+        let message = || String::from_utf8_lossy(&self.result).into_owned();
+        if self.exec_error != 0 {
+            return Err(EnclaveError::Job { code: self.exec_error, message: message() });
+        }
+        serde_json::from_slice(&self.result).map_err(|e| EnclaveError::Job {
+            code: OUTPUT_DECODING,
+            message: format!("Failed to deserialize enclave result: {}", e),
+        })
+    }
+
+    pub fn log_lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.log).lines().map(str::to_string).collect()
+    }
+
+    /// Checks the job's receipt against the enclave's signing key and this output, as it came from the enclave.
+    /// For an encrypted job, that is before it is decrypted.
+    pub fn verify_receipt(&self, signing_key: &[u8]) -> Result<Receipt, String> {
+        if self.receipt.is_empty() {
+            return Err("the job has no receipt".to_string());
+        }
+        let receipt = receipt_verify::verify_receipt(&self.receipt, signing_key)
+            .map_err(|e| format!("invalid receipt: {}", e))?;
+        if receipt.exec_error != self.exec_error || receipt.output_sha256[..] != Sha256::digest(&self.result)[..] {
+            return Err("the receipt is for a different output".to_string());
+        }
+        Ok(receipt)
+    }
+
+    /// Decrypts the result and log of an encrypted job with the requester's key.
+    pub fn decrypt(self, reply_key: &SecretKey) -> Result<JobOutput, String> {
+        if self.exec_error == INVALID_REQUEST {
+            return Ok(self);
+        }
+        let result = envelope::open(reply_key, &self.result, envelope::RESULT_CONTEXT)?;
+        let log = if self.log.is_empty() {
+            Vec::new()
+        } else {
+            envelope::open(reply_key, &self.log, envelope::LOG_CONTEXT)?
+        };
+        Ok(JobOutput { result, log, ..self })
+    }
+
+    /// The fuel and hex-encoded result and log envelopes of an encrypted job as JSON, with the `error` code if
+    /// it failed. Only the requester can read its message, unless the request itself was rejected.
+    pub fn to_encrypted_json(&self) -> Value {
+        let mut report = json!({ "fuel_used": self.fuel_used });
+        match self.exec_error {
+            0 => {}
+            INVALID_REQUEST => report["error"] = self.result().unwrap_err().to_json(),
+            code => report["error"] = json!({ "code": code, "kind": exec_error_name(code) }),
+        }
+        if self.exec_error != INVALID_REQUEST {
+            report["encrypted_result"] = json!(encode_hex(&self.result));
+            report["encrypted_log"] = json!(encode_hex(&self.log));
+        }
+        if !self.receipt.is_empty() {
+            report["receipt"] = json!(encode_hex(&self.receipt));
+        }
+        report
+    }
+
+    /// The job's fuel, log and either `result` or `error` as JSON.
+    pub fn to_json(&self) -> Value {
+        let mut report = json!({ "fuel_used": self.fuel_used, "log": self.log_lines() });
+        match self.result() {
+            Ok(result) => report["result"] = result,
+            Err(e) => report["error"] = e.to_json(),
+        }
+        if !self.receipt.is_empty() {
+            report["receipt"] = json!(encode_hex(&self.receipt));
+        }
+        report
+    }
+}
+
+/// An SGX report of the enclave, with the keys its report data commits to.
+pub struct EnclaveReport {
+    /// The `sgx_report_t`, as laid out by the SDK.
+    pub report: Vec<u8>,
+    /// The key datasets and jobs are encrypted to.
+    pub encryption_key: [u8; PUBLIC_KEY_LEN],
+    /// The key receipts are signed with.
+    pub signing_key: [u8; PUBLIC_KEY_LEN],
+}
+
+impl EnclaveReport {
+    /// Checks that the report data commits to the keys. Whether the report itself is genuine takes its target
+    /// enclave, or a quote of it.
+    pub fn check_binding(&self) -> Result<(), String> {
+        receipt_verify::verify_report_binding(&self.report, &self.encryption_key, &self.signing_key)
+            .map_err(|e| format!("enclave report is inconsistent: {}", e))
+    }
+
+    /// The report, its measurements and the keys as hex.
+    pub fn to_json(&self) -> Value {
+        let measurement = |field: fn(&[u8]) -> Result<[u8; 32], ntc_receipt::ReceiptError>| {
+            field(&self.report).map(|hash| encode_hex(&hash)).unwrap_or_default()
+        };
+        json!({
+            "report": encode_hex(&self.report),
+            "mr_enclave": measurement(receipt_verify::mr_enclave),
+            "mr_signer": measurement(receipt_verify::mr_signer),
+            "encryption_key": encode_hex(&self.encryption_key),
+            "signing_key": encode_hex(&self.signing_key),
+        })
+    }
+}
+
+/// Reads an `sgx_target_info_t`, such as the quoting enclave's, from its raw bytes.
+pub fn target_info(bytes: &[u8]) -> Result<sgx_target_info_t, String> {
+    if bytes.len() != mem::size_of::<sgx_target_info_t>() {
+        return Err(format!("target info must be {} bytes, not {}", mem::size_of::<sgx_target_info_t>(), bytes.len()));
+    }
+    // Safe because the length matches and the structure is plain data.
+    Ok(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const sgx_target_info_t) })
+}
+
+/// Runs modules; implemented by the enclave, and by fakes in tests.
+pub trait Executor: Send + Sync {
+    /// Prepares `binary` for execution, returning the SHA-256 jobs refer to it by.
+    fn register(&self, binary: &[u8]) -> Result<ModuleHash, EnclaveError>;
+
+    /// Runs a registered module on `data`, encoded as `format`, and `schema`, under the data owner's `policy`
+    /// (JSON, or empty for none).
+    fn execute(
+        &self,
+        module_hash: &ModuleHash,
+        data: &[u8],
+        format: DataFormat,
+        schema: &[u8],
+        policy: &[u8],
+        limits: &Limits,
+    ) -> Result<JobOutput, EnclaveError>;
+
+    /// The SEC1 uncompressed public key that jobs and datasets are encrypted to.
+    fn public_key(&self) -> Result<[u8; PUBLIC_KEY_LEN], EnclaveError>;
+
+    /// Runs a job request sealed to `public_key`, returning its result and log sealed to the requester.
+    fn execute_encrypted(&self, request: &[u8], limits: &Limits) -> Result<JobOutput, EnclaveError>;
+
+    /// An SGX report for `target_info` that commits to the enclave's encryption and receipt signing keys.
+    fn report(&self, target_info: &sgx_target_info_t) -> Result<EnclaveReport, EnclaveError>;
+}
+
+/// An initialized enclave, shared by every job it runs.
+pub struct EnclaveExecutor {
+    enclave: SgxEnclave,
+    /// Where the receipts of its jobs are recorded, if anywhere.
+    receipt_log: Option<ReceiptLog>,
+}
+
+impl EnclaveExecutor {
+    pub fn launch(file: &Path, debug: bool) -> SgxResult<Self> {
+        let mut launch_token: sgx_launch_token_t = [0; 1024];
+        let mut launch_token_updated: i32 = 0;
+        // call sgx_create_enclave to initialize an enclave instance
+        let mut misc_attr = sgx_misc_attribute_t {
+            secs_attr: sgx_attributes_t { flags: 0, xfrm: 0 },
+            misc_select: 0,
+        };
+        let enclave = SgxEnclave::create(
+            file,
+            debug as i32,
+            &mut launch_token,
+            &mut launch_token_updated,
+            &mut misc_attr,
+        )?;
+        Ok(Self { enclave, receipt_log: None })
+    }
+
+    pub fn eid(&self) -> sgx_enclave_id_t {
+        self.enclave.geteid()
+    }
+
+    pub fn destroy(self) {
+        self.enclave.destroy();
+    }
+
+    /// Appends the enclave's report to `log`, and from then on the receipt of every job it runs.
+    pub fn record_receipts(&mut self, mut log: ReceiptLog) -> Result<(), EnclaveError> {
+        let report = self.report(&sgx_target_info_t::default())?;
+        log.record_enclave(&report).map_err(|e| EnclaveError::ReceiptLog(e.to_string()))?;
+        self.receipt_log = Some(log);
+        Ok(())
+    }
+
+    /// Appends the job's receipt to the receipt log, if there is one.
+    fn record(&self, output: JobOutput) -> Result<JobOutput, EnclaveError> {
+        if let (Some(log), false) = (&self.receipt_log, output.receipt.is_empty()) {
+            log.record_receipt(&output.receipt).map_err(|e| EnclaveError::ReceiptLog(e.to_string()))?;
+        }
+        Ok(output)
+    }
+
+    /// Describes the enclave's module cache counters, if it reports them.
+    pub fn cache_stats(&self) -> Option<String> {
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let (mut hits, mut misses, mut entries, mut bytes) = (0u64, 0u64, 0u64, 0u64);
+        let result = unsafe {
+            module_cache_stats(self.eid(), &mut retval, &mut hits, &mut misses, &mut entries, &mut bytes)
+        };
+        if result != sgx_status_t::SGX_SUCCESS || retval != sgx_status_t::SGX_SUCCESS {
+            return None;
+        }
+        Some(format!("{} hits, {} misses, {} modules ({} bytes)", hits, misses, entries, bytes))
+    }
+
+    /// Has the enclave open `envelope`, sealed to its public key, and seal the dataset inside under `policy`,
+    /// along with the owner's `dataset_policy`. Returns the sealed dataset, which jobs take with
+    /// `DataFormat::Sealed`.
+    pub fn import_dataset(&self, envelope: &[u8], format: DataFormat, dataset_policy: &[u8], policy: SealPolicy)
+        -> Result<Vec<u8>, EnclaveError> {
+        if format == DataFormat::Sealed {
+            let message = "the dataset is already sealed".to_string();
+            return Err(EnclaveError::Job { code: INVALID_INPUT, message });
+        }
+        let (data_format, csv_delimiter, csv_quote) = ecall_format(format);
+        // `SGX_KEYPOLICY_MRENCLAVE` and `SGX_KEYPOLICY_MRSIGNER`.
+        let seal_policy = match policy {
+            SealPolicy::MrEnclave => 1,
+            SealPolicy::MrSigner => 2,
+        };
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let mut sealed_buffer: Vec<u8> = vec![0; envelope.len() + dataset_policy.len() + SEAL_OVERHEAD];
+        let mut sealed_len: usize = 0;
+        let mut exec_error: u32 = 0;
+
+        loop {
+            let result = unsafe {
+                import_dataset(
+                    self.eid(),
+                    &mut retval,
+                    envelope.as_ptr(),
+                    envelope.len(),
+                    data_format,
+                    csv_delimiter,
+                    csv_quote,
+                    dataset_policy.as_ptr(),
+                    dataset_policy.len(),
+                    seal_policy,
+                    sealed_buffer.as_mut_ptr(),
+                    sealed_buffer.len(),
+                    &mut sealed_len,
+                    &mut exec_error,
+                )
+            };
+            if result != sgx_status_t::SGX_SUCCESS {
+                return Err(EnclaveError::Ecall(result));
+            }
+
+            match retval {
+                sgx_status_t::SGX_SUCCESS => break,
+                sgx_status_t::SGX_ERROR_INVALID_PARAMETER if sealed_len > sealed_buffer.len() => {
+                    sealed_buffer = vec![0; sealed_len];
+                }
+                _ => return Err(EnclaveError::Ecall(retval)),
+            }
+        }
+
+        sealed_buffer.truncate(sealed_len);
+        if exec_error != 0 {
+            let message = String::from_utf8_lossy(&sealed_buffer).into_owned();
+            return Err(EnclaveError::Job { code: exec_error, message });
+        }
+        Ok(sealed_buffer)
+    }
+}
+
+impl Executor for EnclaveExecutor {
+    fn register(&self, binary: &[u8]) -> Result<ModuleHash, EnclaveError> {
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let mut module_hash = [0u8; 32];
+        let mut error_buffer: Vec<u8> = vec![0; ERROR_BUFFER_SIZE];
+        let mut error_len: usize = 0;
+        let mut exec_error: u32 = 0;
+        let result = unsafe {
+            register_module(
+                self.eid(),
+                &mut retval,
+                binary.as_ptr(),
+                binary.len(),
+                module_hash.as_mut_ptr(),
+                error_buffer.as_mut_ptr(),
+                error_buffer.len(),
+                &mut error_len,
+                &mut exec_error,
+            )
+        };
+        if result != sgx_status_t::SGX_SUCCESS {
+            return Err(EnclaveError::Ecall(result));
+        }
+        if retval != sgx_status_t::SGX_SUCCESS {
+            return Err(EnclaveError::Ecall(retval));
+        }
+        if exec_error != 0 {
+            let message = String::from_utf8_lossy(&error_buffer[..error_len.min(error_buffer.len())]).into_owned();
+            return Err(EnclaveError::Job { code: exec_error, message });
+        }
+        let expected = registry::sha256_hex(binary);
+        let actual = encode_hex(&module_hash);
+        if actual != expected {
+            return Err(EnclaveError::HashMismatch { expected, actual });
+        }
+        Ok(module_hash)
+    }
+
+    fn execute(
+        &self,
+        module_hash: &ModuleHash,
+        data: &[u8],
+        format: DataFormat,
+        schema: &[u8],
+        policy: &[u8],
+        limits: &Limits,
+    ) -> Result<JobOutput, EnclaveError> {
+        let (data_format, csv_delimiter, csv_quote) = ecall_format(format);
+        let timestamp = unix_time();
+        let output = JobBuffers::new(limits).run(|retval, out| unsafe {
+            exec_module(
+                self.eid(),
+                retval,
+                module_hash.as_ptr(),
+                data.as_ptr(),
+                data.len(),
+                data_format,
+                csv_delimiter,
+                csv_quote,
+                schema.as_ptr(),
+                schema.len(),
+                policy.as_ptr(),
+                policy.len(),
+                limits.fuel,
+                timestamp,
+                out.result.as_mut_ptr(),
+                out.result.len(),
+                &mut out.result_len,
+                out.log.as_mut_ptr(),
+                out.log.len(),
+                &mut out.log_len,
+                out.receipt.as_mut_ptr(),
+                out.receipt.len(),
+                &mut out.receipt_len,
+                &mut out.fuel_used,
+                &mut out.exec_error,
+            )
+        })?;
+        self.record(output)
+    }
+
+    /// The key datasets and jobs are encrypted to. It is generated when the enclave first needs it and lasts
+    /// until the enclave is destroyed.
+    fn public_key(&self) -> Result<[u8; PUBLIC_KEY_LEN], EnclaveError> {
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let mut public_key = [0u8; PUBLIC_KEY_LEN];
+        let result = unsafe { enclave_public_key(self.eid(), &mut retval, public_key.as_mut_ptr()) };
+        if result != sgx_status_t::SGX_SUCCESS {
+            return Err(EnclaveError::Ecall(result));
+        }
+        if retval != sgx_status_t::SGX_SUCCESS {
+            return Err(EnclaveError::Ecall(retval));
+        }
+        Ok(public_key)
+    }
+
+    fn execute_encrypted(&self, request: &[u8], limits: &Limits) -> Result<JobOutput, EnclaveError> {
+        let timestamp = unix_time();
+        let output = JobBuffers::new(limits).run(|retval, out| unsafe {
+            exec_encrypted(
+                self.eid(),
+                retval,
+                request.as_ptr(),
+                request.len(),
+                limits.fuel,
+                timestamp,
+                out.result.as_mut_ptr(),
+                out.result.len(),
+                &mut out.result_len,
+                out.log.as_mut_ptr(),
+                out.log.len(),
+                &mut out.log_len,
+                out.receipt.as_mut_ptr(),
+                out.receipt.len(),
+                &mut out.receipt_len,
+                &mut out.fuel_used,
+                &mut out.exec_error,
+            )
+        })?;
+        self.record(output)
+    }
+
+    fn report(&self, target_info: &sgx_target_info_t) -> Result<EnclaveReport, EnclaveError> {
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        let mut report = sgx_report_t::default();
+        let mut encryption_key = [0u8; PUBLIC_KEY_LEN];
+        let mut signing_key = [0u8; PUBLIC_KEY_LEN];
+        let result = unsafe {
+            enclave_report(
+                self.eid(),
+                &mut retval,
+                target_info,
+                &mut report,
+                encryption_key.as_mut_ptr(),
+                signing_key.as_mut_ptr(),
+            )
+        };
+        if result != sgx_status_t::SGX_SUCCESS {
+            return Err(EnclaveError::Ecall(result));
+        }
+        if retval != sgx_status_t::SGX_SUCCESS {
+            return Err(EnclaveError::Ecall(retval));
+        }
+        // Safe because `sgx_report_t` is plain data.
+        let report = unsafe {
+            slice::from_raw_parts(&report as *const sgx_report_t as *const u8, mem::size_of::<sgx_report_t>())
+        };
+        Ok(EnclaveReport { report: report.to_vec(), encryption_key, signing_key })
+    }
+}
+
+/// Buffers receiving the output of a job ECALL.
+struct JobBuffers {
+    result: Vec<u8>,
+    result_len: usize,
+    log: Vec<u8>,
+    log_len: usize,
+    receipt: Vec<u8>,
+    receipt_len: usize,
+    fuel_used: u64,
+    exec_error: u32,
+}
+
+impl JobBuffers {
+    fn new(limits: &Limits) -> Self {
+        JobBuffers {
+            result: vec![0; limits.result_bytes],
+            result_len: 0,
+            log: vec![0; limits.log_bytes],
+            log_len: 0,
+            receipt: vec![0; SIGNED_RECEIPT_LEN],
+            receipt_len: 0,
+            fuel_used: 0,
+            exec_error: 0,
+        }
+    }
+
+    /// Issues `ecall` with these buffers, growing the result buffer and retrying if the enclave asks for more
+    /// room.
+    fn run<F>(mut self, mut ecall: F) -> Result<JobOutput, EnclaveError>
+    where
+        F: FnMut(&mut sgx_status_t, &mut Self) -> sgx_status_t,
+    {
+        let mut retval = sgx_status_t::SGX_SUCCESS;
+        loop {
+            let result = ecall(&mut retval, &mut self);
+            if result != sgx_status_t::SGX_SUCCESS {
+                return Err(EnclaveError::Ecall(result));
+            }
+
+            match retval {
+                sgx_status_t::SGX_SUCCESS => break,
+                sgx_status_t::SGX_ERROR_INVALID_PARAMETER if self.result_len > self.result.len() => {
+                    // The enclave reports the size it needs; retry with a buffer that fits.
+                    self.result = vec![0; self.result_len];
+                }
+                _ => return Err(EnclaveError::Ecall(retval)),
+            }
+        }
+
+        self.result.truncate(self.result_len);
+        self.log.truncate(self.log_len.min(self.log.len()));
+        // A receipt in a format this app does not know would not fit; it could not be verified anyway.
+        let receipt_len = if self.receipt_len <= self.receipt.len() { self.receipt_len } else { 0 };
+        self.receipt.truncate(receipt_len);
+        Ok(JobOutput {
+            result: self.result,
+            log: self.log,
+            fuel_used: self.fuel_used,
+            exec_error: self.exec_error,
+            receipt: self.receipt,
+        })
+    }
+}
+
+/// The enclave's `data_format` code for `format`, with the CSV delimiter and quote, which it ignores for the
+/// other formats.
+pub fn ecall_format(format: DataFormat) -> (u32, u8, u8) {
+    match format {
+        DataFormat::Json => (0, b',', b'"'),
+        DataFormat::Csv { delimiter, quote } => (1, delimiter, quote),
+        DataFormat::Columnar => (2, b',', b'"'),
+        DataFormat::Sealed => (3, b',', b'"'),
+    }
+}
+
+/// Number of threads that can be inside the enclave at once, from `TCSNum` in `Enclave.config.xml`.
+pub fn tcs_count() -> usize {
+    let value = ENCLAVE_CONFIG
+        .split("<TCSNum>")
+        .nth(1)
+        .and_then(|rest| rest.split("</TCSNum>").next())
+        .map(str::trim);
+    // The SDK reads the value as a C integer literal, so it may be hex.
+    let count = match value {
+        Some(hex) if hex.starts_with("0x") || hex.starts_with("0X") => usize::from_str_radix(&hex[2..], 16).ok(),
+        Some(decimal) => decimal.parse().ok(),
+        None => None,
+    };
+    count.unwrap_or(1).max(1)
+}
+
+/// Seconds since the Unix epoch, which the enclave records in receipts.
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+    use rand_core::OsRng;
+
+    #[test]
+    fn receipts_verify_against_the_output_they_came_with() {
+        let key = SigningKey::random(&mut OsRng);
+        let public = key.verifying_key().to_encoded_point(false);
+        let receipt = Receipt {
+            sequence: 1,
+            timestamp: 1_700_000_000,
+            exec_error: 0,
+            module_sha256: [1; 32],
+            data_sha256: [2; 32],
+            schema_sha256: [3; 32],
+            output_sha256: Sha256::digest(b"{\"mean\": 2}").into(),
+            limits: ntc_receipt::Limits { fuel: 100, memory_pages: 16, output_bytes: 1024, log_bytes: 1024 },
+            fuel_used: 7,
+        };
+        let mut signed = receipt.encode();
+        let signature: Signature = key.sign(&signed);
+        signed.extend_from_slice(&signature.to_bytes());
+        let result = b"{\"mean\": 2}".to_vec();
+        let output = JobOutput { result, log: vec![], fuel_used: 1, exec_error: 0, receipt: signed };
+
+        assert_eq!(output.verify_receipt(public.as_bytes()), Ok(receipt));
+        assert_eq!(output.to_json()["receipt"], json!(encode_hex(&output.receipt)));
+        let other = SigningKey::random(&mut OsRng).verifying_key().to_encoded_point(false);
+        assert!(output.verify_receipt(other.as_bytes()).is_err());
+        let forged = JobOutput { result: b"{\"mean\": 3}".to_vec(), ..output };
+        assert!(forged.verify_receipt(public.as_bytes()).is_err());
+        assert!(JobOutput { receipt: vec![], ..forged }.verify_receipt(public.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Reads the parts of a WASM binary shown by `app inspect`, without instantiating it.
+
+use serde::Serialize;
+
+const MAGIC: &[u8] = b"\0asm";
+const CUSTOM_SECTION: u8 = 0;
+const IMPORT_SECTION: u8 = 2;
+const EXPORT_SECTION: u8 = 7;
+
+/// Name of the custom section in which guests declare their ABI.
+static ABI_SECTION: &str = "ntc_abi";
+
+/// Name of the custom section in which guests declare the schema of their results.
+static OUTPUT_SCHEMA_SECTION: &str = "ntc_output_schema";
+
+#[derive(Debug, Default, Serialize)]
+pub struct ModuleInfo {
+    /// Contents of the `ntc_abi` section, if the module declares its ABI.
+    pub abi: Option<String>,
+    /// Contents of the `ntc_output_schema` section, if the module declares one.
+    pub output_schema: Option<String>,
+    pub custom_sections: Vec<String>,
+    /// Imports as `module.field (kind)`.
+    pub imports: Vec<String>,
+    /// Exports as `name (kind)`.
+    pub exports: Vec<String>,
+}
+
+/// Lists the ABI declaration, output schema, custom sections, imports and exports of `binary`.
+pub fn inspect(binary: &[u8]) -> Result<ModuleInfo, String> {
+    if binary.len() < 8 || &binary[..4] != MAGIC {
+        return Err("not a WASM binary".to_string());
+    }
+    let mut info = ModuleInfo::default();
+    let mut reader = Reader { bytes: binary, pos: 8 };
+    while !reader.is_empty() {
+        let id = reader.byte()?;
+        let len = reader.u32()? as usize;
+        let mut section = Reader { bytes: reader.take(len)?, pos: 0 };
+        match id {
+            CUSTOM_SECTION => {
+                let name = section.name()?;
+                if name == ABI_SECTION {
+                    info.abi = Some(String::from_utf8_lossy(section.rest()).into_owned());
+                } else if name == OUTPUT_SCHEMA_SECTION {
+                    info.output_schema = Some(String::from_utf8_lossy(section.rest()).into_owned());
+                }
+                info.custom_sections.push(name);
+            }
+            IMPORT_SECTION => {
+                for _ in 0..section.u32()? {
+                    let module = section.name()?;
+                    let field = section.name()?;
+                    let kind = section.import_kind()?;
+                    info.imports.push(format!("{}.{} ({})", module, field, kind));
+                }
+            }
+            EXPORT_SECTION => {
+                for _ in 0..section.u32()? {
+                    let name = section.name()?;
+                    let kind = kind_name(section.byte()?);
+                    section.u32()?;
+                    info.exports.push(format!("{} ({})", name, kind));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+fn kind_name(kind: u8) -> &'static str {
+    match kind {
+        0 => "func",
+        1 => "table",
+        2 => "memory",
+        3 => "global",
+        _ => "unknown",
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or_else(|| "unexpected end of module".to_string())?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.bytes[self.pos..];
+        self.pos = self.bytes.len();
+        bytes
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Reads an unsigned LEB128 integer.
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("integer too long".to_string())
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn limits(&mut self) -> Result<(), String> {
+        let flags = self.byte()?;
+        self.u32()?;
+        if flags & 1 != 0 {
+            self.u32()?;
+        }
+        Ok(())
+    }
+
+    /// Skips an import description, returning its kind.
+    fn import_kind(&mut self) -> Result<&'static str, String> {
+        let kind = self.byte()?;
+        match kind {
+            0 => {
+                self.u32()?;
+            }
+            1 => {
+                self.byte()?;
+                self.limits()?;
+            }
+            2 => self.limits()?,
+            3 => {
+                self.take(2)?;
+            }
+            _ => return Err(format!("unknown import kind {}", kind)),
+        }
+        Ok(kind_name(kind))
+    }
+}
@@ -0,0 +1,384 @@
+//! Bounded queue of execution jobs, run concurrently by a pool of workers sharing one enclave.
+//!
+//! Each worker issues one ECALL at a time, so the pool should not outnumber the enclave's TCSs: an ECALL
+//! made while every TCS is busy fails with `SGX_ERROR_OUT_OF_TCS`. An ECALL cannot be interrupted, so a
+//! job that outlives its timeout is reported as timed out while its worker waits for the enclave to return
+//! (fuel limits bound how long that takes).
+
+use crate::cli::{DataFormat, Limits};
+use crate::executor::Executor;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+/// Finished jobs kept for status queries; the oldest are forgotten first.
+const MAX_FINISHED_JOBS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    TimedOut,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+            JobStatus::TimedOut => "timed-out",
+            JobStatus::Cancelled => "cancelled",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A job to run.
+pub struct JobSpec {
+    /// Label of the module, e.g. `name@version`.
+    pub module: String,
+    pub sha256: String,
+    pub input: JobInput,
+    pub limits: Limits,
+}
+
+/// What a job runs on.
+pub enum JobInput {
+    /// A module and data that the host can read.
+    Plain {
+        binary: Vec<u8>,
+        data: Vec<u8>,
+        data_format: DataFormat,
+        schema: Vec<u8>,
+        /// The data owner's policy as JSON, or empty for none.
+        policy: Vec<u8>,
+    },
+    /// A job request encrypted to the enclave, whose result and log are reported encrypted to the requester.
+    Encrypted(Vec<u8>),
+}
+
+/// Returned by [`JobQueue::submit`] when `capacity` jobs are already waiting.
+#[derive(Debug)]
+pub struct QueueFull {
+    pub capacity: usize,
+}
+
+#[derive(Debug)]
+pub enum CancelError {
+    NotFound,
+    /// Only queued jobs can be cancelled.
+    NotQueued(JobStatus),
+}
+
+struct Job {
+    module: String,
+    sha256: String,
+    status: JobStatus,
+    deadline: Option<Instant>,
+    /// Inputs of a job that has not started yet.
+    spec: Option<JobSpec>,
+    /// The job's fuel, log and result or error, once it has finished.
+    report: Option<Value>,
+}
+
+impl Job {
+    /// Marks an unfinished job as timed out once its deadline has passed.
+    fn check_deadline(&mut self, now: Instant) {
+        let expired = matches!(self.deadline, Some(deadline) if now > deadline);
+        if expired && !self.status.is_finished() {
+            self.status = JobStatus::TimedOut;
+            self.spec = None;
+            self.report = Some(json!({ "error": { "kind": "timed out", "message": "job exceeded its timeout" } }));
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_id: u64,
+    pending: VecDeque<u64>,
+    jobs: BTreeMap<u64, Job>,
+}
+
+impl QueueState {
+    fn finish(&mut self, id: u64, status: JobStatus, report: Value) {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.status = status;
+            job.report = Some(report);
+        }
+        let finished: Vec<u64> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.status.is_finished())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in finished.iter().take(finished.len().saturating_sub(MAX_FINISHED_JOBS)) {
+            self.jobs.remove(id);
+        }
+    }
+}
+
+struct Shared<E> {
+    executor: E,
+    capacity: usize,
+    state: Mutex<QueueState>,
+    available: Condvar,
+}
+
+impl<E> Shared<E> {
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        // Workers never panic while holding the lock, and the state stays consistent if one does.
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub struct JobQueue<E> {
+    shared: Arc<Shared<E>>,
+}
+
+impl<E: Executor + 'static> JobQueue<E> {
+    /// The executor the jobs run on.
+    pub fn executor(&self) -> &E {
+        &self.shared.executor
+    }
+
+    /// Starts `workers` threads running jobs on `executor`; at most `capacity` jobs may wait for them.
+    /// The workers run for the life of the process.
+    pub fn new(executor: E, workers: usize, capacity: usize) -> Self {
+        let shared = Arc::new(Shared {
+            executor,
+            capacity,
+            state: Mutex::new(QueueState::default()),
+            available: Condvar::new(),
+        });
+        for _ in 0..workers.max(1) {
+            let shared = shared.clone();
+            thread::spawn(move || run_jobs(&shared));
+        }
+        Self { shared }
+    }
+
+    /// Queues a job, returning its id.
+    pub fn submit(&self, spec: JobSpec) -> Result<u64, QueueFull> {
+        let mut state = self.shared.state();
+        // Jobs that timed out while queued no longer take up room.
+        let now = Instant::now();
+        let QueueState { pending, jobs, .. } = &mut *state;
+        pending.retain(|id| match jobs.get_mut(id) {
+            Some(job) => {
+                job.check_deadline(now);
+                job.status == JobStatus::Queued
+            }
+            None => false,
+        });
+        if state.pending.len() >= self.shared.capacity {
+            return Err(QueueFull { capacity: self.shared.capacity });
+        }
+        state.next_id += 1;
+        let id = state.next_id;
+        let job = Job {
+            module: spec.module.clone(),
+            sha256: spec.sha256.clone(),
+            status: JobStatus::Queued,
+            deadline: spec.limits.timeout.map(|timeout| Instant::now() + timeout),
+            spec: Some(spec),
+            report: None,
+        };
+        state.jobs.insert(id, job);
+        state.pending.push_back(id);
+        self.shared.available.notify_one();
+        Ok(id)
+    }
+
+    /// The job's status as JSON, with its position in the queue or, once finished, its report.
+    pub fn status(&self, id: u64) -> Option<Value> {
+        let mut state = self.shared.state();
+        let position = state.pending.iter().position(|pending| *pending == id);
+        let job = state.jobs.get_mut(&id)?;
+        job.check_deadline(Instant::now());
+        let mut status = json!({ "id": id, "module": job.module, "sha256": job.sha256, "status": job.status });
+        match (&job.report, position) {
+            (Some(Value::Object(report)), _) => {
+                for (key, value) in report {
+                    status[key] = value.clone();
+                }
+            }
+            (_, Some(position)) if job.status == JobStatus::Queued => status["queue_position"] = json!(position),
+            _ => {}
+        }
+        Some(status)
+    }
+
+    /// Cancels a job that has not started yet.
+    pub fn cancel(&self, id: u64) -> Result<(), CancelError> {
+        let mut state = self.shared.state();
+        let job = state.jobs.get_mut(&id).ok_or(CancelError::NotFound)?;
+        job.check_deadline(Instant::now());
+        if job.status != JobStatus::Queued {
+            return Err(CancelError::NotQueued(job.status));
+        }
+        job.spec = None;
+        state.pending.retain(|pending| *pending != id);
+        state.finish(id, JobStatus::Cancelled, json!({}));
+        Ok(())
+    }
+}
+
+/// Takes jobs off the queue and runs them, one at a time per worker.
+fn run_jobs<E: Executor>(shared: &Shared<E>) {
+    loop {
+        let (id, spec) = {
+            let mut state = shared.state();
+            loop {
+                let id = match state.pending.pop_front() {
+                    Some(id) => id,
+                    None => {
+                        state = shared.available.wait(state).unwrap_or_else(|poisoned| poisoned.into_inner());
+                        continue;
+                    }
+                };
+                let job = match state.jobs.get_mut(&id) {
+                    Some(job) => job,
+                    None => continue,
+                };
+                // A job whose deadline passed while it was queued is not started at all.
+                job.check_deadline(Instant::now());
+                if let (JobStatus::Queued, Some(spec)) = (job.status, job.spec.take()) {
+                    job.status = JobStatus::Running;
+                    break (id, spec);
+                }
+            }
+        };
+
+        let executor = &shared.executor;
+        let outcome = match &spec.input {
+            // Registering is cheap once the enclave has cached the module.
+            JobInput::Plain { binary, data, data_format, schema, policy } => executor
+                .register(binary)
+                .and_then(|hash| executor.execute(&hash, data, *data_format, schema, policy, &spec.limits))
+                .map(|output| output.to_json()),
+            JobInput::Encrypted(request) => {
+                executor.execute_encrypted(request, &spec.limits).map(|output| output.to_encrypted_json())
+            }
+        };
+        let report = outcome.unwrap_or_else(|e| json!({ "error": e.to_json() }));
+
+        let mut state = shared.state();
+        if let Some(job) = state.jobs.get_mut(&id) {
+            job.check_deadline(Instant::now());
+            // A job that timed out keeps that status; its late result is dropped.
+            if job.status == JobStatus::Running {
+                let status = if report.get("error").is_some() { JobStatus::Failed } else { JobStatus::Succeeded };
+                state.finish(id, status, report);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{EnclaveError, EnclaveReport, JobOutput, ModuleHash, PUBLIC_KEY_LEN};
+    use sgx_types::{sgx_status_t, sgx_target_info_t};
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+
+    /// Holds each job until the test releases it.
+    struct GatedExecutor {
+        gate: Mutex<Receiver<()>>,
+    }
+
+    impl Executor for GatedExecutor {
+        fn register(&self, _: &[u8]) -> Result<ModuleHash, EnclaveError> {
+            Ok([0; 32])
+        }
+
+        fn execute(&self, _: &ModuleHash, data: &[u8], _: DataFormat, _: &[u8], _: &[u8], _: &Limits)
+            -> Result<JobOutput, EnclaveError> {
+            self.gate.lock().unwrap().recv().unwrap();
+            Ok(JobOutput { result: data.to_vec(), log: vec![], fuel_used: 1, exec_error: 0, receipt: vec![] })
+        }
+
+        fn public_key(&self) -> Result<[u8; PUBLIC_KEY_LEN], EnclaveError> {
+            Ok([4; PUBLIC_KEY_LEN])
+        }
+
+        fn execute_encrypted(&self, request: &[u8], _: &Limits) -> Result<JobOutput, EnclaveError> {
+            self.gate.lock().unwrap().recv().unwrap();
+            Ok(JobOutput { result: request.to_vec(), log: vec![], fuel_used: 1, exec_error: 0, receipt: vec![] })
+        }
+
+        fn report(&self, _: &sgx_target_info_t) -> Result<EnclaveReport, EnclaveError> {
+            Err(EnclaveError::Ecall(sgx_status_t::SGX_ERROR_UNEXPECTED))
+        }
+    }
+
+    fn spec(data: &str, limits: &str) -> JobSpec {
+        JobSpec {
+            module: "test@1".to_string(),
+            sha256: String::new(),
+            input: JobInput::Plain {
+                binary: vec![],
+                data: data.as_bytes().to_vec(),
+                data_format: DataFormat::Json,
+                schema: b"{}".to_vec(),
+                policy: vec![],
+            },
+            limits: limits.parse().unwrap(),
+        }
+    }
+
+    fn wait_for(queue: &JobQueue<GatedExecutor>, id: u64, status: &str) -> Value {
+        for _ in 0..200 {
+            let job = queue.status(id).unwrap();
+            if job["status"] == status {
+                return job;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job {} never became {}: {}", id, status, queue.status(id).unwrap());
+    }
+
+    #[test]
+    fn queue_applies_back_pressure_cancellation_and_timeouts() {
+        let (release, gate) = mpsc::channel();
+        let queue = JobQueue::new(GatedExecutor { gate: Mutex::new(gate) }, 1, 2);
+
+        let running = queue.submit(spec("1", "")).unwrap();
+        wait_for(&queue, running, "running");
+        let cancelled = queue.submit(spec("2", "")).unwrap();
+        let timed_out = queue.submit(spec("3", "timeout-ms=200")).unwrap();
+        assert_eq!(queue.status(timed_out).unwrap()["queue_position"], 1);
+        assert!(queue.submit(spec("4", "")).is_err());
+
+        assert!(matches!(queue.cancel(running), Err(CancelError::NotQueued(JobStatus::Running))));
+        queue.cancel(cancelled).unwrap();
+        assert_eq!(queue.status(cancelled).unwrap()["status"], "cancelled");
+        assert!(matches!(queue.cancel(99), Err(CancelError::NotFound)));
+
+        thread::sleep(Duration::from_millis(250));
+        release.send(()).unwrap();
+        assert_eq!(wait_for(&queue, running, "succeeded")["result"], 1);
+        assert_eq!(queue.status(timed_out).unwrap()["status"], "timed-out");
+
+        let last = queue.submit(spec("5", "")).unwrap();
+        release.send(()).unwrap();
+        assert_eq!(wait_for(&queue, last, "succeeded")["result"], 5);
+    }
+}
@@ -17,124 +17,460 @@
 
 extern crate sgx_types;
 extern crate sgx_urts;
-extern crate wabt;
-use serde_json::Error;
-use sgx_types::*;
-use sgx_urts::SgxEnclave;
 
 extern crate serde_json;
+extern crate serde;
+extern crate sha2;
 use serde_json::json;
 use std::path::Path;
-use std::{
-    fs::{self, File},
-    io::Read,
-};
-use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::error;
+use std::fmt;
+use std::process;
 use serde_json::Value;
 
-static WASM_FILE_MEDIAN: &str = "get_median_wasm.wasm";
+extern crate clap;
+extern crate tiny_http;
+extern crate ntc_guest;
+extern crate ntc_receipt;
+extern crate p256;
+extern crate aes_gcm;
+extern crate rand_core;
+use clap::Parser;
 
-static WASM_FILE_MEAN: &str = "get_mean_wasm.wasm";
+mod cli;
+mod envelope;
+mod executor;
+mod inspect;
+mod jobs;
+mod receipt_log;
+mod registry;
+mod server;
+use cli::{AttestArgs, Cli, Command, DataFormat, DatasetArgs, ImportDatasetArgs, OutputFormat, RunArgs};
+use executor::{EnclaveExecutor, Executor, JobOutput, ModuleHash};
+use ntc_receipt::{encode_hex, Receipt};
+use p256::SecretKey;
+use rand_core::OsRng;
+use sgx_types::sgx_target_info_t;
+use sha2::{Digest, Sha256};
+use jobs::JobQueue;
+use receipt_log::ReceiptLog;
+use registry::{ModuleEntry, ModuleRegistry};
+use server::ApiServer;
 
-static WASM_FILE_SD: &str = "get_sd_wasm.wasm";
+/// Modules built alongside the app, added to the registry on first run as `(name, version, file)`.
+static BUNDLED_MODULES: [(&str, &str, &str); 3] = [
+    ("mean", "0.1.0", "get_mean_wasm.wasm"),
+    ("median", "0.1.0", "get_median_wasm.wasm"),
+    ("sd", "0.1.0", "get_sd_wasm.wasm"),
+];
 
-static ENCLAVE_FILE: &str = "enclave.signed.so";
+/// Schema passed to modules when a job is given none.
+static EMPTY_SCHEMA: &[u8] = b"{}";
 
-extern "C" {
-    fn exec_wasm(eid: sgx_enclave_id_t,
-        retval: *mut sgx_status_t,
-        data_in: *const u8,
-        data_len: usize,
-        schema_in: *const u8,
-        schema_len: usize,
-        binary: *const u8,
-        binary_len: usize,
-    ) -> sgx_status_t;
+type AppError = Box<dyn error::Error>;
+
+type AppResult<T> = Result<T, AppError>;
+
+/// Launches the enclave, recording the receipts of its jobs in the receipt log if `log_receipts` is set.
+fn launch_enclave(cli: &Cli, log_receipts: bool) -> AppResult<EnclaveExecutor> {
+    let mut executor = EnclaveExecutor::launch(&cli.enclave, cli.debug)
+        .map_err(|e| format!("Init Enclave Failed {}!", e.as_str()))?;
+    status(cli, format!("Init Enclave Successful {}!", executor.eid()));
+    if log_receipts {
+        let recorded = ReceiptLog::open(&cli.receipt_log)
+            .map_err(|e| format!("Cannot open receipt log {}: {}", cli.receipt_log.display(), e).into())
+            .and_then(|log| executor.record_receipts(log).map_err(AppError::from));
+        if let Err(e) = recorded {
+            executor.destroy();
+            return Err(e);
+        }
+    }
+    Ok(executor)
 }
 
-fn init_enclave() -> SgxResult<SgxEnclave> {
-    let mut launch_token: sgx_launch_token_t = [0; 1024];
-    let mut launch_token_updated: i32 = 0;
-    // call sgx_create_enclave to initialize an enclave instance
-    // Debug Support: set 2nd parameter to 1
-    let debug = 1;
-    let mut misc_attr = sgx_misc_attribute_t {
-        secs_attr: sgx_attributes_t { flags: 0, xfrm: 0 },
-        misc_select: 0,
-    };
-    SgxEnclave::create(
-        ENCLAVE_FILE,
-        debug,
-        &mut launch_token,
-        &mut launch_token_updated,
-        &mut misc_attr,
-    )
+/// Launches the enclave, runs `f` with it and destroys it again.
+fn with_enclave<T>(cli: &Cli, log_receipts: bool, f: impl FnOnce(&EnclaveExecutor) -> AppResult<T>) -> AppResult<T> {
+    let executor = launch_enclave(cli, log_receipts)?;
+    let result = f(&executor);
+    executor.destroy();
+    result
 }
 
+fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(&cli) {
+        eprintln!("[-] {}", e);
+        process::exit(1);
+    }
+}
 
+fn run(cli: &Cli) -> AppResult<()> {
+    let mut registry = open_registry(&cli.registry)?;
+    match &cli.command {
+        Command::Run(args) => run_module(cli, &registry, args),
+        Command::ImportDataset(args) => import_dataset(cli, args),
+        Command::Attest(args) => attest(cli, args),
+        Command::VerifyReceipts => verify_receipts(cli),
+        Command::ListModules => {
+            match cli.format {
+                OutputFormat::Text => {
+                    for module in registry.list() {
+                        println!("{}", module);
+                    }
+                }
+                OutputFormat::Json => println!("{}", serde_json::to_string_pretty(registry.list())?),
+            }
+            Ok(())
+        }
+        Command::AddModule { name, version, file } => {
+            let binary = read_file(file)?;
+            let module = registry.add(name, version, &binary)?;
+            print_module(cli, "Added", &module)
+        }
+        Command::RemoveModule { module } => {
+            let module = registry.remove(module)?;
+            print_module(cli, "Removed", &module)
+        }
+        Command::Validate(args) => {
+            let (module, binary) = load_module(&registry, &args.module)?;
+            let registered = with_enclave(cli, false, |executor| Ok(executor.register(&binary)))?;
+            if cli.format == OutputFormat::Json {
+                let mut report = json!({ "module": module, "sha256": registry::sha256_hex(&binary) });
+                report["valid"] = json!(registered.is_ok());
+                if let Err(e) = &registered {
+                    report["error"] = e.to_json();
+                }
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+            let hash = registered?;
+            if cli.format == OutputFormat::Text {
+                println!("[+] {} is valid: {}", module, encode_hex(&hash));
+            }
+            Ok(())
+        }
+        Command::Serve { listen, workers, queue_capacity } => {
+            let executor = launch_enclave(cli, true)?;
+            status(cli, format!("Recording receipts in {}", cli.receipt_log.display()));
+            // Each worker needs a TCS of its own for its ECALLs, and the server keeps one for those it makes
+            // itself, such as `GET /enclave-key`.
+            let tcs = executor::tcs_count().saturating_sub(1).max(1);
+            let workers = workers.unwrap_or(tcs).min(tcs);
+            status(cli, format!("Running up to {} jobs at once, queueing up to {}", workers, queue_capacity));
+            let queue = JobQueue::new(executor, workers, *queue_capacity);
+            let server = ApiServer::bind(listen, queue, cli.registry.clone())
+                .map_err(|e| format!("Cannot listen on {}: {}", listen, e))?;
+            if let Some(addr) = server.local_addr() {
+                status(cli, format!("Listening on http://{}", addr));
+            }
+            server.run();
+            Ok(())
+        }
+        Command::Inspect(args) => {
+            let (module, binary) = load_module(&registry, &args.module)?;
+            let info = inspect::inspect(&binary)?;
+            let sha256 = registry::sha256_hex(&binary);
+            match cli.format {
+                OutputFormat::Text => {
+                    println!("module: {}", module);
+                    println!("sha256: {}", sha256);
+                    println!("size: {} bytes", binary.len());
+                    match &info.abi {
+                        Some(abi) => println!("abi: {}", abi.trim_end().replace('\n', ", ")),
+                        None => println!("abi: none declared"),
+                    }
+                    match &info.output_schema {
+                        Some(schema) => println!("output schema: {}", schema.trim()),
+                        None => println!("output schema: none declared"),
+                    }
+                    if info.custom_sections.is_empty() {
+                        println!("custom sections: none");
+                    } else {
+                        println!("custom sections: {}", info.custom_sections.join(", "));
+                    }
+                    println!("imports:");
+                    for import in &info.imports {
+                        println!("  {}", import);
+                    }
+                    println!("exports:");
+                    for export in &info.exports {
+                        println!("  {}", export);
+                    }
+                }
+                OutputFormat::Json => {
+                    let mut report = serde_json::to_value(&info)?;
+                    report["module"] = json!(module);
+                    report["sha256"] = json!(sha256);
+                    report["size"] = json!(binary.len());
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                }
+            }
+            Ok(())
+        }
+    }
+}
 
+/// Opens the module registry, adding the bundled modules the first time.
+fn open_registry(dir: &Path) -> AppResult<ModuleRegistry> {
+    let mut registry = ModuleRegistry::open(dir)?;
+    for (name, version, file) in BUNDLED_MODULES.iter() {
+        if registry.resolve(name).is_ok() || !Path::new(file).exists() {
+            continue;
+        }
+        let added = fs::read(file)
+            .map_err(registry::RegistryError::from)
+            .and_then(|binary| registry.add(name, version, &binary));
+        if let Err(e) = added {
+            eprintln!("[*] Skipping bundled module {} ({}): {}", name, file, e);
+        }
+    }
+    Ok(registry)
+}
 
-fn main() {
-    let enclave = match init_enclave() {
-        Ok(r) => {
-            println!("[+] Init Enclave Successful {}!", r.geteid());
-            r
+/// Loads the module named by `selector`: a registered module, or failing that a `.wasm` file.
+/// Returns a label for it along with its binary.
+fn load_module(registry: &ModuleRegistry, selector: &str) -> AppResult<(String, Vec<u8>)> {
+    match registry.resolve(selector) {
+        Ok(module) => Ok((module.to_string(), registry.load(module)?)),
+        Err(registry::RegistryError::NotFound(_)) if Path::new(selector).is_file() => {
+            Ok((selector.to_string(), read_file(Path::new(selector))?))
         }
-        Err(x) => {
-            println!("[-] Init Enclave Failed {}!", x.as_str());
-            return;
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn print_module(cli: &Cli, action: &str, module: &ModuleEntry) -> AppResult<()> {
+    match cli.format {
+        OutputFormat::Text => println!("[+] {} module {}", action, module),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(module)?),
+    }
+    Ok(())
+}
+
+/// Prints a progress message; only text output shows them.
+fn status<S: fmt::Display>(cli: &Cli, message: S) {
+    if cli.format == OutputFormat::Text {
+        eprintln!("[+] {}", message);
+    }
+}
+
+/// Reads the data owner's policy, if one is given, as compact JSON. The enclave checks its fields.
+fn read_policy(args: &DatasetArgs) -> AppResult<Vec<u8>> {
+    let path = match &args.policy {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    match read_json_from_file(path)? {
+        policy @ Value::Object(_) => Ok(serde_json::to_vec(&policy)?),
+        _ => Err(format!("The policy in {} is not a JSON object", path.display()).into()),
+    }
+}
+
+/// Reads a dataset in the encoding the enclave takes it in, along with that encoding.
+fn read_dataset(args: &DatasetArgs) -> AppResult<(Vec<u8>, DataFormat)> {
+    // Round-trip JSON through serde_json so malformed files are rejected before reaching the enclave. CSV,
+    // columnar and sealed datasets are passed as is; the enclave converts CSV and reports where it is malformed.
+    // A JSON file given as columnar is sent as JSON, which the enclave encodes for guests that take columnar input.
+    let format = args.format()?;
+    let json = || -> AppResult<Vec<u8>> { Ok(serde_json::to_vec(&read_json_from_file(&args.data)?)?) };
+    Ok(match format {
+        DataFormat::Json => (json()?, format),
+        DataFormat::Csv { .. } | DataFormat::Sealed => (read_file(&args.data)?, format),
+        DataFormat::Columnar => {
+            let data = read_file(&args.data)?;
+            if data.starts_with(ntc_guest::columnar::MAGIC) {
+                (data, format)
+            } else {
+                (json()?, DataFormat::Json)
+            }
         }
+    })
+}
+
+fn run_module(cli: &Cli, registry: &ModuleRegistry, args: &RunArgs) -> AppResult<()> {
+    let (module, binary) = load_module(registry, &args.module.module)?;
+    let (data, format) = read_dataset(&args.dataset)?;
+    let policy = read_policy(&args.dataset)?;
+    let schema = match &args.schema {
+        Some(path) => serde_json::to_vec(&read_json_from_file(path)?)?,
+        None => EMPTY_SCHEMA.to_vec(),
     };
 
-    let mut retval = sgx_status_t::SGX_SUCCESS;
-
-    let binary_median = fs::read(WASM_FILE_MEAN).unwrap();
- 
-    // Construct the path to the JSON data and schema files.
-    let test_data_file_path = env::current_dir().unwrap().join("..").join("test_data").join("1_test_data.json");
-    let test_schema_file_path = env::current_dir().unwrap().join("..").join("test_data").join("1_test_schema.json");
-
-    // Read the JSON data and schema from their respective files.
-    let test_json_data = read_json_from_file(&test_data_file_path).expect("Error reading JSON data file");
-    let test_json_schema = read_json_from_file(&test_schema_file_path).expect("Error reading JSON schema file");
-
-    // Serialize the JSON data and schema.
-    let test_serialized_data = serde_json::to_vec(&test_json_data).expect("Failed to serialize data");
-    let test_serialized_schema = serde_json::to_vec(&test_json_schema).expect("Failed to serialize schema");
-
-    let result = unsafe {
-        exec_wasm(
-            enclave.geteid(),
-            &mut retval,
-            test_serialized_data.as_ptr(),
-            test_serialized_data.len(),
-            test_serialized_schema.as_ptr(),
-            test_serialized_schema.len(),
-            binary_median.as_ptr(),
-            binary_median.len(),
-        )
+    with_enclave(cli, true, |executor| {
+        let signing_key = executor.report(&sgx_target_info_t::default())?.signing_key;
+        let (module_hash, output) = if args.encrypt {
+            // Act as a remote requester would: the host only relays the envelopes.
+            let module_hash = Sha256::digest(&binary).into();
+            let reply_key = SecretKey::random(&mut OsRng);
+            let request = envelope::job_request(&reply_key.public_key(), &binary, &data, format, &schema, &policy);
+            let request = envelope::seal(&executor.public_key()?, &request, envelope::JOB_CONTEXT)?;
+            let output = executor.execute_encrypted(&request, &args.limits)?;
+            status(cli, format!("Job and result sent encrypted ({} and {} bytes)", request.len(), output.result.len()));
+            // The receipt commits to the result envelope, so it is checked before decrypting.
+            check_receipt(cli, &output, &signing_key, &module_hash, &data, format, &schema)?;
+            (module_hash, output.decrypt(&reply_key)?)
+        } else {
+            // Register the module once; the job then refers to it by the SHA-256 the enclave returns.
+            let module_hash = executor.register(&binary)?;
+            status(cli, format!("Registered module {} as {}", module, encode_hex(&module_hash)));
+            let output = executor.execute(&module_hash, &data, format, &schema, &policy, &args.limits)?;
+            check_receipt(cli, &output, &signing_key, &module_hash, &data, format, &schema)?;
+            (module_hash, output)
+        };
+        match cli.format {
+            OutputFormat::Text => {
+                for line in output.log_lines() {
+                    eprintln!("[wasm] {}", line);
+                }
+                status(cli, format!("Fuel used: {}", output.fuel_used));
+                if let Ok(result) = output.result() {
+                    println!("{}", serde_json::to_string_pretty(&result)?);
+                }
+                if let Some(stats) = executor.cache_stats() {
+                    status(cli, format!("Module cache: {}", stats));
+                }
+            }
+            OutputFormat::Json => {
+                let mut report = output.to_json();
+                report["module"] = json!(module);
+                report["sha256"] = json!(encode_hex(&module_hash));
+                report["signing_key"] = json!(encode_hex(&signing_key));
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+        }
+
+        let result = output.result()?;
+        if let Some(path) = &args.output {
+            write_json_to_file(path, &result).map_err(|e| format!("Failed to write result to {}: {}", path.display(), e))?;
+            status(cli, format!("Result written to {}", path.display()));
+        }
+        Ok(())
+    })
+}
+
+/// Checks a job's receipt against the enclave's signing key and the job it was given, if the job got one.
+fn check_receipt(
+    cli: &Cli,
+    output: &JobOutput,
+    signing_key: &[u8],
+    module_hash: &ModuleHash,
+    data: &[u8],
+    format: DataFormat,
+    schema: &[u8],
+) -> AppResult<Option<Receipt>> {
+    if output.receipt.is_empty() {
+        return Ok(None);
+    }
+    let receipt = output.verify_receipt(signing_key)?;
+    // The enclave hashes a sealed dataset once unsealed, which the host cannot check.
+    let data_matches = format == DataFormat::Sealed || receipt.data_sha256[..] == Sha256::digest(data)[..];
+    let schema_matches = receipt.schema_sha256[..] == Sha256::digest(schema)[..];
+    if receipt.module_sha256 != *module_hash || !data_matches || !schema_matches {
+        return Err("the job's receipt is for a different module, dataset or schema".into());
+    }
+    status(cli, format!("Receipt {} verified: output {}", receipt.sequence, encode_hex(&receipt.output_sha256)));
+    Ok(Some(receipt))
+}
+
+fn import_dataset(cli: &Cli, args: &ImportDatasetArgs) -> AppResult<()> {
+    let (data, format) = read_dataset(&args.dataset)?;
+    if format == DataFormat::Sealed {
+        return Err(format!("{} is already sealed", args.dataset.data.display()).into());
+    }
+    let policy = read_policy(&args.dataset)?;
+
+    // The enclave's key only lasts as long as the enclave, so the dataset is encrypted to it and imported
+    // within one launch.
+    let sealed = with_enclave(cli, false, |executor| {
+        let public_key = executor.public_key()?;
+        status(cli, format!("Enclave key: {}", encode_hex(&public_key)));
+        let envelope = envelope::seal(&public_key, &data, envelope::DATASET_CONTEXT)?;
+        Ok(executor.import_dataset(&envelope, format, &policy, args.seal_policy)?)
+    })?;
+
+    fs::write(&args.output, &sealed)
+        .map_err(|e| format!("Failed to write sealed dataset to {}: {}", args.output.display(), e))?;
+    match cli.format {
+        OutputFormat::Text => {
+            status(cli, format!("Sealed dataset written to {} ({} bytes)", args.output.display(), sealed.len()))
+        }
+        OutputFormat::Json => {
+            let report = json!({ "output": args.output.display().to_string(), "size": sealed.len() });
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+    Ok(())
+}
+
+fn attest(cli: &Cli, args: &AttestArgs) -> AppResult<()> {
+    let target_info = match &args.target_info {
+        Some(path) => executor::target_info(&read_file(path)?)?,
+        None => sgx_target_info_t::default(),
     };
+    let report = with_enclave(cli, false, |executor| Ok(executor.report(&target_info)?))?;
+    report.check_binding()?;
 
-    match result {
-        sgx_status_t::SGX_SUCCESS => {}
-        _ => {
-            println!("[-] ECALL Enclave Failed {}!", result.as_str());
-            return;
+    if let Some(path) = &args.output {
+        fs::write(path, &report.report).map_err(|e| format!("Failed to write report to {}: {}", path.display(), e))?;
+        status(cli, format!("Report written to {}", path.display()));
+    }
+    let json = report.to_json();
+    match cli.format {
+        OutputFormat::Text => {
+            for field in &["mr_enclave", "mr_signer", "encryption_key", "signing_key"] {
+                println!("{}: {}", field.replace('_', " "), json[*field].as_str().unwrap_or_default());
+            }
+            status(cli, "Report data commits to both keys");
         }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&json)?),
     }
+    Ok(())
+}
 
-    println!(
-        "[+] ecall_test success",
-        
-    );
+fn verify_receipts(cli: &Cli) -> AppResult<()> {
+    let path = &cli.receipt_log;
+    let transcript = receipt_log::verify(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    match cli.format {
+        OutputFormat::Text => {
+            for logged in &transcript.receipts {
+                let receipt = &logged.receipt;
+                println!(
+                    "line {}: #{} of enclave {} at {}: module {}, {}, fuel {} of {}",
+                    logged.line,
+                    receipt.sequence,
+                    &encode_hex(&transcript.enclaves[logged.enclave].mr_enclave)[..16],
+                    receipt.timestamp,
+                    encode_hex(&receipt.module_sha256),
+                    executor::exec_error_name(receipt.exec_error),
+                    receipt.fuel_used,
+                    receipt.limits.fuel
+                );
+            }
+            for problem in &transcript.problems {
+                eprintln!("[-] {}", problem);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&transcript.to_json())?),
+    }
+    if !transcript.problems.is_empty() {
+        return Err(format!("{} problems found in {}", transcript.problems.len(), path.display()).into());
+    }
+    let (receipts, enclaves) = (transcript.receipts.len(), transcript.enclaves.len());
+    status(cli, format!("{} receipts from {} enclave launches verified", receipts, enclaves));
+    Ok(())
+}
+
+fn read_file(path: &Path) -> AppResult<Vec<u8>> {
+    fs::read(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e).into())
+}
 
-    enclave.destroy();
+fn read_json_from_file<P: AsRef<Path>>(path: P) -> AppResult<Value> {
+    let path = path.as_ref();
+    let contents = read_file(path)?;
+    serde_json::from_slice(&contents).map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e).into())
 }
 
-fn read_json_from_file<P: AsRef<Path>>(path: P) -> Result<Value, serde_json::Error> {
-    let mut file = File::open(path).expect("Unable to open file");
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).expect("Unable to read file");
-    serde_json::from_str(&contents)
+fn write_json_to_file<P: AsRef<Path>>(path: P, value: &Value) -> std::io::Result<()> {
+    let contents = serde_json::to_string_pretty(value)?;
+    fs::write(path, contents)
 }
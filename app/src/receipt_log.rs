@@ -0,0 +1,286 @@
+//! Append-only log of the receipts the enclave signs, and the checks `app verify-receipts` makes of it.
+//!
+//! The log holds one JSON document per line. An enclave launch that records receipts first appends its report
+//! as `{"enclave": {...}}`, with the fields of `EnclaveReport::to_json`, and then every job receipt as
+//! `{"receipt": <hex>, "signing_key": <hex>}`. Several processes may append to the same log; lines are never
+//! rewritten.
+
+use crate::executor::{exec_error_name, EnclaveReport, PUBLIC_KEY_LEN};
+use ntc_receipt::verify::{mr_enclave, verify_receipt, verify_report_binding};
+use ntc_receipt::{decode_hex, encode_hex, Receipt};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+pub struct ReceiptLog {
+    file: Mutex<File>,
+    /// The key receipts are signed with, once the enclave has been recorded.
+    signing_key: Option<[u8; PUBLIC_KEY_LEN]>,
+}
+
+impl ReceiptLog {
+    /// Opens the log for appending, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file), signing_key: None })
+    }
+
+    /// Records the report binding the enclave's signing key, which the receipts that follow are signed with.
+    pub fn record_enclave(&mut self, report: &EnclaveReport) -> io::Result<()> {
+        self.append(&json!({ "enclave": report.to_json() }))?;
+        self.signing_key = Some(report.signing_key);
+        Ok(())
+    }
+
+    /// Records a job's signed receipt.
+    pub fn record_receipt(&self, receipt: &[u8]) -> io::Result<()> {
+        let signing_key = self
+            .signing_key
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no enclave has been recorded"))?;
+        self.append(&json!({ "receipt": encode_hex(receipt), "signing_key": encode_hex(&signing_key) }))
+    }
+
+    fn append(&self, entry: &Value) -> io::Result<()> {
+        // One write per line, so that lines appended by other processes do not interleave with it.
+        let line = format!("{}\n", entry);
+        let mut file = self.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+}
+
+/// An enclave launch recorded in the log.
+pub struct LoggedEnclave {
+    pub line: usize,
+    pub mr_enclave: [u8; 32],
+    pub signing_key: Vec<u8>,
+}
+
+/// A receipt in the log whose signature checks out.
+pub struct LoggedReceipt {
+    pub line: usize,
+    /// Index of the enclave in `Transcript::enclaves` that signed it.
+    pub enclave: usize,
+    pub receipt: Receipt,
+}
+
+/// What the log holds, and what is wrong with it.
+pub struct Transcript {
+    pub enclaves: Vec<LoggedEnclave>,
+    pub receipts: Vec<LoggedReceipt>,
+    pub problems: Vec<String>,
+}
+
+impl Transcript {
+    pub fn to_json(&self) -> Value {
+        let enclaves: Vec<Value> = self
+            .enclaves
+            .iter()
+            .map(|enclave| {
+                json!({
+                    "line": enclave.line,
+                    "mr_enclave": encode_hex(&enclave.mr_enclave),
+                    "signing_key": encode_hex(&enclave.signing_key),
+                })
+            })
+            .collect();
+        let receipts: Vec<Value> = self
+            .receipts
+            .iter()
+            .map(|logged| {
+                let mut receipt = receipt_json(&logged.receipt);
+                receipt["line"] = json!(logged.line);
+                receipt["enclave_line"] = json!(self.enclaves[logged.enclave].line);
+                receipt
+            })
+            .collect();
+        json!({
+            "valid": self.problems.is_empty(),
+            "enclaves": enclaves,
+            "receipts": receipts,
+            "problems": self.problems,
+        })
+    }
+}
+
+/// A receipt's fields as JSON, with hashes in hex.
+pub fn receipt_json(receipt: &Receipt) -> Value {
+    json!({
+        "sequence": receipt.sequence,
+        "timestamp": receipt.timestamp,
+        "error": { "code": receipt.exec_error, "kind": exec_error_name(receipt.exec_error) },
+        "module_sha256": encode_hex(&receipt.module_sha256),
+        "data_sha256": encode_hex(&receipt.data_sha256),
+        "schema_sha256": encode_hex(&receipt.schema_sha256),
+        "output_sha256": encode_hex(&receipt.output_sha256),
+        "limits": {
+            "fuel": receipt.limits.fuel,
+            "memory_pages": receipt.limits.memory_pages,
+            "output_bytes": receipt.limits.output_bytes,
+            "log_bytes": receipt.limits.log_bytes,
+        },
+        "fuel_used": receipt.fuel_used,
+    })
+}
+
+/// Checks every entry of the log at `path`: that each enclave report binds its keys, that each receipt is
+/// signed by the key of an enclave recorded before it, and that each enclave's receipts are numbered from 1
+/// without gaps or repeats. Receipts missing from the end of an enclave's sequence cannot be detected.
+pub fn verify(path: &Path) -> io::Result<Transcript> {
+    let mut transcript = Transcript { enclaves: Vec::new(), receipts: Vec::new(), problems: Vec::new() };
+    let mut enclaves_by_key = BTreeMap::new();
+    for (index, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line_number = index + 1;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Value = match serde_json::from_str(&line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                transcript.problems.push(format!("line {}: not JSON: {}", line_number, e));
+                continue;
+            }
+        };
+        let hex_field = |value: &Value, key: &str| value[key].as_str().and_then(decode_hex).unwrap_or_default();
+        if let Some(enclave) = entry.get("enclave") {
+            let report = hex_field(enclave, "report");
+            let signing_key = hex_field(enclave, "signing_key");
+            let checked = verify_report_binding(&report, &hex_field(enclave, "encryption_key"), &signing_key)
+                .and_then(|()| mr_enclave(&report));
+            match checked {
+                Ok(mr_enclave) => {
+                    enclaves_by_key.insert(signing_key.clone(), transcript.enclaves.len());
+                    transcript.enclaves.push(LoggedEnclave { line: line_number, mr_enclave, signing_key });
+                }
+                Err(e) => transcript.problems.push(format!("line {}: invalid enclave report: {}", line_number, e)),
+            }
+        } else if entry.get("receipt").is_some() {
+            let signing_key = hex_field(&entry, "signing_key");
+            let enclave = match enclaves_by_key.get(&signing_key) {
+                Some(enclave) => *enclave,
+                None => {
+                    let problem = "receipt signed by a key no earlier enclave report binds";
+                    transcript.problems.push(format!("line {}: {}", line_number, problem));
+                    continue;
+                }
+            };
+            match verify_receipt(&hex_field(&entry, "receipt"), &signing_key) {
+                Ok(receipt) => transcript.receipts.push(LoggedReceipt { line: line_number, enclave, receipt }),
+                Err(e) => transcript.problems.push(format!("line {}: {}", line_number, e)),
+            }
+        } else {
+            transcript.problems.push(format!("line {}: neither an enclave nor a receipt", line_number));
+        }
+    }
+
+    // Jobs finish, and are logged, in any order, so each enclave's receipts are checked as a set.
+    for (index, enclave) in transcript.enclaves.iter().enumerate() {
+        let mut sequences: Vec<u64> = transcript
+            .receipts
+            .iter()
+            .filter(|logged| logged.enclave == index)
+            .map(|logged| logged.receipt.sequence)
+            .collect();
+        sequences.sort_unstable();
+        let mut expected = 1;
+        for sequence in sequences {
+            if sequence < expected {
+                let problem = format!("receipt {} of the enclave on line {} is repeated", sequence, enclave.line);
+                transcript.problems.push(problem);
+                continue;
+            }
+            if sequence > expected {
+                let missing = if sequence == expected + 1 {
+                    format!("receipt {}", expected)
+                } else {
+                    format!("receipts {} to {}", expected, sequence - 1)
+                };
+                transcript.problems.push(format!("{} of the enclave on line {} are missing", missing, enclave.line));
+            }
+            expected = sequence + 1;
+        }
+    }
+    Ok(transcript)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntc_receipt::verify::{REPORT_DATA_OFFSET, REPORT_LEN};
+    use ntc_receipt::Limits;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+    use rand_core::OsRng;
+    use sha2::{Digest, Sha256};
+
+    /// A report binding `signing_key`, as the enclave would create it.
+    fn enclave_report(signing_key: &SigningKey) -> EnclaveReport {
+        let encryption_key = [4; PUBLIC_KEY_LEN];
+        let mut signing = [0; PUBLIC_KEY_LEN];
+        signing.copy_from_slice(signing_key.verifying_key().to_encoded_point(false).as_bytes());
+        let mut report = vec![0; REPORT_LEN];
+        let commitment = Sha256::digest(ntc_receipt::key_binding(&encryption_key, &signing));
+        report[REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + 32].copy_from_slice(&commitment);
+        EnclaveReport { report, encryption_key, signing_key: signing }
+    }
+
+    fn signed_receipt(key: &SigningKey, sequence: u64) -> Vec<u8> {
+        let receipt = Receipt {
+            sequence,
+            timestamp: 1_700_000_000 + sequence,
+            exec_error: 0,
+            module_sha256: [1; 32],
+            data_sha256: [2; 32],
+            schema_sha256: [3; 32],
+            output_sha256: [4; 32],
+            limits: Limits { fuel: 100, memory_pages: 16, output_bytes: 1024, log_bytes: 1024 },
+            fuel_used: 42,
+        };
+        let mut signed = receipt.encode();
+        let signature: Signature = key.sign(&signed);
+        signed.extend_from_slice(&signature.to_bytes());
+        signed
+    }
+
+    #[test]
+    fn verify_finds_forged_and_missing_receipts() {
+        let path = std::env::temp_dir().join(format!("ntc-receipts-test-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let key = SigningKey::random(&mut OsRng);
+        let mut log = ReceiptLog::open(&path).unwrap();
+        assert!(log.record_receipt(&signed_receipt(&key, 1)).is_err());
+        log.record_enclave(&enclave_report(&key)).unwrap();
+        for sequence in &[2, 1] {
+            log.record_receipt(&signed_receipt(&key, *sequence)).unwrap();
+        }
+
+        let transcript = verify(&path).unwrap();
+        assert!(transcript.problems.is_empty(), "{:?}", transcript.problems);
+        assert_eq!(transcript.enclaves.len(), 1);
+        assert_eq!(transcript.receipts[0].receipt.sequence, 2);
+        assert_eq!(transcript.to_json()["receipts"][1]["fuel_used"], 42);
+
+        // A receipt skipped, one signed by an unknown key, and one tampered with.
+        log.record_receipt(&signed_receipt(&key, 5)).unwrap();
+        let other = SigningKey::random(&mut OsRng);
+        let stranger = encode_hex(other.verifying_key().to_encoded_point(false).as_bytes());
+        let mut tampered = signed_receipt(&key, 6);
+        tampered[20] ^= 1;
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        let forged = json!({ "receipt": encode_hex(&signed_receipt(&other, 6)), "signing_key": stranger });
+        let enclave_key = encode_hex(&log.signing_key.unwrap());
+        let tampered = json!({ "receipt": encode_hex(&tampered), "signing_key": enclave_key });
+        writeln!(file, "{}\n{}", forged, tampered).unwrap();
+
+        let problems = verify(&path).unwrap().problems;
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("line 5: receipt signed by a key"));
+        assert_eq!(problems[1], "line 6: signature does not match the receipt");
+        assert_eq!(problems[2], "receipts 3 to 4 of the enclave on line 1 are missing");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+//! Host-side registry of named, versioned WASM modules.
+//!
+//! Modules live in a single directory: each binary is stored once as `<sha256>.wasm`, and `registry.json`
+//! maps names and versions to those hashes. The hash is the module's identity; it is the same SHA-256 the
+//! enclave returns from `register_module` and expects in `exec_module`.
+
+use ntc_receipt::encode_hex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+static MANIFEST_FILE: &str = "registry.json";
+
+/// Shortest hash prefix accepted when selecting a module by hash.
+const MIN_HASH_PREFIX: usize = 8;
+
+/// A module recorded in the registry.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ModuleEntry {
+    pub name: String,
+    pub version: String,
+    /// Lowercase hex SHA-256 of the binary.
+    pub sha256: String,
+}
+
+impl ModuleEntry {
+    fn file_name(&self) -> String {
+        format!("{}.wasm", self.sha256)
+    }
+}
+
+impl fmt::Display for ModuleEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{} {}", self.name, self.version, self.sha256)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    modules: Vec<ModuleEntry>,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(io::Error),
+    Manifest(serde_json::Error),
+    /// No module matches the selector.
+    NotFound(String),
+    /// A hash prefix matches more than one module.
+    Ambiguous(String),
+    /// The name and version are already registered with a different binary.
+    Conflict { name: String, version: String, sha256: String },
+    /// A stored binary no longer matches the hash it is registered under.
+    Corrupted { sha256: String, actual: String },
+    InvalidName(String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::Io(err) => write!(f, "registry I/O error: {}", err),
+            RegistryError::Manifest(err) => write!(f, "invalid registry manifest: {}", err),
+            RegistryError::NotFound(selector) => write!(f, "no module matches `{}`", selector),
+            RegistryError::Ambiguous(selector) => write!(f, "`{}` matches more than one module", selector),
+            RegistryError::Conflict { name, version, sha256 } => {
+                write!(f, "{}@{} is already registered as {}", name, version, sha256)
+            }
+            RegistryError::Corrupted { sha256, actual } => {
+                write!(f, "stored binary for {} hashes to {}", sha256, actual)
+            }
+            RegistryError::InvalidName(name) => write!(f, "invalid module name or version `{}`", name),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+impl From<io::Error> for RegistryError {
+    fn from(err: io::Error) -> Self {
+        RegistryError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for RegistryError {
+    fn from(err: serde_json::Error) -> Self {
+        RegistryError::Manifest(err)
+    }
+}
+
+pub struct ModuleRegistry {
+    dir: PathBuf,
+    manifest: Manifest,
+}
+
+impl ModuleRegistry {
+    /// Opens the registry in `dir`, creating the directory if it does not exist yet.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self, RegistryError> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let manifest = match fs::read(dir.join(MANIFEST_FILE)) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Manifest::default(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self { dir, manifest })
+    }
+
+    /// Registered modules, sorted by name and version.
+    pub fn list(&self) -> &[ModuleEntry] {
+        &self.manifest.modules
+    }
+
+    /// Stores `binary` as `name@version`. Adding the same binary again is a no-op; reusing a name and
+    /// version for a different binary is refused.
+    pub fn add(&mut self, name: &str, version: &str, binary: &[u8]) -> Result<ModuleEntry, RegistryError> {
+        for part in &[name, version] {
+            if part.is_empty() || part.contains('@') || part.chars().any(char::is_whitespace) {
+                return Err(RegistryError::InvalidName(part.to_string()));
+            }
+        }
+        let entry = ModuleEntry {
+            name: name.to_string(),
+            version: version.to_string(),
+            sha256: sha256_hex(binary),
+        };
+        if let Some(existing) = self.find(name, version) {
+            if existing.sha256 == entry.sha256 {
+                return Ok(existing.clone());
+            }
+            return Err(RegistryError::Conflict {
+                name: entry.name,
+                version: entry.version,
+                sha256: existing.sha256.clone(),
+            });
+        }
+
+        let path = self.dir.join(entry.file_name());
+        if !path.exists() {
+            fs::write(&path, binary)?;
+        }
+        self.manifest.modules.push(entry.clone());
+        self.manifest
+            .modules
+            .sort_by(|a, b| a.name.cmp(&b.name).then_with(|| compare_versions(&a.version, &b.version)));
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Removes the module matching `selector`, deleting its binary once no other entry refers to it.
+    pub fn remove(&mut self, selector: &str) -> Result<ModuleEntry, RegistryError> {
+        let entry = self.resolve(selector)?.clone();
+        self.manifest.modules.retain(|module| module != &entry);
+        self.save()?;
+        if !self.manifest.modules.iter().any(|module| module.sha256 == entry.sha256) {
+            match fs::remove_file(self.dir.join(entry.file_name())) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Selects a module by `name` (its latest version), `name@version`, or a hash or hash prefix of at
+    /// least 8 hex digits.
+    pub fn resolve(&self, selector: &str) -> Result<&ModuleEntry, RegistryError> {
+        let not_found = || RegistryError::NotFound(selector.to_string());
+        if let Some((name, version)) = split_selector(selector) {
+            return self.find(name, version).ok_or_else(not_found);
+        }
+
+        let latest = self
+            .manifest
+            .modules
+            .iter()
+            .filter(|module| module.name == selector)
+            .max_by(|a, b| compare_versions(&a.version, &b.version));
+        if let Some(module) = latest {
+            return Ok(module);
+        }
+
+        if selector.len() < MIN_HASH_PREFIX || !selector.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(not_found());
+        }
+        let prefix = selector.to_ascii_lowercase();
+        let mut matches = self.manifest.modules.iter().filter(|module| module.sha256.starts_with(&prefix));
+        let first = matches.next().ok_or_else(not_found)?;
+        // Several names may share one binary; only distinct binaries make the prefix ambiguous.
+        if matches.any(|module| module.sha256 != first.sha256) {
+            return Err(RegistryError::Ambiguous(selector.to_string()));
+        }
+        Ok(first)
+    }
+
+    /// Reads the binary of `entry`, checking it still matches its hash.
+    pub fn load(&self, entry: &ModuleEntry) -> Result<Vec<u8>, RegistryError> {
+        let binary = fs::read(self.dir.join(entry.file_name()))?;
+        let actual = sha256_hex(&binary);
+        if actual != entry.sha256 {
+            return Err(RegistryError::Corrupted { sha256: entry.sha256.clone(), actual });
+        }
+        Ok(binary)
+    }
+
+    fn find(&self, name: &str, version: &str) -> Option<&ModuleEntry> {
+        self.manifest
+            .modules
+            .iter()
+            .find(|module| module.name == name && module.version == version)
+    }
+
+    /// Rewrites the manifest through a temporary file so an interrupted write cannot corrupt it.
+    fn save(&self) -> Result<(), RegistryError> {
+        let contents = serde_json::to_vec_pretty(&self.manifest)?;
+        let temp = self.dir.join(format!("{}.tmp", MANIFEST_FILE));
+        fs::write(&temp, contents)?;
+        fs::rename(&temp, self.dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+}
+
+/// Lowercase hex SHA-256 of `binary`.
+pub fn sha256_hex(binary: &[u8]) -> String {
+    encode_hex(&Sha256::digest(binary))
+}
+
+fn split_selector(selector: &str) -> Option<(&str, &str)> {
+    let at = selector.find('@')?;
+    Some((&selector[..at], &selector[at + 1..]))
+}
+
+/// Orders versions by their dot-separated numeric components, falling back to plain string order.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |version: &str| -> Option<Vec<u64>> { version.split('.').map(|part| part.parse().ok()).collect() };
+    match (parse(a), parse(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
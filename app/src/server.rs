@@ -0,0 +1,375 @@
+//! Local HTTP/JSON API for submitting jobs to a long-lived enclave.
+//!
+//! - `POST /jobs` with `{"module": ..., "data": ..., "schema": ..., "limits": ...}` queues a job and
+//!   answers `202` with its id. `module` is a registry selector (name, `name@version` or hash), `schema`
+//!   defaults to `{}` and `limits` takes the same `key=value` list as `app run --limits`.
+//!   With `"data_format": "csv"`, `data` is a string of CSV with a header row; `csv_delimiter` and
+//!   `csv_quote` optionally give its one-character delimiter and quote. With `"data_format": "columnar"`,
+//!   `data` is a dataset in the binary columnar format of `ntc_guest::columnar`, as a hex string; JSON data is
+//!   encoded by the enclave for guests that take columnar input. With `"data_format": "sealed"`, `data` is a
+//!   dataset sealed by `app import-dataset`, as a hex string.
+//!   An optional `policy` object restricts the job as a data owner's policy does (see `app import-dataset
+//!   --policy`); the enclave also enforces the policy sealed with a dataset.
+//!   When the queue is full it answers `503` with `Retry-After`.
+//! - `POST /jobs` with `{"encrypted": ..., "limits": ...}` queues a job request sealed to the enclave key, as
+//!   hex. The job's status reports its result and log as hex envelopes for the requester (`encrypted_result`
+//!   and `encrypted_log`), and only the code of its error, if any.
+//! - `GET /jobs/{id}` returns the job's status and, once it has finished, its result or error.
+//! - `GET /enclave-key` returns the enclave's public key as `{"public_key": <hex>}`.
+//! - `GET /enclave-report` returns an SGX report of the enclave, targeting no enclave, whose report data
+//!   commits to its encryption key and the key that signs job receipts, as hex: `report`, `mr_enclave`,
+//!   `mr_signer`, `encryption_key` and `signing_key`. Finished jobs carry their signed `receipt` as hex.
+//! - `DELETE /jobs/{id}` cancels a job that has not started yet.
+//! - `GET /modules` lists the module registry.
+
+use crate::cli::{DataFormat, Limits};
+use crate::executor::Executor;
+use crate::jobs::{CancelError, JobInput, JobQueue, JobSpec, JobStatus};
+use crate::registry::{ModuleRegistry, RegistryError};
+use ntc_receipt::{decode_hex, encode_hex};
+use serde_json::{json, Value};
+use sgx_types::sgx_target_info_t;
+use std::error;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Largest request body accepted, including the dataset.
+const MAX_REQUEST_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Seconds a client is asked to wait before resubmitting to a full queue.
+const RETRY_AFTER_SECS: u32 = 1;
+
+/// An error answered with `status` and `{"error": message}`.
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new<S: Into<String>>(status: u16, message: S) -> Self {
+        Self { status, message: message.into() }
+    }
+}
+
+impl From<RegistryError> for ApiError {
+    fn from(err: RegistryError) -> Self {
+        let status = match err {
+            RegistryError::NotFound(_) => 404,
+            RegistryError::Ambiguous(_) => 400,
+            _ => 500,
+        };
+        ApiError::new(status, err.to_string())
+    }
+}
+
+pub struct ApiServer<E> {
+    http: Server,
+    queue: JobQueue<E>,
+    registry_dir: PathBuf,
+}
+
+impl<E: Executor + 'static> ApiServer<E> {
+    /// Listens on `addr`, running jobs from `queue`.
+    pub fn bind(addr: &str, queue: JobQueue<E>, registry_dir: PathBuf) -> Result<Self, Box<dyn error::Error + Send + Sync>> {
+        let http = Server::http(addr)?;
+        Ok(Self { http, queue, registry_dir })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Answers requests until the process exits.
+    pub fn run(&self) {
+        for mut request in self.http.incoming_requests() {
+            let (status, body) = match self.route(&mut request) {
+                Ok(response) => response,
+                Err(e) => (e.status, json!({ "error": e.message })),
+            };
+            let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                .expect("static header is valid");
+            let mut response = Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(content_type);
+            if status == 503 {
+                let retry_after = Header::from_bytes(&b"Retry-After"[..], RETRY_AFTER_SECS.to_string().as_bytes())
+                    .expect("numeric header is valid");
+                response.add_header(retry_after);
+            }
+            // The client may have gone away; there is nobody left to tell.
+            let _ = request.respond(response);
+        }
+    }
+
+    fn route(&self, request: &mut Request) -> Result<(u16, Value), ApiError> {
+        let url = request.url().to_string();
+        let path = url.split('?').next().unwrap_or("");
+        let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+        match (request.method(), segments.as_slice()) {
+            (Method::Get, ["modules"]) => {
+                let registry = ModuleRegistry::open(&self.registry_dir)?;
+                Ok((200, json!(registry.list())))
+            }
+            (Method::Get, ["enclave-key"]) => {
+                let public_key = self.queue.executor().public_key().map_err(|e| ApiError::new(500, e.to_string()))?;
+                Ok((200, json!({ "public_key": encode_hex(&public_key) })))
+            }
+            (Method::Get, ["enclave-report"]) => {
+                let report = self
+                    .queue
+                    .executor()
+                    .report(&sgx_target_info_t::default())
+                    .map_err(|e| ApiError::new(500, e.to_string()))?;
+                Ok((200, report.to_json()))
+            }
+            (Method::Post, ["jobs"]) => self.submit(request),
+            (Method::Get, ["jobs", id]) => {
+                let id: u64 = id.parse().map_err(|_| ApiError::new(404, "no such job"))?;
+                match self.queue.status(id) {
+                    Some(job) => Ok((200, job)),
+                    None => Err(ApiError::new(404, "no such job")),
+                }
+            }
+            (Method::Delete, ["jobs", id]) => {
+                let id: u64 = id.parse().map_err(|_| ApiError::new(404, "no such job"))?;
+                match self.queue.cancel(id) {
+                    Ok(()) => Ok((200, self.queue.status(id).unwrap_or(Value::Null))),
+                    Err(CancelError::NotFound) => Err(ApiError::new(404, "no such job")),
+                    Err(CancelError::NotQueued(status)) => {
+                        Err(ApiError::new(409, format!("job is {} and can no longer be cancelled", status)))
+                    }
+                }
+            }
+            (_, ["modules"]) | (_, ["enclave-key"]) | (_, ["enclave-report"]) | (_, ["jobs"]) | (_, ["jobs", _]) => {
+                Err(ApiError::new(405, "method not allowed"))
+            }
+            _ => Err(ApiError::new(404, "not found")),
+        }
+    }
+
+    fn submit(&self, request: &mut Request) -> Result<(u16, Value), ApiError> {
+        let mut body = Vec::new();
+        request
+            .as_reader()
+            .take(MAX_REQUEST_BYTES + 1)
+            .read_to_end(&mut body)
+            .map_err(|e| ApiError::new(400, e.to_string()))?;
+        if body.len() as u64 > MAX_REQUEST_BYTES {
+            return Err(ApiError::new(413, "request body too large"));
+        }
+        let body: Value = serde_json::from_slice(&body).map_err(|e| ApiError::new(400, e.to_string()))?;
+        let limits = match body.get("limits") {
+            Some(Value::String(limits)) => limits.parse().map_err(|e: String| ApiError::new(400, e))?,
+            Some(_) => return Err(ApiError::new(400, "`limits` must be a string")),
+            None => Limits::default(),
+        };
+
+        // The module of an encrypted job is only known to the requester and the enclave.
+        let spec = match body.get("encrypted") {
+            Some(Value::String(hex)) => JobSpec {
+                module: "encrypted".to_string(),
+                sha256: String::new(),
+                input: JobInput::Encrypted(
+                    decode_hex(hex).ok_or_else(|| ApiError::new(400, "`encrypted` is not valid hex"))?,
+                ),
+                limits,
+            },
+            Some(_) => return Err(ApiError::new(400, "`encrypted` must be a hex string")),
+            None => self.plain_job(&body, limits)?,
+        };
+        match self.queue.submit(spec) {
+            Ok(id) => Ok((202, json!({ "id": id, "status": JobStatus::Queued }))),
+            Err(full) => Err(ApiError::new(503, format!("job queue is full ({} jobs waiting)", full.capacity))),
+        }
+    }
+
+    /// The job described by the fields of a request that is not encrypted.
+    fn plain_job(&self, body: &Value, limits: Limits) -> Result<JobSpec, ApiError> {
+        let selector = body["module"]
+            .as_str()
+            .ok_or_else(|| ApiError::new(400, "`module` must be a string"))?;
+        let data_format = match body.get("data_format") {
+            Some(Value::String(name)) => {
+                let delimiter = csv_char(body, "csv_delimiter", ',')?;
+                let quote = csv_char(body, "csv_quote", '"')?;
+                DataFormat::new(name, delimiter, quote).map_err(|e| ApiError::new(400, e))?
+            }
+            Some(_) => return Err(ApiError::new(400, "`data_format` must be a string")),
+            None => DataFormat::Json,
+        };
+        let data = match (body.get("data"), data_format) {
+            (Some(Value::String(csv)), DataFormat::Csv { .. }) => csv.clone().into_bytes(),
+            (Some(_), DataFormat::Csv { .. }) => return Err(ApiError::new(400, "CSV `data` must be a string")),
+            (Some(Value::String(hex)), DataFormat::Columnar) => {
+                decode_hex(hex).ok_or_else(|| ApiError::new(400, "columnar `data` is not valid hex"))?
+            }
+            (Some(_), DataFormat::Columnar) => return Err(ApiError::new(400, "columnar `data` must be a hex string")),
+            (Some(Value::String(hex)), DataFormat::Sealed) => {
+                decode_hex(hex).ok_or_else(|| ApiError::new(400, "sealed `data` is not valid hex"))?
+            }
+            (Some(_), DataFormat::Sealed) => return Err(ApiError::new(400, "sealed `data` must be a hex string")),
+            (Some(data), DataFormat::Json) => data.to_string().into_bytes(),
+            (None, _) => return Err(ApiError::new(400, "`data` is required")),
+        };
+        let schema = match body.get("schema") {
+            Some(schema) => schema.to_string().into_bytes(),
+            None => crate::EMPTY_SCHEMA.to_vec(),
+        };
+        let policy = match body.get("policy") {
+            Some(policy @ Value::Object(_)) => policy.to_string().into_bytes(),
+            Some(_) => return Err(ApiError::new(400, "`policy` must be an object")),
+            None => Vec::new(),
+        };
+
+        let registry = ModuleRegistry::open(&self.registry_dir)?;
+        let module = registry.resolve(selector)?;
+        Ok(JobSpec {
+            module: format!("{}@{}", module.name, module.version),
+            sha256: module.sha256.clone(),
+            input: JobInput::Plain { binary: registry.load(module)?, data, data_format, schema, policy },
+            limits,
+        })
+    }
+}
+
+/// The one-character string under `key` in a job request, or `default` if it is absent.
+fn csv_char(body: &Value, key: &str, default: char) -> Result<char, ApiError> {
+    let value = match body.get(key) {
+        Some(value) => value,
+        None => return Ok(default),
+    };
+    let mut chars = value.as_str().unwrap_or_default().chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(ApiError::new(400, format!("`{}` must be a one-character string", key))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{EnclaveError, EnclaveReport, JobOutput, ModuleHash, PUBLIC_KEY_LEN};
+    use ntc_receipt::verify::{verify_report_binding, REPORT_DATA_OFFSET, REPORT_LEN};
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Echoes each job's data back as its result.
+    struct EchoExecutor;
+
+    impl Executor for EchoExecutor {
+        fn register(&self, binary: &[u8]) -> Result<ModuleHash, EnclaveError> {
+            Ok(Sha256::digest(binary).into())
+        }
+
+        fn execute(&self, _: &ModuleHash, data: &[u8], _: DataFormat, _: &[u8], _: &[u8], limits: &Limits)
+            -> Result<JobOutput, EnclaveError> {
+            let log = b"echo\n".to_vec();
+            Ok(JobOutput { result: data.to_vec(), log, fuel_used: limits.fuel, exec_error: 0, receipt: vec![] })
+        }
+
+        fn public_key(&self) -> Result<[u8; PUBLIC_KEY_LEN], EnclaveError> {
+            Ok([4; PUBLIC_KEY_LEN])
+        }
+
+        fn execute_encrypted(&self, request: &[u8], limits: &Limits) -> Result<JobOutput, EnclaveError> {
+            let result = request.to_vec();
+            Ok(JobOutput { result, log: vec![], fuel_used: limits.fuel, exec_error: 0, receipt: vec![] })
+        }
+
+        fn report(&self, _: &sgx_target_info_t) -> Result<EnclaveReport, EnclaveError> {
+            let (encryption_key, signing_key) = ([4; PUBLIC_KEY_LEN], [5; PUBLIC_KEY_LEN]);
+            let mut report = vec![0; REPORT_LEN];
+            let commitment = Sha256::digest(ntc_receipt::key_binding(&encryption_key, &signing_key));
+            report[REPORT_DATA_OFFSET..REPORT_DATA_OFFSET + 32].copy_from_slice(&commitment);
+            Ok(EnclaveReport { report, encryption_key, signing_key })
+        }
+    }
+
+    fn wait_for_finish(addr: SocketAddr, path: &str) -> Value {
+        let mut job = Value::Null;
+        for _ in 0..200 {
+            job = request(addr, "GET", path, "").1;
+            if job["status"] == "succeeded" {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        job
+    }
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn serves_jobs_over_loopback() {
+        let dir = std::env::temp_dir().join(format!("ntc-api-test-{}", std::process::id()));
+        let mut registry = ModuleRegistry::open(&dir).unwrap();
+        let module = registry.add("echo", "1.0", b"\0asm\x01\0\0\0").unwrap();
+
+        let server = ApiServer::bind("127.0.0.1:0", JobQueue::new(EchoExecutor, 2, 8), dir.clone()).unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let (status, modules) = request(addr, "GET", "/modules", "");
+        assert_eq!(status, 200);
+        assert_eq!(modules[0]["name"], "echo");
+
+        let (status, submitted) =
+            request(addr, "POST", "/jobs", r#"{"module": "echo", "data": {"x": [1, 2]}, "limits": "fuel=7"}"#);
+        assert_eq!(status, 202);
+        let path = format!("/jobs/{}", submitted["id"]);
+        let job = wait_for_finish(addr, &path);
+        assert_eq!(job["status"], "succeeded");
+        assert_eq!(job["sha256"], json!(module.sha256));
+        assert_eq!(job["result"], json!({ "x": [1, 2] }));
+        assert_eq!(job["fuel_used"], 7);
+        assert_eq!(job["log"], json!(["echo"]));
+
+        let (status, key) = request(addr, "GET", "/enclave-key", "");
+        assert_eq!(status, 200);
+        assert_eq!(key["public_key"], "04".repeat(PUBLIC_KEY_LEN));
+        let (status, report) = request(addr, "GET", "/enclave-report", "");
+        assert_eq!(status, 200);
+        assert_eq!(report["signing_key"], "05".repeat(PUBLIC_KEY_LEN));
+        assert_eq!(report["mr_enclave"], "00".repeat(32));
+        let raw_report = decode_hex(report["report"].as_str().unwrap()).unwrap();
+        assert_eq!(verify_report_binding(&raw_report, &[4; PUBLIC_KEY_LEN], &[5; PUBLIC_KEY_LEN]), Ok(()));
+        let (status, submitted) = request(addr, "POST", "/jobs", r#"{"encrypted": "c0ffee", "limits": "fuel=3"}"#);
+        assert_eq!(status, 202);
+        let job = wait_for_finish(addr, &format!("/jobs/{}", submitted["id"]));
+        assert_eq!(job["status"], "succeeded");
+        assert_eq!(job["module"], "encrypted");
+        assert_eq!(job["encrypted_result"], "c0ffee");
+        assert!(job.get("result").is_none());
+        assert_eq!(request(addr, "POST", "/jobs", r#"{"encrypted": "c0ffe"}"#).0, 400);
+
+        assert_eq!(request(addr, "POST", "/jobs", r#"{"module": "missing", "data": 1}"#).0, 404);
+        assert_eq!(request(addr, "POST", "/jobs", r#"{"module": "echo"}"#).0, 400);
+        assert_eq!(request(addr, "POST", "/jobs", r#"{"module": "echo", "data": 1, "policy": []}"#).0, 400);
+        assert_eq!(request(addr, "GET", "/jobs/999", "").0, 404);
+        assert_eq!(request(addr, "DELETE", &path, "").0, 409);
+        assert_eq!(request(addr, "DELETE", "/modules", "").0, 405);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
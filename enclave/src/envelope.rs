@@ -0,0 +1,176 @@
+//! The enclave's key pair, and the envelopes through which data owners send it data that the host cannot read,
+//! and through which it answers them.
+//!
+//! The key pair is generated when first needed and lives as long as the enclave, so it is never seen outside
+//! it. An envelope is laid out as
+//!
+//! ```text
+//! sender key   65 bytes: an ephemeral P-256 public key, SEC1 uncompressed (0x04 || x || y)
+//! nonce        12 bytes
+//! ciphertext   AES-128-GCM, as long as the plaintext
+//! tag          16 bytes
+//! ```
+//!
+//! The AES key is the first 16 bytes of SHA-256(`KDF_LABEL` || x), where x is the big-endian x-coordinate of
+//! the ECDH shared point, and the envelope's context (e.g. `b"ntc-dataset"`) is authenticated as additional
+//! data so that an envelope meant for one purpose cannot be replayed for another. SGX keeps curve points and
+//! scalars little-endian; everything on the wire is big-endian.
+
+use sgx_tcrypto::{rsgx_rijndael128GCM_decrypt, rsgx_rijndael128GCM_encrypt, rsgx_sha256_slice, SgxEccHandle};
+use sgx_trts::trts::rsgx_read_rand;
+use sgx_types::*;
+use std::string::{String, ToString};
+use std::sync::SgxMutex;
+use std::vec::Vec;
+
+/// Context of the envelopes in which datasets are sent to `import_dataset`.
+pub const DATASET_CONTEXT: &[u8] = b"ntc-dataset";
+
+/// Context of the envelopes in which jobs are sent to `exec_encrypted`.
+pub const JOB_CONTEXT: &[u8] = b"ntc-job";
+
+/// Context of the envelope holding an encrypted job's result or error message.
+pub const RESULT_CONTEXT: &[u8] = b"ntc-result";
+
+/// Context of the envelope holding an encrypted job's log.
+pub const LOG_CONTEXT: &[u8] = b"ntc-log";
+
+/// Size of a SEC1 uncompressed P-256 public key.
+pub const PUBLIC_KEY_LEN: usize = 65;
+
+const NONCE_LEN: usize = SGX_AESGCM_IV_SIZE;
+const TAG_LEN: usize = 16;
+
+/// Bytes an envelope adds to its plaintext.
+pub const OVERHEAD: usize = PUBLIC_KEY_LEN + NONCE_LEN + TAG_LEN;
+
+/// Prefix of the hashed shared secret from which envelope keys are derived.
+const KDF_LABEL: &[u8] = b"ntc-envelope-v1";
+
+lazy_static! {
+    static ref KEY_PAIR: SgxMutex<Option<(sgx_ec256_private_t, sgx_ec256_public_t)>> = SgxMutex::new(None);
+}
+
+/// The enclave's key pair, generated on first use.
+fn key_pair() -> SgxResult<(sgx_ec256_private_t, sgx_ec256_public_t)> {
+    let mut key_pair = KEY_PAIR.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(key_pair) = *key_pair {
+        return Ok(key_pair);
+    }
+    let handle = SgxEccHandle::new();
+    handle.open()?;
+    let generated = handle.create_key_pair()?;
+    *key_pair = Some(generated);
+    Ok(generated)
+}
+
+/// The enclave's public key, SEC1 uncompressed.
+pub fn public_key() -> SgxResult<[u8; PUBLIC_KEY_LEN]> {
+    let (_, public) = key_pair()?;
+    Ok(encode_public_key(&public))
+}
+
+/// Decrypts an envelope sealed to the enclave's public key for `context`.
+pub fn open(envelope: &[u8], context: &[u8]) -> Result<Vec<u8>, String> {
+    if envelope.len() < OVERHEAD {
+        return Err("envelope is truncated".to_string());
+    }
+    let (sender, rest) = envelope.split_at(PUBLIC_KEY_LEN);
+    let (nonce, rest) = rest.split_at(NONCE_LEN);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+
+    let sender = decode_public_key(sender).ok_or("envelope does not start with an uncompressed P-256 key")?;
+    let (private, _) = key_pair().map_err(|status| format!("enclave key is unavailable: {}", status))?;
+    let key = shared_key(&private, &sender)?;
+
+    let mut mac: sgx_aes_gcm_128bit_tag_t = [0; TAG_LEN];
+    mac.copy_from_slice(tag);
+    let mut plaintext = vec![0; ciphertext.len()];
+    rsgx_rijndael128GCM_decrypt(&key, ciphertext, nonce, context, &mac, &mut plaintext)
+        .map_err(|_| "envelope cannot be decrypted with the enclave key".to_string())?;
+    Ok(plaintext)
+}
+
+/// Encrypts `plaintext` for `context` to `recipient`, a SEC1 uncompressed P-256 public key.
+pub fn seal(recipient: &[u8], plaintext: &[u8], context: &[u8]) -> Result<Vec<u8>, String> {
+    let recipient = decode_public_key(recipient).ok_or("recipient key is not an uncompressed P-256 key")?;
+    let handle = SgxEccHandle::new();
+    handle.open().map_err(|status| format!("cannot open the ECC context: {}", status))?;
+    let (private, public) = handle
+        .create_key_pair()
+        .map_err(|status| format!("cannot generate an ephemeral key: {}", status))?;
+    let key = shared_key(&private, &recipient)?;
+
+    let mut nonce = [0u8; NONCE_LEN];
+    rsgx_read_rand(&mut nonce).map_err(|status| format!("cannot generate a nonce: {}", status))?;
+    let mut ciphertext = vec![0; plaintext.len()];
+    let mut mac: sgx_aes_gcm_128bit_tag_t = [0; TAG_LEN];
+    rsgx_rijndael128GCM_encrypt(&key, plaintext, &nonce, context, &mut ciphertext, &mut mac)
+        .map_err(|status| format!("encryption failed: {}", status))?;
+
+    let mut envelope = encode_public_key(&public).to_vec();
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&ciphertext);
+    envelope.extend_from_slice(&mac);
+    Ok(envelope)
+}
+
+/// Checks that `encoded` is a SEC1 uncompressed point on P-256 that envelopes can be sealed to.
+pub fn check_public_key(encoded: &[u8]) -> Result<(), String> {
+    let public = decode_public_key(encoded).ok_or("key is not an uncompressed P-256 key")?;
+    let handle = SgxEccHandle::new();
+    handle.open().map_err(|status| format!("cannot open the ECC context: {}", status))?;
+    match handle.check_point(&public) {
+        Ok(true) => Ok(()),
+        _ => Err("key is not a point on P-256".to_string()),
+    }
+}
+
+/// Derives the AES key shared by `private` and `public`, rejecting points that are not on the curve.
+fn shared_key(
+    private: &sgx_ec256_private_t,
+    public: &sgx_ec256_public_t,
+) -> Result<sgx_aes_gcm_128bit_key_t, String> {
+    let handle = SgxEccHandle::new();
+    handle.open().map_err(|status| format!("cannot open the ECC context: {}", status))?;
+    if !matches!(handle.check_point(public), Ok(true)) {
+        return Err("public key is not a point on P-256".to_string());
+    }
+    let shared = handle
+        .compute_shared_dhkey(private, public)
+        .map_err(|status| format!("key agreement failed: {}", status))?;
+
+    let mut input = KDF_LABEL.to_vec();
+    input.extend(shared.s.iter().rev());
+    let digest = rsgx_sha256_slice(&input).map_err(|status| format!("key derivation failed: {}", status))?;
+    let mut key: sgx_aes_gcm_128bit_key_t = [0; 16];
+    key.copy_from_slice(&digest[..16]);
+    Ok(key)
+}
+
+/// Encodes a public key SEC1 uncompressed, converting its coordinates to big-endian.
+pub fn encode_public_key(public: &sgx_ec256_public_t) -> [u8; PUBLIC_KEY_LEN] {
+    let mut encoded = [0; PUBLIC_KEY_LEN];
+    encoded[0] = 0x04;
+    for (dst, src) in encoded[1..33].iter_mut().zip(public.gx.iter().rev()) {
+        *dst = *src;
+    }
+    for (dst, src) in encoded[33..].iter_mut().zip(public.gy.iter().rev()) {
+        *dst = *src;
+    }
+    encoded
+}
+
+fn decode_public_key(encoded: &[u8]) -> Option<sgx_ec256_public_t> {
+    if encoded.len() != PUBLIC_KEY_LEN || encoded[0] != 0x04 {
+        return None;
+    }
+    let mut public = sgx_ec256_public_t::default();
+    for (dst, src) in public.gx.iter_mut().zip(encoded[1..33].iter().rev()) {
+        *dst = *src;
+    }
+    for (dst, src) in public.gy.iter_mut().zip(encoded[33..].iter().rev()) {
+        *dst = *src;
+    }
+    Some(public)
+}
@@ -0,0 +1,90 @@
+//! Jobs submitted to `exec_encrypted` inside an envelope, so that the host relays the module, data, schema and
+//! data-owner policy without seeing them.
+//!
+//! The envelope's plaintext is laid out as follows, with little-endian lengths:
+//!
+//! ```text
+//! magic          b"NTCJ"
+//! version        u8, currently 1
+//! reply key      65 bytes: the P-256 key the result and log are encrypted to, SEC1 uncompressed
+//! data_format    u8, as for `exec_wasm`
+//! csv_delimiter  u8
+//! csv_quote      u8
+//! binary         u32 length, then the WASM binary
+//! data           u32 length, then the data
+//! schema         u32 length, then the schema
+//! policy         u32 length, then the data-owner policy (see `policy.rs`)
+//! ```
+
+use envelope::PUBLIC_KEY_LEN;
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+/// First bytes of every job request.
+const MAGIC: &[u8] = b"NTCJ";
+
+const VERSION: u8 = 1;
+
+/// A decoded job request.
+pub struct JobRequest {
+    pub reply_key: Vec<u8>,
+    pub data_format: u32,
+    pub csv_delimiter: u8,
+    pub csv_quote: u8,
+    pub binary: Vec<u8>,
+    pub data: Vec<u8>,
+    pub schema: Vec<u8>,
+    /// Empty if the request has no policy.
+    pub policy: Vec<u8>,
+}
+
+impl JobRequest {
+    /// Decodes the plaintext of a job envelope.
+    pub fn decode(plaintext: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { input: plaintext };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not a job request".to_string());
+        }
+        if reader.take(1)?[0] != VERSION {
+            return Err("unsupported job request version".to_string());
+        }
+        let reply_key = reader.take(PUBLIC_KEY_LEN)?.to_vec();
+        let format = reader.take(3)?;
+        let request = JobRequest {
+            reply_key,
+            data_format: u32::from(format[0]),
+            csv_delimiter: format[1],
+            csv_quote: format[2],
+            binary: reader.field()?,
+            data: reader.field()?,
+            schema: reader.field()?,
+            policy: reader.field()?,
+        };
+        if !reader.input.is_empty() {
+            return Err("unexpected bytes after the job request".to_string());
+        }
+        Ok(request)
+    }
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.input.len() < len {
+            return Err("job request is truncated".to_string());
+        }
+        let (taken, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(taken)
+    }
+
+    /// Reads a length-prefixed field.
+    fn field(&mut self) -> Result<Vec<u8>, String> {
+        let mut len = [0u8; 4];
+        len.copy_from_slice(self.take(4)?);
+        Ok(self.take(u32::from_le_bytes(len) as usize)?.to_vec())
+    }
+}
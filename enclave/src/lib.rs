@@ -14,104 +14,1083 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
 extern crate sgx_types;
+extern crate sgx_tcrypto;
+extern crate sgx_trts;
+extern crate sgx_tseal;
+extern crate sgx_tse;
 #[cfg(not(target_env = "sgx"))]
 #[macro_use]
 extern crate sgx_tstd as std;
 extern crate wasmi;
 extern crate wasmi_impl;
 extern crate serde_json;
+extern crate ntc_receipt;
+#[macro_use]
+extern crate lazy_static;
+
+mod envelope;
+mod job_request;
+mod module_cache;
+mod policy;
+mod receipts;
+mod sealing;
 
 use sgx_types::*;
-use std::io::{self, Write};
+use sgx_tcrypto::rsgx_sha256_slice;
+use sgx_trts::trts::rsgx_read_rand;
+use std::borrow::Cow;
 use std::slice;
+use std::string::{String, ToString};
+use std::sync::{Arc, SgxMutex, SgxMutexGuard};
 use std::vec::Vec;
-use core::convert::TryInto;
 use serde_json::{json, Value};
+use wasmi::RuntimeValue;
+use wasmi_impl::csv::CsvOptions;
+use wasmi_impl::{ExecLimits, ExecWasmError, InputFormat, PreparedModule};
+use job_request::JobRequest;
+use module_cache::{ModuleCache, ModuleHash};
+use ntc_receipt::Receipt;
+use policy::DatasetPolicy;
+use sealing::SealPolicy;
+
+/// Upper bound on the linear memory of a single guest (1 GiB), well inside the enclave's `HeapMaxSize`.
+const MAX_GUEST_MEMORY_PAGES: u32 = 16384;
+
+/// Fuel granted to a job when the host does not request a specific budget.
+const DEFAULT_FUEL_LIMIT: u64 = 10_000_000_000;
+
+/// `data_format` marking job data as a sealed dataset returned by `import_dataset`.
+const SEALED_DATASET: u32 = 3;
+
+/// Total size of the module binaries whose prepared modules are kept (64 MiB).
+const MODULE_CACHE_CAPACITY: usize = 64 * 1024 * 1024;
+
+lazy_static! {
+    static ref MODULE_CACHE: SgxMutex<ModuleCache> = SgxMutex::new(ModuleCache::new(MODULE_CACHE_CAPACITY));
+}
+
+/// Limits applied to a module executed by this enclave. A `fuel_limit` of 0 selects `DEFAULT_FUEL_LIMIT`.
+fn exec_limits(fuel_limit: u64) -> ExecLimits {
+    let fuel = if fuel_limit == 0 { DEFAULT_FUEL_LIMIT } else { fuel_limit };
+    ExecLimits {
+        max_memory_pages: Some(MAX_GUEST_MEMORY_PAGES),
+        fuel: Some(fuel),
+        ..ExecLimits::default()
+    }
+}
+
+/// Encoding of the job data, selected by the `data_format` argument of `exec_wasm` and `exec_module`.
+#[derive(Clone, Copy)]
+enum DataFormat {
+    /// Column arrays as JSON.
+    Json,
+    /// CSV with a header row, converted into JSON column arrays typed by the schema.
+    Csv(CsvOptions),
+    /// The binary columnar encoding of `ntc_guest::columnar`.
+    Columnar,
+}
+
+impl DataFormat {
+    /// `data_format` is 0 for JSON, 1 for CSV or 2 for columnar data; the CSV delimiter and quote are ignored
+    /// for the others.
+    fn from_ecall(data_format: u32, csv_delimiter: u8, csv_quote: u8) -> Result<Self, String> {
+        match data_format {
+            0 => Ok(DataFormat::Json),
+            1 => CsvOptions::new(csv_delimiter, csv_quote).map(DataFormat::Csv),
+            2 => Ok(DataFormat::Columnar),
+            other => Err(format!("unsupported data format {}", other)),
+        }
+    }
+}
+
+/// Outcome of a job, reported to the host through the `exec_error` out-parameter of `exec_wasm`.
+///
+/// The host mirrors these values, so existing codes must not be renumbered.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ExecErrorCode {
+    Success = 0,
+    /// The binary could not be decoded, validated or instrumented.
+    InvalidModule = 1,
+    /// The module imports something the enclave does not provide.
+    UnresolvedImport = 2,
+    /// The module's entry point or allocator export is missing or has the wrong signature.
+    InvalidExport = 3,
+    /// The module trapped.
+    Trap = 4,
+    /// The module exhausted its fuel budget.
+    OutOfFuel = 5,
+    /// The job needs more linear memory than allowed.
+    MemoryLimitExceeded = 6,
+    /// The module handed out a buffer outside its linear memory, or failed to allocate one.
+    MemoryAccess = 7,
+    /// The module's output overruns its output region or is not valid JSON.
+    OutputDecoding = 8,
+    /// Any other runtime failure.
+    Internal = 9,
+    /// The module called `env.abort`.
+    Aborted = 10,
+    /// The module called WASI `proc_exit` with a non-zero code.
+    Exited = 11,
+    /// The module's start function failed.
+    StartFailed = 12,
+    /// `exec_module` was given the hash of a module that is not in the cache.
+    ModuleNotFound = 13,
+    /// The input data cannot be decoded in its format or does not match its schema.
+    InvalidInput = 14,
+    /// The module's result does not match the output schema it declares.
+    OutputRejected = 15,
+    /// An encrypted job request cannot be opened or decoded. Unlike other failures of encrypted jobs, it is
+    /// reported in plaintext.
+    InvalidRequest = 16,
+    /// The job breaks the policy its dataset's owner set: the module is not allowed to run on the dataset, the
+    /// dataset has too few rows, or the result is larger than allowed.
+    PolicyViolation = 17,
+}
+
+impl From<&ExecWasmError> for ExecErrorCode {
+    fn from(err: &ExecWasmError) -> Self {
+        match err {
+            ExecWasmError::ModuleDecode(_)
+            | ExecWasmError::ModuleValidation(_)
+            | ExecWasmError::InvalidAbiDeclaration(_)
+            | ExecWasmError::InvalidOutputSchema(_)
+            | ExecWasmError::UnsupportedAbiVersion { .. }
+            | ExecWasmError::InstrumentationFailed => ExecErrorCode::InvalidModule,
+            ExecWasmError::UnresolvedImport { .. } => ExecErrorCode::UnresolvedImport,
+            ExecWasmError::MissingExport { .. }
+            | ExecWasmError::InvalidExportSignature { .. }
+            | ExecWasmError::UnsupportedEntrypoint { .. }
+            | ExecWasmError::AbiMismatch { .. } => ExecErrorCode::InvalidExport,
+            ExecWasmError::Trap(_) | ExecWasmError::HostCallTooLarge { .. } => ExecErrorCode::Trap,
+            ExecWasmError::Aborted { .. } => ExecErrorCode::Aborted,
+            ExecWasmError::Exited { .. } => ExecErrorCode::Exited,
+            ExecWasmError::StartFailed(_) => ExecErrorCode::StartFailed,
+            ExecWasmError::OutOfFuel { .. } => ExecErrorCode::OutOfFuel,
+            ExecWasmError::MemoryLimitExceeded { .. } => ExecErrorCode::MemoryLimitExceeded,
+            ExecWasmError::MemoryOutOfBounds { .. } | ExecWasmError::GuestAllocationFailed { .. } => {
+                ExecErrorCode::MemoryAccess
+            }
+            ExecWasmError::OutputTooLarge { .. } => ExecErrorCode::OutputDecoding,
+            ExecWasmError::WasmiError(_) => ExecErrorCode::Internal,
+        }
+    }
+}
+
+/// A finished job, ready to be reported to the host.
+struct JobReport {
+    code: ExecErrorCode,
+    /// The serialized JSON result, or an error message if the job failed.
+    payload: Vec<u8>,
+    log: Vec<u8>,
+    fuel_used: u64,
+    /// What the job ran on, if it got as far as the module and its data; only such jobs get a receipt.
+    subject: Option<JobSubject>,
+}
+
+impl JobReport {
+    /// Reports a failed job with `message` in place of its result, and no log. Jobs that fail after their module
+    /// ran fill in the fuel used and subject themselves.
+    fn failure(code: ExecErrorCode, message: String) -> Self {
+        eprintln!("WASM execution error: {}", message);
+        Self {
+            code,
+            payload: message.into_bytes(),
+            log: Vec::new(),
+            fuel_used: 0,
+            subject: None,
+        }
+    }
+
+    /// The job's signed receipt, committing to its output as it is delivered to the host and stamped with the
+    /// host's `timestamp`, or nothing if the job has no subject.
+    fn signed_receipt(&self, timestamp: u64) -> Result<Vec<u8>, String> {
+        let subject = match &self.subject {
+            Some(subject) => subject,
+            None => return Ok(Vec::new()),
+        };
+        let output_sha256 =
+            rsgx_sha256_slice(&self.payload).map_err(|status| format!("cannot hash output: {}", status))?;
+        receipts::sign(Receipt {
+            sequence: 0,
+            timestamp,
+            exec_error: self.code as u32,
+            module_sha256: subject.module_sha256,
+            data_sha256: subject.data_sha256,
+            schema_sha256: subject.schema_sha256,
+            output_sha256,
+            limits: subject.limits,
+            fuel_used: self.fuel_used,
+        })
+    }
+}
+
+/// The SHA-256 of a job's module, data and schema, and the limits it ran under, recorded in its receipt.
+#[derive(Clone, Copy)]
+struct JobSubject {
+    module_sha256: ModuleHash,
+    data_sha256: [u8; 32],
+    schema_sha256: [u8; 32],
+    limits: ntc_receipt::Limits,
+}
+
+/// Host buffers through which an ECALL reports a job. See `exec_wasm` for their meaning.
+struct JobOutputs {
+    result_out: *mut u8,
+    result_capacity: usize,
+    result_len: *mut usize,
+    log_out: *mut u8,
+    log_capacity: usize,
+    log_len: *mut usize,
+    receipt_out: *mut u8,
+    receipt_capacity: usize,
+    receipt_len: *mut usize,
+    fuel_used: *mut u64,
+    exec_error: *mut u32,
+}
+
+impl JobOutputs {
+    fn has_null_pointer(&self) -> bool {
+        self.result_out.is_null()
+            || self.result_len.is_null()
+            || self.log_out.is_null()
+            || self.log_len.is_null()
+            || self.receipt_out.is_null()
+            || self.receipt_len.is_null()
+            || self.fuel_used.is_null()
+            || self.exec_error.is_null()
+    }
+
+    /// Writes `report` and its signed receipt, stamped with the host's `timestamp`, to the host's buffers. A
+    /// receipt that does not fit is left out, with `receipt_len` set to its size. A report whose result does not
+    /// fit is not signed at all, so that the host retrying with a larger buffer does not use up a receipt.
+    ///
+    /// # Safety
+    ///
+    /// Every pointer must be valid as described for `exec_wasm`.
+    unsafe fn write(&self, report: &JobReport, timestamp: u64) -> sgx_status_t {
+        if report.payload.len() > self.result_capacity {
+            return unsafe { write_result(&report.payload, self.result_out, self.result_capacity, self.result_len) };
+        }
+        let receipt = match report.signed_receipt(timestamp) {
+            Ok(receipt) => receipt,
+            Err(message) => {
+                eprintln!("Failed to sign the job receipt: {}", message);
+                return sgx_status_t::SGX_ERROR_UNEXPECTED;
+            }
+        };
+        let log = &report.log[..report.log.len().min(self.log_capacity)];
+        unsafe {
+            slice::from_raw_parts_mut(self.log_out, log.len()).copy_from_slice(log);
+            *self.log_len = log.len();
+            *self.receipt_len = receipt.len();
+            if receipt.len() <= self.receipt_capacity {
+                slice::from_raw_parts_mut(self.receipt_out, receipt.len()).copy_from_slice(&receipt);
+            }
+            *self.fuel_used = report.fuel_used;
+            *self.exec_error = report.code as u32;
+            write_result(&report.payload, self.result_out, self.result_capacity, self.result_len)
+        }
+    }
+}
+
+/// Resolves the job data and its format from the `data_format` ECALL arguments, unsealing a sealed dataset, and
+/// the policy it is used under: the submitted `policy`, combined with the one sealed with a sealed dataset.
+fn job_data<'a>(
+    data: &'a [u8],
+    data_format: u32,
+    csv_delimiter: u8,
+    csv_quote: u8,
+    policy: &[u8],
+) -> Result<(Cow<'a, [u8]>, DataFormat, DatasetPolicy), String> {
+    let policy = DatasetPolicy::parse(policy)?;
+    if data_format != SEALED_DATASET {
+        let format = DataFormat::from_ecall(data_format, csv_delimiter, csv_quote)?;
+        return Ok((Cow::Borrowed(data), format, policy));
+    }
+    let (data, (data_format, csv_delimiter, csv_quote), sealed_policy) = sealing::unseal_dataset(data)?;
+    let format = DataFormat::from_ecall(data_format, csv_delimiter, csv_quote)?;
+    Ok((Cow::Owned(data), format, policy.combine(DatasetPolicy::parse(&sealed_policy)?.sealed())))
+}
+
+/// Prepares (or finds in the cache) `binary` and runs it on `data`, given in the encoding selected by the
+/// `data_format` ECALL arguments.
+fn run_binary_job(
+    binary: &[u8],
+    data: &[u8],
+    data_format: u32,
+    csv_delimiter: u8,
+    csv_quote: u8,
+    schema: &[u8],
+    policy: &[u8],
+    fuel_limit: u64,
+) -> Result<JobReport, sgx_status_t> {
+    let hash = module_hash(binary)?;
+    let input = job_data(data, data_format, csv_delimiter, csv_quote, policy);
+    Ok(match (cached_module(hash, binary), input) {
+        (Ok(module), Ok((data, format, policy))) => {
+            run_job(hash, &module, &data, format, schema, &policy, fuel_limit)?
+        }
+        (Err(e), _) => JobReport::failure(ExecErrorCode::from(&e), e.to_string()),
+        (_, Err(message)) => JobReport::failure(ExecErrorCode::InvalidInput, message),
+    })
+}
+
+/// Decodes `data`, validates it against `schema` and runs a prepared module on them, if the dataset's `policy`
+/// allows it.
+///
+/// Returns an error only if the job could not be set up; failures of the module itself are part of the report.
+fn run_job(
+    module_hash: ModuleHash,
+    module: &PreparedModule,
+    data: &[u8],
+    format: DataFormat,
+    schema: &[u8],
+    policy: &DatasetPolicy,
+    fuel_limit: u64,
+) -> Result<JobReport, sgx_status_t> {
+    // The receipt commits to the data as submitted, before it is converted for the guest.
+    let mut limits = exec_limits(fuel_limit);
+    let subject = Some(JobSubject {
+        module_sha256: module_hash,
+        data_sha256: rsgx_sha256_slice(data)?,
+        schema_sha256: rsgx_sha256_slice(schema)?,
+        limits: ntc_receipt::Limits {
+            fuel: limits.fuel.unwrap_or(0),
+            memory_pages: limits.max_memory_pages.unwrap_or(0),
+            output_bytes: limits.max_output_bytes as u32,
+            log_bytes: limits.max_log_bytes as u32,
+        },
+    });
+
+    // The module is not instantiated unless the dataset's owner allows it to run on the dataset.
+    let refuse = |code, message| Ok(JobReport { subject, ..JobReport::failure(code, message) });
+    if let Err(message) = policy.check_module(&module_hash) {
+        return refuse(ExecErrorCode::PolicyViolation, message);
+    }
+
+    // Reject malformed input before the guest sees it; guests may assume the data matches the schema.
+    let data = match guest_input(module.abi().input, data, format, schema, policy) {
+        Ok(data) => data,
+        Err((code, message)) => return refuse(code, message),
+    };
+
+    // Seed the module's RNG from the enclave so that the host can neither choose nor predict it.
+    if let Err(status) = rsgx_read_rand(&mut limits.rng_seed) {
+        eprintln!("Failed to seed the module RNG: {}", status);
+        return Err(status);
+    }
+
+    // Execute the module with input data and schema; its output comes back exactly as long as it reported.
+    let mut log = Vec::new();
+    let mut fuel_used = 0;
+    let response = match wasmi_impl::exec_prepared_module(module, &data, schema, &limits) {
+        Ok(outcome) => {
+            fuel_used = outcome.fuel_used;
+            // The log is not checked against the output schema or the policy, so it is never released for a module
+            // that declares an output schema or for a dataset that is sealed or has a policy.
+            if module.output_schema().is_none() && !policy.withholds_log() {
+                log = outcome.log;
+                log.extend_from_slice(&outcome.stdout);
+                log.extend_from_slice(&outcome.stderr);
+                if outcome.log_truncated {
+                    log.extend_from_slice(b"[log truncated]\n");
+                }
+            }
+
+            // Guests returning a scalar have their value reported directly; otherwise attempt to deserialize
+            // their output to JSON and re-serialize it compactly for the host.
+            let output = match outcome.return_value {
+                Some(value) => Ok(scalar_to_json(value)),
+                None => serde_json::from_slice(&outcome.output),
+            };
+            output
+                .map_err(|e| (ExecErrorCode::OutputDecoding, format!("failed to decode module output: {}", e)))
+                .and_then(|json_data| {
+                    check_output(module, &json_data)?;
+                    let result = serde_json::to_vec(&json_data).map_err(|e| {
+                        (ExecErrorCode::OutputDecoding, format!("failed to encode module output: {}", e))
+                    })?;
+                    policy.check_output(&result).map_err(|message| (ExecErrorCode::PolicyViolation, message))?;
+                    Ok(result)
+                })
+        },
+        Err(e) => {
+            // The start function may be what ran out of fuel.
+            let cause = match &e {
+                ExecWasmError::StartFailed(cause) => cause.as_ref(),
+                other => other,
+            };
+            if let ExecWasmError::OutOfFuel { limit } = cause {
+                fuel_used = *limit;
+            }
+            Err((ExecErrorCode::from(&e), e.to_string()))
+        }
+    };
+
+    // On failure the result buffer carries a human-readable error message instead of JSON.
+    Ok(match response {
+        Ok(payload) => JobReport { code: ExecErrorCode::Success, payload, log, fuel_used, subject },
+        // A failed job's log could hold the result it was not allowed to release, so it is dropped.
+        Err((code, message)) => JobReport { fuel_used, subject, ..JobReport::failure(code, message) },
+    })
+}
+
+/// Decodes `data`, checks it against `schema` and the row count `policy` requires, and encodes it as the guest's
+/// declared `input` requires. Data already in that encoding is passed on as it is.
+fn guest_input<'a>(
+    input: InputFormat,
+    data: &'a [u8],
+    format: DataFormat,
+    schema: &[u8],
+    policy: &DatasetPolicy,
+) -> Result<Cow<'a, [u8]>, (ExecErrorCode, String)> {
+    let value = decode_input(data, format, schema, policy.is_sealed())
+        .map_err(|message| (ExecErrorCode::InvalidInput, message))?;
+    policy.check_rows(&value).map_err(|message| (ExecErrorCode::PolicyViolation, message))?;
+
+    match (input, format) {
+        (InputFormat::Json, DataFormat::Json) | (InputFormat::Columnar, DataFormat::Columnar) => {
+            Ok(Cow::Borrowed(data))
+        }
+        (InputFormat::Json, _) => serde_json::to_vec(&value)
+            .map(Cow::Owned)
+            .map_err(|e| format!("failed to encode data as JSON: {}", e)),
+        (InputFormat::Columnar, _) => wasmi_impl::columnar::from_json(&value)
+            .map(Cow::Owned)
+            .map_err(|e| format!("the module takes columnar data, but it cannot be encoded as such:\n{}", e)),
+    }
+    .map_err(|message| (ExecErrorCode::InvalidInput, message))
+}
+
+/// Decodes `data` as JSON and checks it against `schema`. Errors about a `sealed` dataset do not quote its
+/// values; schema violations are reported by JSON pointer and keyword only.
+fn decode_input(data: &[u8], format: DataFormat, schema: &[u8], sealed: bool) -> Result<Value, String> {
+    let schema: Value = serde_json::from_slice(schema).map_err(|e| format!("schema is not valid JSON: {}", e))?;
+    let value = match format {
+        DataFormat::Json => serde_json::from_slice(data).map_err(|e| format!("data is not valid JSON: {}", e))?,
+        DataFormat::Csv(options) => wasmi_impl::csv::to_columns(data, &schema, &options).map_err(|errors| {
+            let message: Vec<String> =
+                errors.iter().map(|error| if sealed { error.redacted() } else { error.to_string() }).collect();
+            format!("data is not valid CSV:\n{}", message.join("\n"))
+        })?,
+        DataFormat::Columnar => wasmi_impl::columnar::to_json(data)
+            .map_err(|e| format!("data is not a valid columnar dataset: {}", e))?,
+    };
+    if let Err(errors) = wasmi_impl::schema::validate(&value, &schema) {
+        let message: Vec<String> =
+            errors.iter().map(|error| if sealed { error.redacted() } else { error.to_string() }).collect();
+        return Err(format!("input does not match its schema:\n{}", message.join("\n")));
+    }
+    Ok(value)
+}
+
+/// Withholds a result that does not match the module's declared output schema, so that a module cannot
+/// release more than it promises (raw rows, for instance). Results of modules without a schema are released as is.
+fn check_output(module: &PreparedModule, result: &Value) -> Result<(), (ExecErrorCode, String)> {
+    let schema = match module.output_schema() {
+        Some(schema) => schema,
+        None => return Ok(()),
+    };
+    wasmi_impl::schema::validate(result, schema).map_err(|errors| {
+        // Only the violations are reported; the rejected result itself never leaves the enclave.
+        let message: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        let message = format!("output does not match the module's output schema:\n{}", message.join("\n"));
+        (ExecErrorCode::OutputRejected, message)
+    })
+}
+
+/// Computes the SHA-256 of a module binary, which identifies it in the module cache.
+fn module_hash(binary: &[u8]) -> Result<ModuleHash, sgx_status_t> {
+    rsgx_sha256_slice(binary).map_err(|status| {
+        eprintln!("Failed to hash module: {}", status);
+        status
+    })
+}
+
+/// Returns the prepared module for `binary`, preparing and caching it if it is not cached yet.
+fn cached_module(hash: ModuleHash, binary: &[u8]) -> Result<Arc<PreparedModule>, ExecWasmError> {
+    if let Some(module) = module_cache().get(&hash) {
+        return Ok(module);
+    }
+    // Prepare outside the lock so that other jobs are not held up.
+    let module = Arc::new(wasmi_impl::prepare_module(binary)?);
+    module_cache().insert(hash, module.clone(), binary.len());
+    Ok(module)
+}
+
+/// The `len` bytes at `ptr`, or none if `ptr` is null, as the bridge passes it for an empty buffer.
+///
+/// # Safety
+///
+/// `ptr` must be null or valid for `len` bytes.
+unsafe fn optional_slice<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if ptr.is_null() {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(ptr, len) }
+    }
+}
+
+fn module_cache() -> SgxMutexGuard<'static, ModuleCache> {
+    // The cache stays consistent even if a job panicked while holding the lock.
+    MODULE_CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
 /// Executes a WebAssembly (WASM) binary within an SGX enclave, processing JSON data according to a given schema.
+///
+/// The binary is prepared once and cached by its SHA-256, so resubmitting it skips decoding and validation;
+/// `register_module` and `exec_module` also avoid copying it into the enclave again.
 /// 
 /// # Safety
 ///
 /// This function is marked unsafe because it interacts with raw pointers. The caller must ensure that:
-/// - `data_in`, `schema_in`, and `binary` are valid pointers for `data_len`, `schema_len`, and `binary_len` bytes, respectively.
-/// - `result_out` points to a sufficiently allocated memory space to store the output.
+/// - `data_in`, `schema_in`, `binary` and `policy_in` are null or valid for `data_len`, `schema_len`, `binary_len`
+///   and `policy_len` bytes, respectively; the bridge passes null for an empty buffer.
+/// - `result_out`, `log_out` and `receipt_out` are valid for writes of `result_capacity`, `log_capacity` and
+///   `receipt_capacity` bytes, and `result_len`, `log_len`, `receipt_len`, `fuel_used` and `exec_error` are valid
+///   for a single write.
 /// 
 /// # Arguments
 ///
 /// * `data_in`: Pointer to the input data bytes.
 /// * `data_len`: Length of the input data.
+/// * `data_format`: Encoding of the input data: 0 for JSON, 1 for CSV with a header row, 2 for the binary
+///   columnar encoding, or 3 for a sealed dataset returned by `import_dataset`, which is unsealed inside the
+///   enclave and carries its own encoding. CSV is typed by the schema, and the data is converted to the
+///   encoding the module declares as its input before the module sees it.
+/// * `csv_delimiter`: Field delimiter of CSV data, e.g. `b','`.
+/// * `csv_quote`: Quote character of CSV data, e.g. `b'"'`.
 /// * `schema_in`: Pointer to the input schema bytes.
 /// * `schema_len`: Length of the input schema.
+/// * `policy_in`: Pointer to a data-owner policy for the data, as JSON (see `policy.rs`): the modules allowed to
+///   run on it, the largest result they may release and the fewest rows it may be used with. A sealed dataset's
+///   own policy applies as well. Jobs breaking either fail with `ExecErrorCode::PolicyViolation`.
+/// * `policy_len`: Length of the policy; 0 for none.
 /// * `binary`: Pointer to the WASM binary.
 /// * `binary_len`: Length of the WASM binary.
+/// * `fuel_limit`: Fuel the module may consume (roughly one unit per instruction), or 0 for the enclave default.
+/// * `timestamp`: The host's time, in seconds since the Unix epoch, recorded as is in the job's receipt.
+/// * `result_out`: Buffer that receives the serialized JSON result, or an error message if the job failed.
+/// * `result_capacity`: Size of the `result_out` buffer.
+/// * `result_len`: Receives the length of the serialized JSON result or error message.
+/// * `log_out`: Buffer that receives the messages the module passed to `env.log`, one per line, followed by
+///   anything it wrote to WASI stdout and stderr; output beyond `log_capacity` is dropped. Only successful jobs
+///   report a log, and not even those if the module declares an output schema or the dataset is sealed or has a
+///   policy, since the log is checked against neither.
+/// * `log_capacity`: Size of the `log_out` buffer.
+/// * `log_len`: Receives the number of bytes written to `log_out`.
+/// * `receipt_out`: Buffer that receives the job's execution receipt, numbered and signed by the enclave, as
+///   `ntc_receipt` describes. Jobs that fail before their module and data are resolved get none.
+/// * `receipt_capacity`: Size of the `receipt_out` buffer; `ntc_receipt::SIGNED_RECEIPT_LEN` is enough.
+/// * `receipt_len`: Receives the length of the receipt, or 0 if there is none. If it exceeds `receipt_capacity`,
+///   the receipt is left out.
+/// * `fuel_used`: Receives the fuel consumed by the module. Set to the limit if the module ran out of fuel.
+/// * `exec_error`: Receives an `ExecErrorCode`; 0 means the job succeeded.
 ///
 /// # Returns
 ///
-/// Returns an `sgx_status_t` indicating the success or failure of the operation.
+/// Returns an `sgx_status_t` indicating whether the request was handled; a failing job is still
+/// `SGX_SUCCESS`, with the reason reported through `exec_error` and `result_out`.
+/// If `result_out` is too small to hold the result, `SGX_ERROR_INVALID_PARAMETER` is returned and
+/// `result_len` is set to the number of bytes needed, so the caller can retry with a larger buffer.
 #[no_mangle]
 pub unsafe extern "C" fn exec_wasm(
     data_in: *const u8,
     data_len: usize,
+    data_format: u32,
+    csv_delimiter: u8,
+    csv_quote: u8,
     schema_in: *const u8,
     schema_len: usize,
+    policy_in: *const u8,
+    policy_len: usize,
     binary: *const u8,
     binary_len: usize,
+    fuel_limit: u64,
+    timestamp: u64,
+    result_out: *mut u8,
+    result_capacity: usize,
+    result_len: *mut usize,
+    log_out: *mut u8,
+    log_capacity: usize,
+    log_len: *mut usize,
+    receipt_out: *mut u8,
+    receipt_capacity: usize,
+    receipt_len: *mut usize,
+    fuel_used: *mut u64,
+    exec_error: *mut u32,
 ) -> sgx_status_t {
+    let outputs = JobOutputs {
+        result_out,
+        result_capacity,
+        result_len,
+        log_out,
+        log_capacity,
+        log_len,
+        receipt_out,
+        receipt_capacity,
+        receipt_len,
+        fuel_used,
+        exec_error,
+    };
+
     // Validate input parameters.
-    if binary.is_null() {
-        eprintln!("Binary pointer is null.");
+    if outputs.has_null_pointer() {
+        eprintln!("Result pointer is null.");
         return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
     }
 
-    // Initialize a buffer to store the result of WASM execution.
-    let mut result_buffer: Vec<u8> = vec![0; 4096];
-
     // Convert raw pointers to slices for safe access.
-    let binary_slice = unsafe { slice::from_raw_parts(binary, binary_len)};
-    let data = unsafe { slice::from_raw_parts(data_in, data_len)};
-    let schema = unsafe { slice::from_raw_parts(schema_in, schema_len)};
-
-    // Execute the WASM binary with input data and schema, storing the output in result_buffer.
-    match wasmi_impl::exec_wasm_with_data_and_schema(
-        binary_slice,
-        data.as_ptr(),
-        data.len(),
-        schema.as_ptr(),
-        schema.len(),
-        result_buffer.as_mut_ptr(),
-        result_buffer.len(),
-    ) {
-        Ok(_) => {
-            // Attempt to deserialize the output buffer to JSON.
-            match trim_and_deserialize_output(&result_buffer) {
-                Ok(json_data) => {
-                    println!("\nEnclave Output:\n Deserialized JSON: {:?}\n", json_data);
-                    sgx_status_t::SGX_SUCCESS
-                },
-                Err(e) => {
-                    eprintln!("Failed to deserialize JSON: {:?}", e);
-                    sgx_status_t::SGX_ERROR_UNEXPECTED // Indicates deserialization failure.
-                }
+    let binary_slice = unsafe { optional_slice(binary, binary_len) };
+    let data = unsafe { optional_slice(data_in, data_len) };
+    let schema = unsafe { optional_slice(schema_in, schema_len) };
+    let policy = unsafe { optional_slice(policy_in, policy_len) };
+
+    let report = run_binary_job(binary_slice, data, data_format, csv_delimiter, csv_quote, schema, policy, fuel_limit);
+    let report = match report {
+        Ok(report) => report,
+        Err(status) => return status,
+    };
+
+    // Safe because the caller guarantees the output pointers are valid.
+    unsafe { outputs.write(&report, timestamp) }
+}
+
+/// Executes a job sent encrypted to the enclave's public key (see `enclave_public_key`), so that the host only
+/// relays ciphertext. The request holds the binary, data, schema and data format as laid out in `job_request`,
+/// along with the key of the requester, to which the result (or error message) and log are encrypted.
+///
+/// The host still learns the job's `exec_error` code and fuel used, and the sizes of its envelopes. A request
+/// that cannot be opened or decoded is reported in plaintext with `ExecErrorCode::InvalidRequest`, since there
+/// is no key to encrypt the report to.
+///
+/// # Safety
+///
+/// `request_in` must be null or valid for `request_len` bytes; the other pointers are as described for `exec_wasm`.
+///
+/// # Arguments
+///
+/// * `request_in`: Pointer to the envelope holding the job request.
+/// * `request_len`: Length of the envelope.
+/// * `fuel_limit`: Fuel the module may consume, or 0 for the enclave default.
+/// * `timestamp`: The host's time, recorded in the job's receipt.
+/// * The remaining arguments are as for `exec_wasm`, except that `result_out` receives an envelope for the
+///   requester's key with context `ntc-result`, and `log_out` one with context `ntc-log`. The log is cut short
+///   to fit its envelope into `log_capacity`; an empty log, or one with no room for an envelope, is left empty.
+#[no_mangle]
+pub unsafe extern "C" fn exec_encrypted(
+    request_in: *const u8,
+    request_len: usize,
+    fuel_limit: u64,
+    timestamp: u64,
+    result_out: *mut u8,
+    result_capacity: usize,
+    result_len: *mut usize,
+    log_out: *mut u8,
+    log_capacity: usize,
+    log_len: *mut usize,
+    receipt_out: *mut u8,
+    receipt_capacity: usize,
+    receipt_len: *mut usize,
+    fuel_used: *mut u64,
+    exec_error: *mut u32,
+) -> sgx_status_t {
+    let outputs = JobOutputs {
+        result_out,
+        result_capacity,
+        result_len,
+        log_out,
+        log_capacity,
+        log_len,
+        receipt_out,
+        receipt_capacity,
+        receipt_len,
+        fuel_used,
+        exec_error,
+    };
+    if outputs.has_null_pointer() {
+        eprintln!("Result pointer is null.");
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    let request_bytes = unsafe { optional_slice(request_in, request_len) };
+
+    let request = envelope::open(request_bytes, envelope::JOB_CONTEXT)
+        .and_then(|plaintext| JobRequest::decode(&plaintext))
+        .and_then(|request| {
+            envelope::check_public_key(&request.reply_key).map_err(|e| format!("invalid reply key: {}", e))?;
+            Ok(request)
+        });
+    let request = match request {
+        Ok(request) => request,
+        // Safe because the caller guarantees the output pointers are valid.
+        Err(message) => {
+            return unsafe { outputs.write(&JobReport::failure(ExecErrorCode::InvalidRequest, message), timestamp) }
+        }
+    };
+
+    let report = run_binary_job(
+        &request.binary,
+        &request.data,
+        request.data_format,
+        request.csv_delimiter,
+        request.csv_quote,
+        &request.schema,
+        &request.policy,
+        fuel_limit,
+    );
+    let mut report = match report {
+        Ok(report) => report,
+        Err(status) => return status,
+    };
+
+    let log_room = log_capacity.saturating_sub(envelope::OVERHEAD);
+    report.log.truncate(log_room);
+    let encrypted = envelope::seal(&request.reply_key, &report.payload, envelope::RESULT_CONTEXT).and_then(|payload| {
+        let log = if report.log.is_empty() {
+            Vec::new()
+        } else {
+            envelope::seal(&request.reply_key, &report.log, envelope::LOG_CONTEXT)?
+        };
+        Ok(JobReport { payload, log, ..report })
+    });
+    let report = match encrypted {
+        Ok(report) => report,
+        Err(message) => JobReport::failure(ExecErrorCode::Internal, format!("cannot encrypt the report: {}", message)),
+    };
+
+    // Safe because the caller guarantees the output pointers are valid.
+    unsafe { outputs.write(&report, timestamp) }
+}
+
+/// Prepares a module and caches it so that jobs can run it by hash through `exec_module`.
+///
+/// # Safety
+///
+/// `binary` must be null or valid for `binary_len` bytes, `module_hash_out` valid for writes of 32 bytes,
+/// `error_out` for writes of `error_capacity` bytes, and `error_len` and `exec_error` for a single write.
+///
+/// # Arguments
+///
+/// * `binary`: Pointer to the WASM binary.
+/// * `binary_len`: Length of the WASM binary.
+/// * `module_hash_out`: Receives the SHA-256 of the binary, which identifies the module in `exec_module`.
+/// * `error_out`: Buffer that receives an error message if the module is rejected.
+/// * `error_capacity`: Size of the `error_out` buffer.
+/// * `error_len`: Receives the length of the error message, or 0 if the module was accepted.
+/// * `exec_error`: Receives an `ExecErrorCode` describing why the module was rejected; 0 means it was accepted.
+///
+/// # Returns
+///
+/// Returns `SGX_SUCCESS` once the request was handled, including when the module is rejected.
+/// If the error message does not fit in `error_out`, `SGX_ERROR_INVALID_PARAMETER` is returned and `error_len`
+/// is set to the number of bytes needed.
+#[no_mangle]
+pub unsafe extern "C" fn register_module(
+    binary: *const u8,
+    binary_len: usize,
+    module_hash_out: *mut u8,
+    error_out: *mut u8,
+    error_capacity: usize,
+    error_len: *mut usize,
+    exec_error: *mut u32,
+) -> sgx_status_t {
+    if module_hash_out.is_null() || error_out.is_null() || error_len.is_null() || exec_error.is_null() {
+        eprintln!("Module registration pointer is null.");
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    let binary_slice = unsafe { optional_slice(binary, binary_len) };
+    let hash = match module_hash(binary_slice) {
+        Ok(hash) => hash,
+        Err(status) => return status,
+    };
+
+    // Safe because the caller guarantees the output pointers are valid.
+    unsafe { slice::from_raw_parts_mut(module_hash_out, hash.len()) }.copy_from_slice(&hash);
+    let (code, message) = match cached_module(hash, binary_slice) {
+        Ok(_) => (ExecErrorCode::Success, String::new()),
+        Err(e) => (ExecErrorCode::from(&e), e.to_string()),
+    };
+    unsafe { *exec_error = code as u32 };
+    unsafe { write_result(message.as_bytes(), error_out, error_capacity, error_len) }
+}
+
+/// Executes a module previously registered with `register_module`, identified by the SHA-256 of its binary.
+///
+/// If the module is no longer cached, the job fails with `ExecErrorCode::ModuleNotFound` and the host should
+/// register it again.
+///
+/// # Safety
+///
+/// `module_hash` must be valid for 32 bytes; the other pointers are as described for `exec_wasm`.
+#[no_mangle]
+pub unsafe extern "C" fn exec_module(
+    module_hash: *const u8,
+    data_in: *const u8,
+    data_len: usize,
+    data_format: u32,
+    csv_delimiter: u8,
+    csv_quote: u8,
+    schema_in: *const u8,
+    schema_len: usize,
+    policy_in: *const u8,
+    policy_len: usize,
+    fuel_limit: u64,
+    timestamp: u64,
+    result_out: *mut u8,
+    result_capacity: usize,
+    result_len: *mut usize,
+    log_out: *mut u8,
+    log_capacity: usize,
+    log_len: *mut usize,
+    receipt_out: *mut u8,
+    receipt_capacity: usize,
+    receipt_len: *mut usize,
+    fuel_used: *mut u64,
+    exec_error: *mut u32,
+) -> sgx_status_t {
+    let outputs = JobOutputs {
+        result_out,
+        result_capacity,
+        result_len,
+        log_out,
+        log_capacity,
+        log_len,
+        receipt_out,
+        receipt_capacity,
+        receipt_len,
+        fuel_used,
+        exec_error,
+    };
+    if module_hash.is_null() || outputs.has_null_pointer() {
+        eprintln!("Result pointer is null.");
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+
+    let mut hash: ModuleHash = [0; 32];
+    hash.copy_from_slice(unsafe { slice::from_raw_parts(module_hash, 32) });
+    let data = unsafe { optional_slice(data_in, data_len) };
+    let schema = unsafe { optional_slice(schema_in, schema_len) };
+    let policy = unsafe { optional_slice(policy_in, policy_len) };
+
+    let cached = module_cache().get(&hash);
+    let input = job_data(data, data_format, csv_delimiter, csv_quote, policy);
+    let report = match (cached, input) {
+        (Some(module), Ok((data, format, policy))) => {
+            match run_job(hash, &module, &data, format, schema, &policy, fuel_limit) {
+                Ok(report) => report,
+                Err(status) => return status,
             }
-        },
-        Err(e) => {
-            eprintln!("WASM execution error: {:?}", e);
-            sgx_status_t::SGX_ERROR_UNEXPECTED // Indicates execution failure.
+        }
+        (None, _) => JobReport::failure(ExecErrorCode::ModuleNotFound, "module is not registered".to_string()),
+        (_, Err(message)) => JobReport::failure(ExecErrorCode::InvalidInput, message),
+    };
+
+    // Safe because the caller guarantees the output pointers are valid.
+    unsafe { outputs.write(&report, timestamp) }
+}
+
+/// Creates an SGX report for `target_info` whose `report_data` commits to the enclave's encryption key (see
+/// `enclave_public_key`) and receipt signing key, and writes both keys, SEC1 uncompressed.
+///
+/// The report data starts with the SHA-256 of `ntc_receipt::key_binding` of the two keys, and is zero after
+/// it. A quote for the report lets a requester trust both keys, and with them every receipt the enclave signs.
+///
+/// # Safety
+///
+/// `target_info` must be valid for reads and `report_out` for a write of their types, and `encryption_key_out`
+/// and `signing_key_out` for writes of 65 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn enclave_report(
+    target_info: *const sgx_target_info_t,
+    report_out: *mut sgx_report_t,
+    encryption_key_out: *mut u8,
+    signing_key_out: *mut u8,
+) -> sgx_status_t {
+    if target_info.is_null() || report_out.is_null() || encryption_key_out.is_null() || signing_key_out.is_null() {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    // Safe because the caller guarantees the pointers are valid.
+    match receipts::report(unsafe { &*target_info }) {
+        Ok((report, encryption_key, signing_key)) => {
+            unsafe {
+                *report_out = report;
+                slice::from_raw_parts_mut(encryption_key_out, encryption_key.len()).copy_from_slice(&encryption_key);
+                slice::from_raw_parts_mut(signing_key_out, signing_key.len()).copy_from_slice(&signing_key);
+            }
+            sgx_status_t::SGX_SUCCESS
+        }
+        Err(status) => {
+            eprintln!("Failed to create the enclave report: {}", status);
+            status
         }
     }
 }
 
-/// Trims the output buffer at the first null byte and attempts to deserialize it into JSON.
+/// Writes the enclave's P-256 public key, SEC1 uncompressed, to `public_key_out`.
+///
+/// Data owners encrypt datasets to this key for `import_dataset`, and requesters their jobs for `exec_encrypted`.
+/// The key pair never leaves the enclave and is generated afresh whenever the enclave is launched.
+///
+/// # Safety
+///
+/// `public_key_out` must be valid for writes of 65 bytes.
+#[no_mangle]
+pub unsafe extern "C" fn enclave_public_key(public_key_out: *mut u8) -> sgx_status_t {
+    if public_key_out.is_null() {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    match envelope::public_key() {
+        Ok(key) => {
+            // Safe because the caller guarantees `public_key_out` is valid for the key.
+            unsafe { slice::from_raw_parts_mut(public_key_out, key.len()) }.copy_from_slice(&key);
+            sgx_status_t::SGX_SUCCESS
+        }
+        Err(status) => {
+            eprintln!("Failed to generate the enclave key: {}", status);
+            status
+        }
+    }
+}
+
+/// Decrypts a dataset sent to the enclave and seals it, so that the host can store it and later run jobs on it
+/// (with `data_format` 3) without ever seeing it in plaintext.
+///
+/// The dataset is only decoded and checked against a schema when a job runs on it.
+///
+/// # Safety
+///
+/// `envelope_in` must be null or valid for `envelope_len` bytes, `sealed_out` valid for writes of `sealed_capacity`
+/// bytes, and `sealed_len` and `exec_error` for a single write.
 ///
 /// # Arguments
 ///
-/// * `buffer`: The raw buffer containing the output data.
+/// * `envelope_in`: The dataset, encrypted to the key from `enclave_public_key` for the context `ntc-dataset`
+///   (see `envelope.rs` for the layout).
+/// * `envelope_len`: Length of the envelope.
+/// * `data_format`: Encoding of the dataset, 0 to 2 as for `exec_wasm`; it is sealed along with the dataset.
+/// * `csv_delimiter`: Field delimiter of a CSV dataset.
+/// * `csv_quote`: Quote character of a CSV dataset.
+/// * `policy_in`: Pointer to the dataset owner's policy, as for `exec_wasm`; it is sealed along with the dataset
+///   and enforced on every job run on it.
+/// * `policy_len`: Length of the policy; 0 for none.
+/// * `seal_policy`: `SGX_KEYPOLICY_MRENCLAVE` (1) to let only this enclave build unseal the dataset, or
+///   `SGX_KEYPOLICY_MRSIGNER` (2) to let any enclave signed with the same key unseal it.
+/// * `sealed_out`: Buffer that receives the sealed dataset, or an error message if the import failed.
+/// * `sealed_capacity`: Size of the `sealed_out` buffer.
+/// * `sealed_len`: Receives the length of the sealed dataset or error message.
+/// * `exec_error`: Receives an `ExecErrorCode`: 0 on success, `InvalidInput` if the envelope, format or policy
+///   is rejected.
+///
+/// # Returns
+///
+/// Returns `SGX_SUCCESS` once the request was handled, including when the import is rejected.
+/// If `sealed_out` is too small, `SGX_ERROR_INVALID_PARAMETER` is returned and `sealed_len` is set to the
+/// number of bytes needed.
+#[no_mangle]
+pub unsafe extern "C" fn import_dataset(
+    envelope_in: *const u8,
+    envelope_len: usize,
+    data_format: u32,
+    csv_delimiter: u8,
+    csv_quote: u8,
+    policy_in: *const u8,
+    policy_len: usize,
+    seal_policy: u32,
+    sealed_out: *mut u8,
+    sealed_capacity: usize,
+    sealed_len: *mut usize,
+    exec_error: *mut u32,
+) -> sgx_status_t {
+    if sealed_out.is_null() || sealed_len.is_null() || exec_error.is_null() {
+        eprintln!("Dataset import pointer is null.");
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    let envelope_bytes = unsafe { optional_slice(envelope_in, envelope_len) };
+    let dataset_policy = unsafe { optional_slice(policy_in, policy_len) };
+
+    let imported = DataFormat::from_ecall(data_format, csv_delimiter, csv_quote)
+        .and_then(|_| DatasetPolicy::parse(dataset_policy))
+        .and_then(|_| SealPolicy::from_ecall(seal_policy))
+        .and_then(|policy| Ok((envelope::open(envelope_bytes, envelope::DATASET_CONTEXT)?, policy)))
+        .map_err(|message| (ExecErrorCode::InvalidInput, message))
+        .and_then(|(data, policy)| {
+            sealing::seal_dataset(&data, (data_format, csv_delimiter, csv_quote), dataset_policy, policy)
+                .map_err(|message| (ExecErrorCode::Internal, message))
+        });
+    let (code, payload) = match imported {
+        Ok(blob) => (ExecErrorCode::Success, blob),
+        Err((code, message)) => {
+            eprintln!("Dataset import error: {}", message);
+            (code, message.into_bytes())
+        }
+    };
+
+    // Safe because the caller guarantees the output pointers are valid.
+    unsafe { *exec_error = code as u32 };
+    unsafe { write_result(&payload, sealed_out, sealed_capacity, sealed_len) }
+}
+
+/// Reports the module cache's counters: lookups that found a prepared module, lookups that did not, and the
+/// number and total binary size of the cached modules.
+///
+/// # Safety
+///
+/// Every pointer must be valid for a single write.
+#[no_mangle]
+pub unsafe extern "C" fn module_cache_stats(
+    hits: *mut u64,
+    misses: *mut u64,
+    entries: *mut u64,
+    bytes: *mut u64,
+) -> sgx_status_t {
+    if hits.is_null() || misses.is_null() || entries.is_null() || bytes.is_null() {
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    let stats = module_cache().stats();
+    unsafe {
+        *hits = stats.hits;
+        *misses = stats.misses;
+        *entries = stats.entries;
+        *bytes = stats.bytes;
+    }
+    sgx_status_t::SGX_SUCCESS
+}
+
+/// Converts the scalar returned by a guest's `exec` into a JSON number.
+///
+/// Non-finite floats have no JSON representation and become `null`.
+fn scalar_to_json(value: RuntimeValue) -> Value {
+    match value {
+        RuntimeValue::I32(v) => json!(v),
+        RuntimeValue::I64(v) => json!(v),
+        RuntimeValue::F32(v) => json!(v.to_float()),
+        RuntimeValue::F64(v) => json!(v.to_float()),
+    }
+}
+
+/// Copies `result` into the caller's output buffer and reports its length.
+///
+/// # Safety
+///
+/// `result_out` must be valid for writes of `result_capacity` bytes and `result_len` must be valid for a single write.
 ///
 /// # Returns
 ///
-/// Returns a `Result` containing the deserialized JSON `Value` or an error if deserialization fails.
-fn trim_and_deserialize_output(buffer: &[u8]) -> Result<Value, serde_json::Error> {
-    let valid_data_end = buffer.iter().position(|&x| x == 0x00).unwrap_or(buffer.len());
-    let valid_data = &buffer[..valid_data_end];
-    serde_json::from_slice(valid_data)
+/// Returns `SGX_ERROR_INVALID_PARAMETER` without copying anything if `result_capacity` is smaller than `result`.
+/// `result_len` is always set to the length of `result`.
+unsafe fn write_result(
+    result: &[u8],
+    result_out: *mut u8,
+    result_capacity: usize,
+    result_len: *mut usize,
+) -> sgx_status_t {
+    unsafe { *result_len = result.len() };
+    if result.len() > result_capacity {
+        eprintln!("Result buffer too small: need {} bytes, got {}.", result.len(), result_capacity);
+        return sgx_status_t::SGX_ERROR_INVALID_PARAMETER;
+    }
+    let output = unsafe { slice::from_raw_parts_mut(result_out, result.len()) };
+    output.copy_from_slice(result);
+    sgx_status_t::SGX_SUCCESS
 }
@@ -0,0 +1,95 @@
+//! Least-recently-used cache of prepared modules, keyed by the SHA-256 of their binary.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use wasmi_impl::PreparedModule;
+
+/// SHA-256 of a module binary.
+pub type ModuleHash = [u8; 32];
+
+struct Entry {
+    module: Arc<PreparedModule>,
+    size: usize,
+    last_used: u64,
+}
+
+/// Counters reported to the host by the `module_cache_stats` ECALL.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+/// Bounds the total size of the binaries whose prepared modules it holds, evicting the least recently
+/// used modules first. The binary size stands in for the prepared module's footprint, which grows with it.
+pub struct ModuleCache {
+    entries: BTreeMap<ModuleHash, Entry>,
+    capacity_bytes: usize,
+    used_bytes: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl ModuleCache {
+    pub fn new(capacity_bytes: usize) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            capacity_bytes,
+            used_bytes: 0,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Looks up a module, counting the lookup as a hit or a miss.
+    pub fn get(&mut self, hash: &ModuleHash) -> Option<Arc<PreparedModule>> {
+        self.clock += 1;
+        match self.entries.get_mut(hash) {
+            Some(entry) => {
+                self.hits += 1;
+                entry.last_used = self.clock;
+                Some(entry.module.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Caches `module`, prepared from a binary of `size` bytes, evicting older modules to make room.
+    /// Modules larger than the whole cache are not kept.
+    pub fn insert(&mut self, hash: ModuleHash, module: Arc<PreparedModule>, size: usize) {
+        if size > self.capacity_bytes {
+            return;
+        }
+        if let Some(previous) = self.entries.remove(&hash) {
+            self.used_bytes -= previous.size;
+        }
+        while self.used_bytes + size > self.capacity_bytes {
+            let oldest = match self.entries.iter().min_by_key(|(_, entry)| entry.last_used) {
+                Some((oldest, _)) => *oldest,
+                None => break,
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.used_bytes -= evicted.size;
+            }
+        }
+        self.clock += 1;
+        self.used_bytes += size;
+        self.entries.insert(hash, Entry { module, size, last_used: self.clock });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len() as u64,
+            bytes: self.used_bytes as u64,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Data-owner policies: which modules may run on a dataset, how large a result they may release from it, and
+//! how few rows it may be run on. They are checked before the module is instantiated, except for the result
+//! size, which is checked before the result leaves the enclave.
+//!
+//! A policy is a JSON object whose fields are all optional:
+//!
+//! ```text
+//! {"modules": ["<SHA-256 of a module binary, hex>", ...], "max_output_bytes": <n>, "min_rows": <n>}
+//! ```
+//!
+//! `max_output_bytes` bounds the JSON result, and `min_rows` the number of records in the dataset: the length
+//! of its shortest column. An empty policy, or none at all, allows every module.
+//!
+//! Sealing a dataset is itself part of its policy: the owner hid its values from the host, so errors about a
+//! sealed dataset do not quote them.
+
+use core::convert::TryInto;
+use module_cache::ModuleHash;
+use ntc_receipt::decode_hex;
+use serde_json::Value;
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+/// What a dataset's owner allows jobs on it to do.
+#[derive(Clone, Debug, Default)]
+pub struct DatasetPolicy {
+    /// Modules that may run on the dataset, or `None` for any module.
+    modules: Option<Vec<ModuleHash>>,
+    max_output_bytes: Option<u64>,
+    min_rows: Option<u64>,
+    /// Whether the dataset was sealed.
+    sealed: bool,
+}
+
+impl DatasetPolicy {
+    /// Parses a policy; empty input is the policy that allows everything.
+    pub fn parse(policy: &[u8]) -> Result<Self, String> {
+        if policy.is_empty() {
+            return Ok(Self::default());
+        }
+        let value: Value = serde_json::from_slice(policy).map_err(|e| format!("policy is not valid JSON: {}", e))?;
+        let fields = value.as_object().ok_or("policy must be a JSON object")?;
+        let mut parsed = Self::default();
+        for (key, value) in fields {
+            match key.as_str() {
+                "modules" => {
+                    let hashes = value.as_array().ok_or("policy `modules` must be an array")?;
+                    let modules: Option<Vec<_>> =
+                        hashes.iter().map(|hash| hash.as_str().and_then(parse_hash)).collect();
+                    parsed.modules = Some(modules.ok_or("policy `modules` must hold SHA-256 hashes as 64 hex digits")?);
+                }
+                "max_output_bytes" => {
+                    parsed.max_output_bytes = Some(value.as_u64().ok_or("policy `max_output_bytes` must be a count")?)
+                }
+                "min_rows" => parsed.min_rows = Some(value.as_u64().ok_or("policy `min_rows` must be a count")?),
+                other => return Err(format!("unknown policy field `{}`", other)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The policy of a sealed dataset, otherwise allowing what `self` allows.
+    pub fn sealed(self) -> Self {
+        DatasetPolicy { sealed: true, ..self }
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.sealed
+    }
+
+    /// The policy allowing only what both `self` and `other` allow.
+    pub fn combine(self, other: Self) -> Self {
+        let modules = match (self.modules, other.modules) {
+            (Some(mine), Some(theirs)) => Some(mine.into_iter().filter(|hash| theirs.contains(hash)).collect()),
+            (mine, theirs) => mine.or(theirs),
+        };
+        let stricter = |a: Option<u64>, b: Option<u64>, pick: fn(u64, u64) -> u64| match (a, b) {
+            (Some(a), Some(b)) => Some(pick(a, b)),
+            (a, b) => a.or(b),
+        };
+        DatasetPolicy {
+            modules,
+            max_output_bytes: stricter(self.max_output_bytes, other.max_output_bytes, u64::min),
+            min_rows: stricter(self.min_rows, other.min_rows, u64::max),
+            sealed: self.sealed || other.sealed,
+        }
+    }
+
+    /// Whether jobs on the dataset must not return their log, in which the module could copy out the dataset's
+    /// values past the policy: true for sealed datasets and for datasets with any policy.
+    pub fn withholds_log(&self) -> bool {
+        self.sealed || self.modules.is_some() || self.max_output_bytes.is_some() || self.min_rows.is_some()
+    }
+
+    pub fn check_module(&self, module_hash: &ModuleHash) -> Result<(), String> {
+        match &self.modules {
+            Some(modules) if !modules.contains(module_hash) => {
+                Err("the dataset's policy does not allow this module".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the number of records in `data`, the dataset decoded as JSON.
+    pub fn check_rows(&self, data: &Value) -> Result<(), String> {
+        let rows = row_count(data);
+        match self.min_rows {
+            Some(min_rows) if rows < min_rows => Err(format!(
+                "the dataset's policy requires at least {} rows, but it has {}",
+                min_rows, rows
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_output(&self, result: &[u8]) -> Result<(), String> {
+        match self.max_output_bytes {
+            Some(max) if result.len() as u64 > max => Err(format!(
+                "the result is {} bytes, more than the {} the dataset's policy allows",
+                result.len(),
+                max
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Records in a dataset: the length of its shortest column, or of the dataset itself if it is an array.
+fn row_count(data: &Value) -> u64 {
+    match data {
+        Value::Array(rows) => rows.len() as u64,
+        Value::Object(columns) => columns
+            .values()
+            .map(|column| column.as_array().map_or(0, |values| values.len() as u64))
+            .min()
+            .unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn parse_hash(hex: &str) -> Option<ModuleHash> {
+    decode_hex(hex)?.try_into().ok()
+}
@@ -0,0 +1,85 @@
+//! Execution receipts, numbered and signed with an ECDSA P-256 key that is generated inside the enclave on first
+//! use and never leaves it, and the SGX report binding that key to the enclave.
+//!
+//! Receipts are encoded by `ntc_receipt`, whose verifier checks them offline. SGX keeps signatures as
+//! little-endian words; they are signed over as `ntc_receipt` describes and sent big-endian.
+
+use envelope;
+use ntc_receipt::{self, Receipt, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use sgx_tcrypto::{rsgx_sha256_slice, SgxEccHandle};
+use sgx_tse::rsgx_create_report;
+use sgx_types::*;
+use std::string::String;
+use std::sync::SgxMutex;
+use std::vec::Vec;
+
+/// The receipt signing key pair, and the number of receipts signed with it.
+struct Signer {
+    private: sgx_ec256_private_t,
+    public: sgx_ec256_public_t,
+    signed: u64,
+}
+
+lazy_static! {
+    static ref SIGNER: SgxMutex<Option<Signer>> = SgxMutex::new(None);
+}
+
+/// Runs `f` with the signer, generating its key pair on first use.
+fn with_signer<T>(f: impl FnOnce(&mut Signer) -> Result<T, String>) -> Result<T, String> {
+    let mut signer = SIGNER.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if signer.is_none() {
+        let handle = SgxEccHandle::new();
+        handle.open().map_err(|status| format!("cannot open the ECC context: {}", status))?;
+        let (private, public) =
+            handle.create_key_pair().map_err(|status| format!("cannot generate the signing key: {}", status))?;
+        *signer = Some(Signer { private, public, signed: 0 });
+    }
+    f(signer.as_mut().unwrap())
+}
+
+/// The key receipts are signed with, SEC1 uncompressed.
+pub fn signing_key() -> Result<[u8; PUBLIC_KEY_LEN], String> {
+    with_signer(|signer| Ok(envelope::encode_public_key(&signer.public)))
+}
+
+/// Numbers `receipt` as the next one signed by this enclave, and returns it encoded and followed by its
+/// signature. The number is only used up once the receipt is signed, so the sequence has no gaps.
+pub fn sign(mut receipt: Receipt) -> Result<Vec<u8>, String> {
+    with_signer(|signer| {
+        receipt.sequence = signer.signed + 1;
+        let handle = SgxEccHandle::new();
+        handle.open().map_err(|status| format!("cannot open the ECC context: {}", status))?;
+        let mut signed = receipt.encode();
+        let signature = handle
+            .ecdsa_sign_slice(&signed, &signer.private)
+            .map_err(|status| format!("cannot sign the receipt: {}", status))?;
+        signer.signed = receipt.sequence;
+
+        let mut encoded = [0u8; SIGNATURE_LEN];
+        for (dst, word) in encoded[..32].chunks_exact_mut(4).zip(signature.x.iter().rev()) {
+            dst.copy_from_slice(&word.to_be_bytes());
+        }
+        for (dst, word) in encoded[32..].chunks_exact_mut(4).zip(signature.y.iter().rev()) {
+            dst.copy_from_slice(&word.to_be_bytes());
+        }
+        signed.extend_from_slice(&encoded);
+        Ok(signed)
+    })
+}
+
+/// A report for `target_info` whose `report_data` commits to the enclave's encryption and signing keys, as
+/// `ntc_receipt::key_binding` describes. Returns the report with both keys.
+pub fn report(
+    target_info: &sgx_target_info_t,
+) -> SgxResult<(sgx_report_t, [u8; PUBLIC_KEY_LEN], [u8; PUBLIC_KEY_LEN])> {
+    let encryption_key = envelope::public_key()?;
+    let signing_key = signing_key().map_err(|message| {
+        eprintln!("Failed to create the signing key: {}", message);
+        sgx_status_t::SGX_ERROR_UNEXPECTED
+    })?;
+    let commitment = rsgx_sha256_slice(&ntc_receipt::key_binding(&encryption_key, &signing_key))?;
+    let mut report_data = sgx_report_data_t::default();
+    report_data.d[..commitment.len()].copy_from_slice(&commitment);
+    let report = rsgx_create_report(target_info, &report_data)?;
+    Ok((report, encryption_key, signing_key))
+}
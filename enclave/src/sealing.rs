@@ -0,0 +1,99 @@
+//! Datasets sealed with `sgx_tseal`, which the host stores and hands back to run jobs on them without ever
+//! seeing their contents.
+//!
+//! The dataset is the sealed (encrypted) text. Its format and its owner's policy (see `policy.rs`) travel
+//! alongside as the authenticated additional text, laid out as `DATASET_MAGIC`, the `data_format` byte, the CSV
+//! delimiter, the CSV quote and the policy.
+
+use sgx_tseal::SgxSealedData;
+use sgx_types::*;
+use std::mem;
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+/// Start of the additional text of every sealed dataset.
+const DATASET_MAGIC: &[u8] = b"ntc-dataset\x01";
+
+/// Key the sealed data is bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SealPolicy {
+    /// Only this exact enclave build can unseal the dataset.
+    MrEnclave,
+    /// Any enclave signed with the same key, such as a later version of this one, can unseal the dataset.
+    MrSigner,
+}
+
+impl SealPolicy {
+    /// `policy` is `SGX_KEYPOLICY_MRENCLAVE` or `SGX_KEYPOLICY_MRSIGNER`.
+    pub fn from_ecall(policy: u32) -> Result<Self, String> {
+        match policy {
+            p if p == u32::from(SGX_KEYPOLICY_MRENCLAVE) => Ok(SealPolicy::MrEnclave),
+            p if p == u32::from(SGX_KEYPOLICY_MRSIGNER) => Ok(SealPolicy::MrSigner),
+            other => Err(format!("unsupported seal policy {}", other)),
+        }
+    }
+
+    fn key_policy(self) -> u16 {
+        match self {
+            SealPolicy::MrEnclave => SGX_KEYPOLICY_MRENCLAVE,
+            SealPolicy::MrSigner => SGX_KEYPOLICY_MRSIGNER,
+        }
+    }
+}
+
+/// The `data_format`, CSV delimiter and CSV quote a dataset was imported with.
+pub type DatasetFormat = (u32, u8, u8);
+
+/// Seals `data` with its format and its owner's `dataset_policy` under `policy`, returning the blob to hand to
+/// the host.
+pub fn seal_dataset(
+    data: &[u8],
+    format: DatasetFormat,
+    dataset_policy: &[u8],
+    policy: SealPolicy,
+) -> Result<Vec<u8>, String> {
+    let (data_format, csv_delimiter, csv_quote) = format;
+    let mut header = DATASET_MAGIC.to_vec();
+    header.extend_from_slice(&[data_format as u8, csv_delimiter, csv_quote]);
+    header.extend_from_slice(dataset_policy);
+
+    let attribute_mask = sgx_attributes_t { flags: TSEAL_DEFAULT_FLAGSMASK, xfrm: 0 };
+    let sealed =
+        SgxSealedData::<[u8]>::seal_data_ex(policy.key_policy(), attribute_mask, TSEAL_DEFAULT_MISCMASK, &header, data)
+            .map_err(|status| format!("sealing failed: {}", status))?;
+    let size = SgxSealedData::<[u8]>::calc_raw_sealed_data_size(header.len() as u32, data.len() as u32);
+    if size == u32::MAX {
+        return Err("dataset is too large to seal".to_string());
+    }
+    let mut blob = vec![0u8; size as usize];
+    // Safe because `blob` is exactly as large as the sealed data needs.
+    unsafe { sealed.to_raw_sealed_data_t(blob.as_mut_ptr() as *mut sgx_sealed_data_t, size) }
+        .ok_or("sealed dataset cannot be serialized")?;
+    Ok(blob)
+}
+
+/// Unseals a blob returned by `seal_dataset`, returning the dataset, its format and its owner's policy.
+pub fn unseal_dataset(blob: &[u8]) -> Result<(Vec<u8>, DatasetFormat, Vec<u8>), String> {
+    if blob.len() < mem::size_of::<sgx_sealed_data_t>() || blob.len() > u32::MAX as usize {
+        return Err("not a sealed dataset".to_string());
+    }
+    // The SDK parses the blob in place, so it works on a copy.
+    let mut blob = blob.to_vec();
+    let sealed = unsafe {
+        SgxSealedData::<[u8]>::from_raw_sealed_data_t(blob.as_mut_ptr() as *mut sgx_sealed_data_t, blob.len() as u32)
+    }
+    .ok_or("not a sealed dataset")?;
+    // A dataset sealed by another enclave fails like a modified one, with a MAC mismatch.
+    let unsealed = sealed
+        .unseal_data()
+        .map_err(|status| format!("sealed dataset cannot be unsealed by this enclave: {}", status))?;
+
+    let header = unsealed.get_additional_txt();
+    let (format, dataset_policy) = match header.strip_prefix(DATASET_MAGIC) {
+        Some(&[data_format, csv_delimiter, csv_quote, ref dataset_policy @ ..]) => {
+            ((u32::from(data_format), csv_delimiter, csv_quote), dataset_policy.to_vec())
+        }
+        _ => return Err("sealed data is not a dataset".to_string()),
+    };
+    Ok((unsealed.get_decrypt_txt().to_vec(), format, dataset_policy))
+}
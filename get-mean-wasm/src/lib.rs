@@ -10,6 +10,42 @@ extern crate alloc; // To use Vec and other collection types.
 use core::slice; // For operations on slices.
 use core::mem; // For memory-related operations.
 use alloc::vec::Vec; // To use Vec in no_std environment.
+use core::alloc::Layout; // For describing host-requested allocations.
+use core::ptr; // For null pointers.
+
+// Declare the ABI this module is built against in its `ntc_abi` custom section.
+ntc_guest::ntc_abi!(input = "json", output = "json");
+
+// Results map each column to its average and nothing else, so the enclave rejects output that would leak rows.
+ntc_guest::ntc_output_schema!(
+    r#"{"type": "object", "additionalProperties": {"type": "object", "properties": {"Average": {"type": "number"}}, "required": ["Average"], "additionalProperties": false}}"#
+);
+
+/// Allocates `len` bytes of linear memory for the host to write inputs into or read outputs from.
+///
+/// The runtime calls this instead of writing at fixed offsets, so host buffers never overlap
+/// this module's static data, stack or heap. Returns a null pointer if the allocation fails.
+#[export_name = "alloc"]
+pub extern "C" fn guest_alloc(len: usize) -> *mut u8 {
+    match Layout::array::<u8>(len) {
+        Ok(layout) if layout.size() > 0 => unsafe { alloc::alloc::alloc(layout) },
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Releases a buffer previously returned by `alloc`.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by `alloc` called with the same `len`, and must not be used afterwards.
+#[export_name = "dealloc"]
+pub unsafe extern "C" fn guest_dealloc(ptr: *mut u8, len: usize) {
+    if let Ok(layout) = Layout::array::<u8>(len) {
+        if !ptr.is_null() && layout.size() > 0 {
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+    }
+}
 
 
 /// This function calculates averages of numeric columns in a JSON dataset according to a JSON schema and writes the results back as JSON.
@@ -49,11 +85,10 @@ pub unsafe extern "C" fn exec(input_data_ptr: *const u8, input_data_len: usize,
         }
     }
 
-    // Serialize the result map to JSON and write it to the specified output buffer.
+    // Serialize the result map to JSON and write it, length-prefixed, to the specified output region.
     let serialized_result = to_vec(&result).expect("Failed to serialize result");
-    assert!(output_len >= serialized_result.len(), "Output buffer too small");
-    let output_slice = unsafe {slice::from_raw_parts_mut(output_ptr, serialized_result.len())};
-    output_slice.copy_from_slice(&serialized_result);
+    assert!(output_len >= ntc_guest::OUTPUT_LENGTH_PREFIX + serialized_result.len(), "Output buffer too small");
+    unsafe { ntc_guest::write_output(output_ptr, &serialized_result) };
 }
 
 
@@ -64,15 +99,13 @@ mod tests {
     use std::mem;
     use serde_json::{Value, json, from_slice, to_vec}; // For working with JSON data.
 
-    fn trim_and_deserialize_output(buffer: &[u8]) -> Result<serde_json::Value, serde_json::Error> {
-    // Search for the first null byte which indicates the end of the valid data
-    let valid_data_end = buffer.iter().position(|&x| x == 0x00).unwrap_or_else(|| buffer.len());
-
-    // Create a slice of the buffer up to the found position
-    let valid_data = &buffer[..valid_data_end];
+    fn deserialize_output(buffer: &[u8]) -> Result<serde_json::Value, serde_json::Error> {
+    // The output region starts with the length of the output as a little-endian u32
+    let (len, output) = buffer.split_at(ntc_guest::OUTPUT_LENGTH_PREFIX);
+    let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
 
-    // Deserialize the JSON data from the trimmed buffer
-    serde_json::from_slice(valid_data)
+    // Deserialize the JSON data from exactly that many bytes
+    serde_json::from_slice(&output[..len])
 }
 
     #[test]
@@ -142,8 +175,8 @@ mod tests {
             );
         }
 
-    // Trim and deserialize output buffer
-    let deserialized_json_data = match trim_and_deserialize_output(&output_buffer) {
+    // Deserialize the length-prefixed output buffer
+    let deserialized_json_data = match deserialize_output(&output_buffer) {
         Ok(json_data) => {
             println!("Deserialized JSON: {:?}", json_data);
             json_data // Here we save the deserialized data to use later
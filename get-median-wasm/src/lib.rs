@@ -2,38 +2,70 @@
 #![deny(clippy::mem_forget)]
 #![deny(unsafe_op_in_unsafe_fn)]
 use core::slice;
-use serde_json::{Value, json};
+use ntc_guest::columnar;
+use serde_json::json;
 
-/// Calculates the median of an array of numbers.
-/// Numbers could be floats or integers.
-/// Return a pointer to an array of results in linear memory.
+// Declare the ABI this module is built against in its `ntc_abi` custom section. The enclave encodes JSON and
+// CSV datasets in the columnar format for it, so the numbers never need parsing here.
+ntc_guest::ntc_abi!(input = "columnar", output = "json");
+
+// Results map each column to its median and nothing else, so the enclave rejects output that would leak rows.
+ntc_guest::ntc_output_schema!(
+    r#"{"type": "object", "additionalProperties": {"type": "object", "properties": {"Median": {"type": "number"}}, "required": ["Median"], "additionalProperties": false}}"#
+);
+
+/// Calculates the median of every column in a columnar dataset.
+/// Numbers could be floats or integers; both are read as `f64`.
+///
+/// The result, e.g. `{"Column_1":{"Median":6.1}}`, is written to the `output_len`-byte region at `output_ptr`
+/// as JSON preceded by its length. The job is aborted if the dataset has an empty column or cannot be decoded,
+/// or if the result does not fit in the region.
+///
+/// # Safety
+///
+/// `data` must be valid for `len` bytes, and `output_ptr` for writes of `output_len` bytes.
 #[no_mangle]
-pub unsafe extern "C" fn exec(data: *const u8, len: u32) -> *const f32 {
+pub unsafe extern "C" fn exec(data: *const u8, len: u32, output_ptr: *mut u8, output_len: u32) {
     let data_slice = unsafe { slice::from_raw_parts(data, len as usize) };
-    let json_data: Value = serde_json::from_slice(data_slice).unwrap();
-    let columns = json_data.as_object().unwrap();
+    let results = medians(data_slice).unwrap_or_else(|message| fail(&message));
+
+    // Write the serialized results, length-prefixed, to the host-provided output buffer.
+    let serialized = serde_json::to_vec(&results).unwrap();
+    if ntc_guest::OUTPUT_LENGTH_PREFIX + serialized.len() > output_len as usize {
+        fail("the result does not fit in the output buffer");
+    }
+    unsafe { ntc_guest::write_output(output_ptr, &serialized) };
+}
 
-    let mut results = Vec::new();
-    for (_column_name, values) in columns {
-        let mut numbers: Vec<f32> = values.as_array().unwrap()
-            .iter()
-            .map(|v| v.as_f64().unwrap() as f32)
-            .collect();
+/// The median of every column in `data`, or why there is none.
+fn medians(data: &[u8]) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    let columns = columnar::decode(data).map_err(|e| format!("cannot decode the dataset: {}", e))?;
 
-        numbers.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut results = serde_json::Map::new();
+    for column in columns {
+        let mut numbers: Vec<f64> = column.f64s().collect();
+        if numbers.is_empty() {
+            return Err(format!("column `{}` is empty", column.name));
+        }
+
+        numbers.sort_by(|a, b| a.total_cmp(b));
         let median = if numbers.len() % 2 == 0 {
             let mid = numbers.len() / 2;
             (numbers[mid - 1] + numbers[mid]) / 2.0
         } else {
             numbers[numbers.len() / 2]
         };
-        results.push(median);
+        results.insert(column.name.to_string(), json!({"Median": median}));
     }
+    Ok(results)
+}
 
-    // Allocate memory in the Wasm module's linear memory to store the results.
-    let result_ptr = results.as_ptr();
-    std::mem::forget(results); // Prevent deallocation
-    result_ptr
+/// Stops the job, reporting `message` as the reason.
+fn fail(message: &str) -> ! {
+    #[cfg(target_arch = "wasm32")]
+    ntc_guest::env::abort(message);
+    #[cfg(not(target_arch = "wasm32"))]
+    panic!("{}", message);
 }
 
 // Tests and other module definitions...
@@ -44,8 +76,8 @@ mod tests {
     use std::fs;
     use std::path::PathBuf;
 
-    /// Function to read the JSON file and serialize the data
-    fn read_and_serialize_json(file_name: &str) -> Vec<u8> {
+    /// Function to read the JSON file and encode its columns as the enclave does
+    fn read_and_encode_json(file_name: &str) -> Vec<u8> {
         // Construct the path to the JSON file
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         println!("{:?}", &path);
@@ -55,26 +87,43 @@ mod tests {
         let json_str = fs::read_to_string(path)
             .expect("Failed to read JSON file");
         println!("{:?}", &json_str);
-        // Serialize the JSON string into bytes
-        json_str.into_bytes()
+        // Encode every column as f64s
+        let json_data: Value = serde_json::from_str(&json_str).unwrap();
+        let mut encoder = columnar::Encoder::new();
+        for (name, values) in json_data.as_object().unwrap() {
+            encoder.f64_column(name, values.as_array().unwrap().iter().map(|v| v.as_f64().unwrap()));
+        }
+        encoder.finish()
     }
 
     #[test]
-        #[test]
     fn test_wasm_binary() {
-        let json_data = read_and_serialize_json("test.json");
+        let data = read_and_encode_json("test.json");
+        let mut output_buffer = vec![0u8; 1024];
 
         // Directly use the exec function
-        let result_ptr = unsafe { exec(json_data.as_ptr(), json_data.len() as u32) };
+        unsafe { exec(data.as_ptr(), data.len() as u32, output_buffer.as_mut_ptr(), output_buffer.len() as u32) };
 
-        let results = unsafe { std::slice::from_raw_parts(result_ptr, 2) };
+        let len = u32::from_le_bytes([output_buffer[0], output_buffer[1], output_buffer[2], output_buffer[3]]) as usize;
+        let results: Value = serde_json::from_slice(&output_buffer[4..4 + len]).unwrap();
         println!("results from test : {:?}", &results);
         // Expected median values for Column_1 and Column_2
         let expected_median_1 = 6.1; // Median of [1, 3, 3, 6.1, 7, 8.1, 9]
         let expected_median_2 = 7.0; // Median of [3, 5, 6.1, 7, 7, 8.1, 9]
 
-        assert!((results[0] - expected_median_1).abs() < f32::EPSILON);
-        assert!((results[1] - expected_median_2).abs() < f32::EPSILON);
+        let median_1 = results["Column_1"]["Median"].as_f64().unwrap() as f32;
+        let median_2 = results["Column_2"]["Median"].as_f64().unwrap() as f32;
+        assert!((median_1 - expected_median_1).abs() < f32::EPSILON);
+        assert!((median_2 - expected_median_2).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn empty_columns_have_no_median() {
+        let mut encoder = columnar::Encoder::new();
+        encoder.f64_column("Column_1", [1.0, 2.0].iter().copied());
+        encoder.f64_column("Column_2", [].iter().copied());
+        assert_eq!(medians(&encoder.finish()).unwrap_err(), "column `Column_2` is empty");
+        assert!(medians(b"not columnar").is_err());
     }
 
 }
@@ -3,6 +3,9 @@
 use core::slice;
 use serde_json::Value;
 
+// Declare the ABI this module is built against in its `ntc_abi` custom section.
+ntc_guest::ntc_abi!(input = "json", output = "scalar");
+
 /// Calculates the SD of an array of integers passed in as a JSON array. Returns a float.
 ///
 /// # Safety
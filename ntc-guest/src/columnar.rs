@@ -0,0 +1,243 @@
+//! Binary columnar encoding of numeric datasets, taken by guests that declare `input = "columnar"`.
+//!
+//! Guests decode it without parsing any text and read every value at full precision. All integers are
+//! little-endian:
+//!
+//! ```text
+//! magic      b"NTCC"
+//! version    u8, currently 1
+//! columns    u32
+//! then, for every column:
+//!   name_len u32
+//!   name     name_len bytes of UTF-8
+//!   type     u8, 1 for f64 and 2 for i64
+//!   rows     u32
+//!   values   rows values of 8 bytes each
+//! ```
+//!
+//! Values are not aligned, so [`Column`] decodes them as it iterates instead of lending them as a slice.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt;
+
+/// First bytes of every columnar dataset.
+pub const MAGIC: &[u8; 4] = b"NTCC";
+
+/// Version of the encoding written by [`Encoder`].
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Offset of the column count, patched by [`Encoder::finish`].
+const COUNT_OFFSET: usize = MAGIC.len() + 1;
+
+/// Type of the values in a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    F64,
+    I64,
+}
+
+impl ColumnType {
+    fn tag(self) -> u8 {
+        match self {
+            ColumnType::F64 => 1,
+            ColumnType::I64 => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(ColumnType::F64),
+            2 => Some(ColumnType::I64),
+            _ => None,
+        }
+    }
+}
+
+/// Where and why a dataset could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnarError {
+    /// Offset of the offending byte.
+    pub offset: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ColumnarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "byte {}: {}", self.offset, self.message)
+    }
+}
+
+/// A named column borrowing its values from the encoded dataset.
+#[derive(Debug, Clone, Copy)]
+pub struct Column<'a> {
+    pub name: &'a str,
+    pub ty: ColumnType,
+    values: &'a [u8],
+}
+
+impl<'a> Column<'a> {
+    pub fn len(&self) -> usize {
+        self.values.len() / 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The values as `f64`; those of an `i64` column are converted, exactly up to 2^53 in magnitude.
+    pub fn f64s(&self) -> impl Iterator<Item = f64> + 'a {
+        let ty = self.ty;
+        self.values.chunks_exact(8).map(move |value| {
+            let value = value.try_into().unwrap();
+            match ty {
+                ColumnType::F64 => f64::from_le_bytes(value),
+                ColumnType::I64 => i64::from_le_bytes(value) as f64,
+            }
+        })
+    }
+
+    /// The values of an `i64` column, or `None` for an `f64` column.
+    pub fn i64s(&self) -> Option<impl Iterator<Item = i64> + 'a> {
+        match self.ty {
+            ColumnType::I64 => {
+                Some(self.values.chunks_exact(8).map(|value| i64::from_le_bytes(value.try_into().unwrap())))
+            }
+            ColumnType::F64 => None,
+        }
+    }
+}
+
+/// Splits `input` into its columns, checking that it is well-formed and that no column name repeats.
+pub fn decode(input: &[u8]) -> Result<Vec<Column<'_>>, ColumnarError> {
+    let mut reader = Reader { input, pos: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(ColumnarError { offset: 0, message: "not a columnar dataset" });
+    }
+    if reader.take(1)?[0] != FORMAT_VERSION {
+        return Err(ColumnarError { offset: MAGIC.len(), message: "unsupported version" });
+    }
+    let count = reader.u32()?;
+
+    let mut columns = Vec::new();
+    for _ in 0..count {
+        let name_offset = reader.pos;
+        let name_len = reader.u32()? as usize;
+        let name = core::str::from_utf8(reader.take(name_len)?)
+            .map_err(|_| ColumnarError { offset: name_offset, message: "column name is not valid UTF-8" })?;
+        if columns.iter().any(|column: &Column<'_>| column.name == name) {
+            return Err(ColumnarError { offset: name_offset, message: "column name appears more than once" });
+        }
+        let type_offset = reader.pos;
+        let ty = ColumnType::from_tag(reader.take(1)?[0])
+            .ok_or(ColumnarError { offset: type_offset, message: "unknown column type" })?;
+        let rows = reader.u32()? as usize;
+        let values = reader.take(rows.checked_mul(8).ok_or_else(|| reader.truncated())?)?;
+        columns.push(Column { name, ty, values });
+    }
+    if reader.pos != input.len() {
+        return Err(ColumnarError { offset: reader.pos, message: "unexpected bytes after the last column" });
+    }
+    Ok(columns)
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn truncated(&self) -> ColumnarError {
+        ColumnarError { offset: self.input.len(), message: "dataset is truncated" }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ColumnarError> {
+        let bytes = self.input.get(self.pos..).and_then(|rest| rest.get(..len)).ok_or_else(|| self.truncated())?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, ColumnarError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+/// Builds a columnar dataset one column at a time. Column names must be distinct, or [`decode`] rejects the
+/// dataset.
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    buf: Vec<u8>,
+    columns: u32,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        buf.push(FORMAT_VERSION);
+        buf.extend_from_slice(&0u32.to_le_bytes());
+        Self { buf, columns: 0 }
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn f64_column(&mut self, name: &str, values: impl IntoIterator<Item = f64>) {
+        self.column(name, ColumnType::F64, values.into_iter().map(f64::to_le_bytes));
+    }
+
+    pub fn i64_column(&mut self, name: &str, values: impl IntoIterator<Item = i64>) {
+        self.column(name, ColumnType::I64, values.into_iter().map(i64::to_le_bytes));
+    }
+
+    fn column(&mut self, name: &str, ty: ColumnType, values: impl Iterator<Item = [u8; 8]>) {
+        self.buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        self.buf.extend_from_slice(name.as_bytes());
+        self.buf.push(ty.tag());
+        // The row count is only known once the values are written.
+        let rows_offset = self.buf.len();
+        self.buf.extend_from_slice(&0u32.to_le_bytes());
+        let mut rows = 0u32;
+        for value in values {
+            self.buf.extend_from_slice(&value);
+            rows += 1;
+        }
+        self.buf[rows_offset..rows_offset + 4].copy_from_slice(&rows.to_le_bytes());
+        self.columns += 1;
+    }
+
+    /// Returns the encoded dataset.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf[COUNT_OFFSET..COUNT_OFFSET + 4].copy_from_slice(&self.columns.to_le_bytes());
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columnar_datasets_round_trip() {
+        let mut encoder = Encoder::new();
+        encoder.f64_column("Column_1", vec![8.1, -0.5]);
+        encoder.i64_column("count", vec![i64::MAX, 3]);
+        let encoded = encoder.finish();
+
+        let columns = decode(&encoded).unwrap();
+        assert_eq!(columns.len(), 2);
+        assert_eq!((columns[0].name, columns[0].ty), ("Column_1", ColumnType::F64));
+        assert_eq!(columns[0].f64s().collect::<Vec<_>>(), vec![8.1, -0.5]);
+        assert!(columns[0].i64s().is_none());
+        assert_eq!(columns[1].i64s().unwrap().collect::<Vec<_>>(), vec![i64::MAX, 3]);
+
+        assert_eq!(decode(&encoded[..encoded.len() - 1]).unwrap_err().message, "dataset is truncated");
+        let mut duplicated = Encoder::new();
+        duplicated.f64_column("x", vec![]);
+        duplicated.i64_column("x", vec![]);
+        assert_eq!(decode(&duplicated.finish()).unwrap_err().message, "column name appears more than once");
+    }
+}
@@ -0,0 +1,155 @@
+//! Shared helpers for building NTC guest modules.
+//!
+//! Every `get-*-wasm` crate invokes [`ntc_abi!`] once so that its binary carries an `ntc_abi` custom
+//! section declaring the ABI it was built against. The execution enclave reads that section to find the
+//! entry point and the input/output formats before running the module. Guests may also invoke
+//! [`ntc_output_schema!`] to declare what their results look like; the enclave withholds any result that
+//! does not match. Guests taking large numeric datasets can declare `input = "columnar"` and read them with
+//! [`columnar::decode`] instead of parsing JSON.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+pub mod columnar;
+
+/// ABI version emitted by [`ntc_abi!`].
+pub const ABI_VERSION: u32 = 2;
+
+/// Size of the little-endian `u32` length that starts the output region; see [`write_output`].
+pub const OUTPUT_LENGTH_PREFIX: usize = 4;
+
+/// Writes `output` to the output region at `region`, preceded by its length, as ABI version 2 requires.
+///
+/// The enclave reads back exactly `output.len()` bytes, so the output may contain NUL bytes.
+///
+/// # Safety
+///
+/// `region` must be valid for writes of `OUTPUT_LENGTH_PREFIX + output.len()` bytes.
+pub unsafe fn write_output(region: *mut u8, output: &[u8]) {
+    let len = (output.len() as u32).to_le_bytes();
+    unsafe {
+        core::ptr::copy_nonoverlapping(len.as_ptr(), region, OUTPUT_LENGTH_PREFIX);
+        core::ptr::copy_nonoverlapping(output.as_ptr(), region.add(OUTPUT_LENGTH_PREFIX), output.len());
+    }
+}
+
+/// Copies `declaration` into a fixed-size array so it can be placed in a custom section.
+///
+/// `N` must be `declaration.len()`; this is only meant to be called from [`ntc_abi!`] and
+/// [`ntc_output_schema!`].
+#[doc(hidden)]
+pub const fn section_bytes<const N: usize>(declaration: &str) -> [u8; N] {
+    let bytes = declaration.as_bytes();
+    let mut section = [0u8; N];
+    let mut i = 0;
+    while i < N {
+        section[i] = bytes[i];
+        i += 1;
+    }
+    section
+}
+
+/// Emits the `ntc_abi` custom section for the current guest.
+///
+/// `input` is `"json"` or `"columnar"` (see [`columnar`]), `output` is `"json"` for guests writing to an output region with [`write_output`] or
+/// `"scalar"` for guests returning a number, and `entry` defaults to `"exec"`.
+///
+/// ```ignore
+/// ntc_guest::ntc_abi!(input = "json", output = "json");
+/// ```
+#[macro_export]
+macro_rules! ntc_abi {
+    (input = $input:literal, output = $output:literal $(,)?) => {
+        $crate::ntc_abi!(entry = "exec", input = $input, output = $output);
+    };
+    (entry = $entry:literal, input = $input:literal, output = $output:literal $(,)?) => {
+        const NTC_ABI_DECLARATION: &str = concat!(
+            "version=2\nentry=", $entry, "\ninput=", $input, "\noutput=", $output, "\n"
+        );
+
+        #[link_section = "ntc_abi"]
+        #[used]
+        static NTC_ABI_SECTION: [u8; NTC_ABI_DECLARATION.len()] =
+            $crate::section_bytes(NTC_ABI_DECLARATION);
+    };
+}
+
+/// Emits the `ntc_output_schema` custom section for the current guest.
+///
+/// `schema` is a JSON schema every result must match before the enclave releases it. Set
+/// `"additionalProperties": false` or constrain additional properties so that a result cannot carry more than
+/// the guest means to publish.
+///
+/// ```ignore
+/// ntc_guest::ntc_output_schema!(r#"{"type": "object", "additionalProperties": {"type": "number"}}"#);
+/// ```
+#[macro_export]
+macro_rules! ntc_output_schema {
+    ($schema:literal $(,)?) => {
+        const NTC_OUTPUT_SCHEMA: &str = $schema;
+
+        #[link_section = "ntc_output_schema"]
+        #[used]
+        static NTC_OUTPUT_SCHEMA_SECTION: [u8; NTC_OUTPUT_SCHEMA.len()] =
+            $crate::section_bytes(NTC_OUTPUT_SCHEMA);
+    };
+}
+
+/// Safe wrappers around the sandboxed host functions the enclave provides in the `env` module.
+#[cfg(target_arch = "wasm32")]
+pub mod env {
+    #[link(wasm_import_module = "env")]
+    extern "C" {
+        #[link_name = "log"]
+        fn host_log(ptr: *const u8, len: usize);
+        #[link_name = "abort"]
+        fn host_abort(ptr: *const u8, len: usize) -> !;
+        #[link_name = "random"]
+        fn host_random(ptr: *mut u8, len: usize);
+    }
+
+    /// Appends `message` to the job's log. Messages over 4 KiB are truncated, and the log is capped per job.
+    pub fn log(message: &str) {
+        unsafe { host_log(message.as_ptr(), message.len()) }
+    }
+
+    /// Stops the job, reporting `message` as the reason.
+    pub fn abort(message: &str) -> ! {
+        unsafe { host_abort(message.as_ptr(), message.len()) }
+    }
+
+    /// Fills `buf` from the job's RNG, which the enclave seeds afresh for every job.
+    /// At most 4 KiB can be requested per call.
+    pub fn random(buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(4096) {
+            unsafe { host_random(chunk.as_mut_ptr(), chunk.len()) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    ntc_abi!(input = "json", output = "scalar");
+    ntc_output_schema!(r#"{"type": "number"}"#);
+
+    #[test]
+    fn declaration_matches_abi_version() {
+        assert_eq!(
+            &NTC_ABI_SECTION[..],
+            &b"version=2\nentry=exec\ninput=json\noutput=scalar\n"[..]
+        );
+        assert!(NTC_ABI_DECLARATION.starts_with(&format!("version={}\n", super::ABI_VERSION)));
+    }
+
+    #[test]
+    fn output_schema_is_embedded_verbatim() {
+        assert_eq!(&NTC_OUTPUT_SCHEMA_SECTION[..], &br#"{"type": "number"}"#[..]);
+    }
+
+    #[test]
+    fn output_is_length_prefixed() {
+        let mut region = [0xffu8; 8];
+        unsafe { super::write_output(region.as_mut_ptr(), b"\0{}") };
+        assert_eq!(region, [3, 0, 0, 0, 0, b'{', b'}', 0xff]);
+    }
+}
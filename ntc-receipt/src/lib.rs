@@ -0,0 +1,271 @@
+//! Execution receipts signed by the NTC execution enclave, and what a requester needs to check them offline.
+//!
+//! For every job that runs, the enclave signs a [`Receipt`] committing to the module, input data, schema and
+//! output by their SHA-256, and recording the limits it applied, the fuel used, the time the host gave for the
+//! job and the job's place in the sequence of receipts, with an ECDSA P-256 key generated inside the enclave.
+//! The key and the sequence last as long as the enclave does. The enclave's SGX report
+//! binds that signing key, together with the key requests are encrypted to, to the enclave's measurement:
+//! its `report_data` starts with the SHA-256 of [`key_binding`]. A verifier that trusts the report (through a
+//! quote, in production) can therefore trust every receipt signed by the key it binds.
+//!
+//! A signed receipt is the encoded receipt followed by the signature. All integers are little-endian:
+//!
+//! ```text
+//! magic          b"NTCR"
+//! version        u8, currently 1
+//! sequence       u64, counting the receipts signed by this enclave from 1; there are no gaps
+//! timestamp      u64, seconds since the Unix epoch as supplied by the host, which the enclave cannot check
+//! exec_error     u32, the job's error code; 0 if it succeeded
+//! module_sha256  32 bytes
+//! data_sha256    32 bytes, of the dataset as submitted (after unsealing, for sealed datasets)
+//! schema_sha256  32 bytes
+//! output_sha256  32 bytes, of the result exactly as delivered to the host: the JSON result, the error
+//!                message of a failed job, or the result envelope of an encrypted job
+//! fuel_limit     u64, the fuel the module was granted
+//! memory_pages   u32, the cap on the module's 64 KiB linear memory pages
+//! output_bytes   u32, the size of the module's output region
+//! log_bytes      u32, the log output kept for the job
+//! fuel_used      u64
+//! signature      64 bytes: ECDSA P-256 over the SHA-256 of the preceding bytes, r and s big-endian
+//! ```
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "verify")]
+pub mod verify;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt;
+
+/// First bytes of every receipt.
+pub const MAGIC: &[u8; 4] = b"NTCR";
+
+/// Version of the encoding written by [`Receipt::encode`].
+pub const FORMAT_VERSION: u8 = 1;
+
+/// Size of an encoded receipt, without its signature.
+pub const RECEIPT_LEN: usize = MAGIC.len() + 1 + 8 + 8 + 4 + 4 * 32 + 8 + 3 * 4 + 8;
+
+/// Size of an ECDSA P-256 signature.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Size of a signed receipt.
+pub const SIGNED_RECEIPT_LEN: usize = RECEIPT_LEN + SIGNATURE_LEN;
+
+/// Size of a SEC1 uncompressed P-256 public key.
+pub const PUBLIC_KEY_LEN: usize = 65;
+
+/// Prefix of the enclave keys hashed into the report data.
+pub const KEY_BINDING_LABEL: &[u8] = b"ntc-report-v1";
+
+/// What a job ran on, under which limits, and what it returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Receipt {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub exec_error: u32,
+    pub module_sha256: [u8; 32],
+    pub data_sha256: [u8; 32],
+    pub schema_sha256: [u8; 32],
+    pub output_sha256: [u8; 32],
+    pub limits: Limits,
+    pub fuel_used: u64,
+}
+
+/// The limits a job ran under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub fuel: u64,
+    pub memory_pages: u32,
+    pub output_bytes: u32,
+    pub log_bytes: u32,
+}
+
+/// Why a receipt or report could not be decoded or verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptError {
+    /// The receipt or report is malformed.
+    Malformed(&'static str),
+    /// A public key is not a P-256 point.
+    InvalidKey,
+    /// The signature does not match the receipt and key.
+    BadSignature,
+    /// The report does not commit to the given keys.
+    KeyMismatch,
+}
+
+impl fmt::Display for ReceiptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReceiptError::Malformed(message) => f.write_str(message),
+            ReceiptError::InvalidKey => f.write_str("key is not a P-256 public key"),
+            ReceiptError::BadSignature => f.write_str("signature does not match the receipt"),
+            ReceiptError::KeyMismatch => f.write_str("report does not commit to these keys"),
+        }
+    }
+}
+
+impl Receipt {
+    /// The receipt's bytes, which the enclave signs.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(RECEIPT_LEN);
+        encoded.extend_from_slice(MAGIC);
+        encoded.push(FORMAT_VERSION);
+        encoded.extend_from_slice(&self.sequence.to_le_bytes());
+        encoded.extend_from_slice(&self.timestamp.to_le_bytes());
+        encoded.extend_from_slice(&self.exec_error.to_le_bytes());
+        for hash in &[&self.module_sha256, &self.data_sha256, &self.schema_sha256, &self.output_sha256] {
+            encoded.extend_from_slice(&hash[..]);
+        }
+        encoded.extend_from_slice(&self.limits.fuel.to_le_bytes());
+        encoded.extend_from_slice(&self.limits.memory_pages.to_le_bytes());
+        encoded.extend_from_slice(&self.limits.output_bytes.to_le_bytes());
+        encoded.extend_from_slice(&self.limits.log_bytes.to_le_bytes());
+        encoded.extend_from_slice(&self.fuel_used.to_le_bytes());
+        encoded
+    }
+
+    /// Decodes a receipt, with or without its signature following it.
+    pub fn decode(bytes: &[u8]) -> Result<Self, ReceiptError> {
+        if bytes.len() < RECEIPT_LEN {
+            return Err(ReceiptError::Malformed("receipt is truncated"));
+        }
+        if &bytes[..MAGIC.len()] != MAGIC {
+            return Err(ReceiptError::Malformed("not a receipt"));
+        }
+        if bytes[MAGIC.len()] != FORMAT_VERSION {
+            return Err(ReceiptError::Malformed("unsupported receipt version"));
+        }
+        let mut fields = Fields { bytes: &bytes[MAGIC.len() + 1..RECEIPT_LEN] };
+        Ok(Receipt {
+            sequence: u64::from_le_bytes(fields.take()),
+            timestamp: u64::from_le_bytes(fields.take()),
+            exec_error: u32::from_le_bytes(fields.take()),
+            module_sha256: fields.take(),
+            data_sha256: fields.take(),
+            schema_sha256: fields.take(),
+            output_sha256: fields.take(),
+            limits: Limits {
+                fuel: u64::from_le_bytes(fields.take()),
+                memory_pages: u32::from_le_bytes(fields.take()),
+                output_bytes: u32::from_le_bytes(fields.take()),
+                log_bytes: u32::from_le_bytes(fields.take()),
+            },
+            fuel_used: u64::from_le_bytes(fields.take()),
+        })
+    }
+}
+
+/// Reads the fixed-size fields of a receipt whose length has been checked.
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (field, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        field.try_into().unwrap()
+    }
+}
+
+/// The bytes whose SHA-256 starts the enclave report's `report_data`: [`KEY_BINDING_LABEL`], then the
+/// enclave's encryption and signing keys, SEC1 uncompressed. The rest of the report data is zero.
+pub fn key_binding(encryption_key: &[u8], signing_key: &[u8]) -> Vec<u8> {
+    let mut binding = KEY_BINDING_LABEL.to_vec();
+    binding.extend_from_slice(encryption_key);
+    binding.extend_from_slice(signing_key);
+    binding
+}
+
+/// Encodes `bytes` as lowercase hex, two digits per byte; the inverse of [`decode_hex`].
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes a string of hex digit pairs, such as the hex of a receipt, key or module hash.
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let digit = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match *pair {
+            [high, low] => Some(digit(high)? << 4 | digit(low)?),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "verify"))]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+    use rand_core::OsRng;
+    use sha2::{Digest, Sha256};
+
+    fn receipt() -> Receipt {
+        Receipt {
+            sequence: 3,
+            timestamp: 1_700_000_000,
+            exec_error: 15,
+            module_sha256: [1; 32],
+            data_sha256: [2; 32],
+            schema_sha256: [3; 32],
+            output_sha256: [4; 32],
+            limits: Limits { fuel: 1000, memory_pages: 16384, output_bytes: 65536, log_bytes: 4096 },
+            fuel_used: 999,
+        }
+    }
+
+    #[test]
+    fn signed_receipts_verify_only_against_their_key() {
+        let key = SigningKey::random(&mut OsRng);
+        let public = key.verifying_key().to_encoded_point(false);
+        let mut signed = receipt().encode();
+        assert_eq!(signed.len(), RECEIPT_LEN);
+        let signature: Signature = key.sign(&signed);
+        signed.extend_from_slice(&signature.to_bytes());
+
+        assert_eq!(verify::verify_receipt(&signed, public.as_bytes()), Ok(receipt()));
+        let other = SigningKey::random(&mut OsRng).verifying_key().to_encoded_point(false);
+        assert_eq!(verify::verify_receipt(&signed, other.as_bytes()), Err(ReceiptError::BadSignature));
+        assert_eq!(Receipt::decode(&signed), Ok(receipt()));
+        let mut tampered = signed.clone();
+        tampered[RECEIPT_LEN - 1] ^= 1;
+        assert_eq!(verify::verify_receipt(&tampered, public.as_bytes()), Err(ReceiptError::BadSignature));
+        assert!(matches!(verify::verify_receipt(&signed[1..], public.as_bytes()), Err(ReceiptError::Malformed(_))));
+        assert_eq!(verify::verify_receipt(&signed, b"key"), Err(ReceiptError::InvalidKey));
+    }
+
+    #[test]
+    fn reports_bind_the_enclave_keys() {
+        let encryption_key = [4; PUBLIC_KEY_LEN];
+        let signing_key = [5; PUBLIC_KEY_LEN];
+        let mut report = vec![0u8; verify::REPORT_LEN];
+        let commitment = Sha256::digest(key_binding(&encryption_key, &signing_key));
+        report[verify::REPORT_DATA_OFFSET..verify::REPORT_DATA_OFFSET + 32].copy_from_slice(&commitment);
+        report[verify::MR_ENCLAVE_OFFSET] = 7;
+
+        assert_eq!(verify::report_data(&report).unwrap()[..32], commitment[..]);
+        assert_eq!(verify::mr_enclave(&report).unwrap()[0], 7);
+        assert_eq!(verify::verify_report_binding(&report, &encryption_key, &signing_key), Ok(()));
+        assert_eq!(
+            verify::verify_report_binding(&report, &signing_key, &encryption_key),
+            Err(ReceiptError::KeyMismatch)
+        );
+        assert!(verify::verify_report_binding(&report[1..], &encryption_key, &signing_key).is_err());
+    }
+
+    #[test]
+    fn hex_encodes_and_decodes_digit_pairs() {
+        assert_eq!(encode_hex(&[0, 255, 122]), "00ff7a");
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0, 255, 122]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("0g"), None);
+    }
+}
@@ -0,0 +1,73 @@
+//! Offline checks of signed receipts and of the SGX report binding the enclave's keys.
+//!
+//! The report is the raw `sgx_report_t` returned by the enclave. Its MAC can only be checked by the enclave it
+//! targets, so a remote verifier should take the report body from a quote verified through Intel's
+//! attestation services; these checks only tie the keys and receipts to that body.
+
+use crate::{key_binding, Receipt, ReceiptError, PUBLIC_KEY_LEN, RECEIPT_LEN, SIGNED_RECEIPT_LEN};
+use core::convert::TryInto;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+/// Size of an `sgx_report_t`: its 384-byte body, key id and MAC.
+pub const REPORT_LEN: usize = 432;
+
+/// Offset of `mr_enclave` in an `sgx_report_t`.
+pub const MR_ENCLAVE_OFFSET: usize = 64;
+
+/// Offset of `mr_signer` in an `sgx_report_t`.
+pub const MR_SIGNER_OFFSET: usize = 128;
+
+/// Offset of `report_data` in an `sgx_report_t`.
+pub const REPORT_DATA_OFFSET: usize = 320;
+
+/// Checks the signature of a signed receipt against the enclave's SEC1-encoded signing key, returning the
+/// receipt.
+pub fn verify_receipt(signed: &[u8], signing_key: &[u8]) -> Result<Receipt, ReceiptError> {
+    if signed.len() != SIGNED_RECEIPT_LEN {
+        return Err(ReceiptError::Malformed("signed receipt has the wrong length"));
+    }
+    let receipt = Receipt::decode(signed)?;
+    let key = VerifyingKey::from_sec1_bytes(signing_key).map_err(|_| ReceiptError::InvalidKey)?;
+    let (message, signature) = signed.split_at(RECEIPT_LEN);
+    let signature = Signature::from_slice(signature).map_err(|_| ReceiptError::BadSignature)?;
+    key.verify(message, &signature).map_err(|_| ReceiptError::BadSignature)?;
+    Ok(receipt)
+}
+
+/// Checks that a report's `report_data` commits to the enclave's encryption and signing keys.
+pub fn verify_report_binding(report: &[u8], encryption_key: &[u8], signing_key: &[u8]) -> Result<(), ReceiptError> {
+    if encryption_key.len() != PUBLIC_KEY_LEN || signing_key.len() != PUBLIC_KEY_LEN {
+        return Err(ReceiptError::InvalidKey);
+    }
+    let report_data = report_data(report)?;
+    let mut expected = [0u8; 64];
+    expected[..32].copy_from_slice(&Sha256::digest(key_binding(encryption_key, signing_key)));
+    if report_data != expected {
+        return Err(ReceiptError::KeyMismatch);
+    }
+    Ok(())
+}
+
+/// The 64 bytes of a report's `report_data`.
+pub fn report_data(report: &[u8]) -> Result<[u8; 64], ReceiptError> {
+    field(report, REPORT_DATA_OFFSET)
+}
+
+/// The enclave measurement in a report.
+pub fn mr_enclave(report: &[u8]) -> Result<[u8; 32], ReceiptError> {
+    field(report, MR_ENCLAVE_OFFSET)
+}
+
+/// The hash of the enclave signer's key in a report.
+pub fn mr_signer(report: &[u8]) -> Result<[u8; 32], ReceiptError> {
+    field(report, MR_SIGNER_OFFSET)
+}
+
+fn field<const N: usize>(report: &[u8], offset: usize) -> Result<[u8; N], ReceiptError> {
+    if report.len() != REPORT_LEN {
+        return Err(ReceiptError::Malformed("report has the wrong length"));
+    }
+    Ok(report[offset..offset + N].try_into().unwrap())
+}
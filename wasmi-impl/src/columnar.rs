@@ -0,0 +1,88 @@
+//! Conversion between JSON column arrays and the binary columnar encoding described in
+//! [`ntc_guest::columnar`].
+//!
+//! Columns of integers that fit in an `i64` are encoded as `i64`, and any other number column as `f64`.
+//! Errors name the offending value by its JSON pointer, as schema errors do.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use ntc_guest::columnar::{self, Encoder};
+use serde_json::{Map, Number, Value};
+
+use crate::schema::push_token;
+
+/// Decodes a columnar dataset into a JSON object mapping each column name to the array of its values.
+pub fn to_json(input: &[u8]) -> Result<Value, String> {
+    let columns = columnar::decode(input).map_err(|e| e.to_string())?;
+    let mut object = Map::new();
+    for column in columns {
+        let values: Vec<Value> = match column.i64s() {
+            Some(values) => values.map(Value::from).collect(),
+            None => column
+                .f64s()
+                .enumerate()
+                .map(|(row, value)| {
+                    Number::from_f64(value).map(Value::Number).ok_or_else(|| {
+                        let mut pointer = String::new();
+                        push_token(&mut pointer, column.name);
+                        push_token(&mut pointer, &row.to_string());
+                        format!("{}: {} is not a finite number", pointer, value)
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        object.insert(column.name.to_string(), Value::Array(values));
+    }
+    Ok(Value::Object(object))
+}
+
+/// Encodes a JSON object of number arrays as a columnar dataset.
+pub fn from_json(data: &Value) -> Result<Vec<u8>, String> {
+    let object = data.as_object().ok_or("(root): expected an object of number arrays")?;
+    let mut encoder = Encoder::new();
+    for (name, values) in object {
+        let mut pointer = String::new();
+        push_token(&mut pointer, name);
+        let values = values.as_array().ok_or_else(|| format!("{}: expected an array of numbers", pointer))?;
+
+        if let Some(integers) = values.iter().map(Value::as_i64).collect::<Option<Vec<i64>>>() {
+            encoder.i64_column(name, integers);
+            continue;
+        }
+        let mut numbers = Vec::with_capacity(values.len());
+        for (row, value) in values.iter().enumerate() {
+            match value.as_f64() {
+                Some(number) => numbers.push(number),
+                None => {
+                    push_token(&mut pointer, &row.to_string());
+                    return Err(format!("{}: expected a number", pointer));
+                }
+            }
+        }
+        encoder.f64_column(name, numbers);
+    }
+    Ok(encoder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columnar_input_converts_to_and_from_json() {
+        use columnar::ColumnType;
+
+        let data = serde_json::json!({ "Column_1": [8.1, 0.5, -2.5], "count": [3, -1], "a/b": [] });
+        let encoded = from_json(&data).unwrap();
+        let columns = columnar::decode(&encoded).unwrap();
+        let types: Vec<_> = columns.iter().map(|column| (column.name, column.ty)).collect();
+        assert_eq!(types, [("Column_1", ColumnType::F64), ("a/b", ColumnType::I64), ("count", ColumnType::I64)]);
+        assert_eq!(to_json(&encoded).unwrap(), data);
+
+        let err = from_json(&serde_json::json!({ "a/b": [1, "x"] })).unwrap_err();
+        assert_eq!(err, "/a~1b/1: expected a number");
+        let err = to_json(&encoded[..5]).unwrap_err();
+        assert_eq!(err, "byte 5: dataset is truncated");
+    }
+}
@@ -0,0 +1,355 @@
+//! Conversion of CSV datasets into the column-array JSON that guests take as input.
+//!
+//! The first record is a header naming the columns, and every later record holds one field per column. Each
+//! column becomes an array under its name, typed by the dataset schema: a column declared as an array of
+//! `number`, `integer`, `boolean`, `string` or `null` items (or as one of those types directly) has each field
+//! parsed as such, and a column the schema does not type is kept as strings. Fields may be quoted, with the
+//! quote character doubled inside them; records end with `\n` or `\r\n`, and blank lines are skipped.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use serde_json::{Map, Number, Value};
+
+/// Conversion stops after this many errors.
+pub const MAX_CSV_ERRORS: usize = 64;
+
+/// Field delimiter and quote character of a CSV dataset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvOptions {
+    delimiter: u8,
+    quote: u8,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self { delimiter: b',', quote: b'"' }
+    }
+}
+
+impl CsvOptions {
+    /// Both characters must be ASCII, distinct, and neither may be a line break.
+    pub fn new(delimiter: u8, quote: u8) -> Result<Self, String> {
+        for &(name, c) in &[("delimiter", delimiter), ("quote", quote)] {
+            if !c.is_ascii() || c == b'\n' || c == b'\r' {
+                return Err(format!("unsupported CSV {} {:?}", name, c as char));
+            }
+        }
+        if delimiter == quote {
+            return Err("CSV delimiter and quote must differ".to_string());
+        }
+        Ok(Self { delimiter, quote })
+    }
+}
+
+/// A record or field that cannot be converted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvError {
+    /// 1-based record number; the header is row 1.
+    pub row: usize,
+    /// Name of the offending column, if the error concerns a single field.
+    pub column: Option<String>,
+    pub message: String,
+    /// The field that could not be parsed, if the error concerns one; it is quoted after `message`.
+    pub field: Option<String>,
+}
+
+impl CsvError {
+    fn new(row: usize, column: Option<String>, message: String) -> Self {
+        CsvError { row, column, message, field: None }
+    }
+
+    /// The error without the field it quotes, if any.
+    pub fn redacted(&self) -> String {
+        CsvError { field: None, ..self.clone() }.to_string()
+    }
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.column {
+            Some(column) => write!(f, "row {}, column `{}`: {}", self.row, column, self.message)?,
+            None => write!(f, "row {}: {}", self.row, self.message)?,
+        }
+        match &self.field {
+            Some(field) => write!(f, ", found `{}`", field),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Converts `input` into a JSON object mapping each column name to the array of its values, returning every
+/// error found (up to `MAX_CSV_ERRORS`).
+pub fn to_columns(input: &[u8], schema: &Value, options: &CsvOptions) -> Result<Value, Vec<CsvError>> {
+    let mut records = Records { input, pos: 0, row: 0, options: *options };
+    let header = match records.next() {
+        Some(Ok((_, header))) => header,
+        Some(Err(error)) => return Err(vec![error]),
+        None => return Err(vec![CsvError::new(1, None, "missing header row".to_string())]),
+    };
+
+    let mut errors = Vec::new();
+    let mut columns: Vec<(String, Vec<&'static str>, Vec<Value>)> = Vec::with_capacity(header.len());
+    for name in header {
+        let error = if name.is_empty() {
+            Some("column name is empty")
+        } else if columns.iter().any(|(existing, _, _)| *existing == name) {
+            Some("column name appears more than once")
+        } else {
+            None
+        };
+        if let Some(message) = error {
+            errors.push(CsvError::new(1, Some(name), message.to_string()));
+            continue;
+        }
+        let types = column_types(schema, &name);
+        columns.push((name, types, Vec::new()));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    for record in records {
+        let (row, fields) = match record {
+            Ok(record) => record,
+            Err(error) => {
+                // The rest of the input can no longer be split into records reliably.
+                errors.push(error);
+                break;
+            }
+        };
+        if fields.len() != columns.len() {
+            let message = format!("expected {} fields, found {}", columns.len(), fields.len());
+            errors.push(CsvError::new(row, None, message));
+        } else {
+            for ((name, types, values), field) in columns.iter_mut().zip(fields) {
+                match parse_field(field, types) {
+                    Ok(value) => values.push(value),
+                    Err((message, field)) => {
+                        errors.push(CsvError { field, ..CsvError::new(row, Some(name.clone()), message) })
+                    }
+                }
+            }
+        }
+        if errors.len() >= MAX_CSV_ERRORS {
+            errors.truncate(MAX_CSV_ERRORS);
+            break;
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let columns: Map<String, Value> = columns
+        .into_iter()
+        .map(|(name, _, values)| (name, Value::Array(values)))
+        .collect();
+    Ok(Value::Object(columns))
+}
+
+/// Splits the input into records of fields.
+struct Records<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// Number of the last record read.
+    row: usize,
+    options: CsvOptions,
+}
+
+impl Records<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn error(&self, message: &str) -> CsvError {
+        CsvError::new(self.row, None, message.to_string())
+    }
+
+    fn field(&mut self) -> Result<String, CsvError> {
+        let CsvOptions { delimiter, quote } = self.options;
+        let mut field = Vec::new();
+        if self.peek() == Some(quote) {
+            self.pos += 1;
+            loop {
+                match self.peek() {
+                    None => return Err(self.error("quoted field is not closed")),
+                    Some(c) if c == quote => {
+                        self.pos += 1;
+                        if self.peek() != Some(quote) {
+                            break;
+                        }
+                        field.push(quote);
+                    }
+                    Some(c) => field.push(c),
+                }
+                self.pos += 1;
+            }
+            if !matches!(self.peek(), None | Some(b'\n') | Some(b'\r')) && self.peek() != Some(delimiter) {
+                return Err(self.error("unexpected character after a closing quote"));
+            }
+        } else {
+            while let Some(c) = self.peek() {
+                if c == delimiter || c == b'\n' || c == b'\r' {
+                    break;
+                }
+                if c == quote {
+                    return Err(self.error("quote inside an unquoted field"));
+                }
+                field.push(c);
+                self.pos += 1;
+            }
+        }
+        String::from_utf8(field).map_err(|_| self.error("field is not valid UTF-8"))
+    }
+}
+
+impl Iterator for Records<'_> {
+    /// A record's row number and fields.
+    type Item = Result<(usize, Vec<String>), CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Skip blank lines.
+        while let Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+        self.peek()?;
+        self.row += 1;
+
+        let mut fields = Vec::new();
+        loop {
+            match self.field() {
+                Ok(field) => fields.push(field),
+                Err(error) => {
+                    // Stop here so that a malformed record is not followed by spurious errors.
+                    self.pos = self.input.len();
+                    return Some(Err(error));
+                }
+            }
+            match self.peek() {
+                Some(c) if c == self.options.delimiter => self.pos += 1,
+                Some(b'\r') => {
+                    self.pos += 1;
+                    if self.peek() == Some(b'\n') {
+                        self.pos += 1;
+                    }
+                    break;
+                }
+                Some(b'\n') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        Some(Ok((self.row, fields)))
+    }
+}
+
+/// Types, in the order they are tried, that a field of the column `name` may be parsed as; empty if the schema
+/// does not type the column.
+fn column_types(schema: &Value, name: &str) -> Vec<&'static str> {
+    let column = match schema.get("properties").and_then(|properties| properties.get(name)) {
+        Some(column) => column,
+        None => return Vec::new(),
+    };
+    // An array column types its items; any other column types each of its values.
+    let item = match column.get("items") {
+        Some(items @ Value::Object(_)) => items,
+        _ => column,
+    };
+    let declared = |name: &str| match item.get("type") {
+        Some(Value::String(declared)) => declared == name,
+        Some(Value::Array(declared)) => declared.iter().any(|declared| declared == name),
+        _ => false,
+    };
+    ["null", "integer", "number", "boolean", "string"]
+        .iter()
+        .copied()
+        .filter(|name| declared(name))
+        .collect()
+}
+
+/// Parses `field` as the first of `types` it matches, or returns what was expected and the offending field.
+fn parse_field(field: String, types: &[&str]) -> Result<Value, (String, Option<String>)> {
+    if types.is_empty() {
+        return Ok(Value::String(field));
+    }
+    let trimmed = field.trim();
+    for &name in types {
+        let value = match name {
+            "null" if trimmed.is_empty() => Some(Value::Null),
+            "integer" => parse_integer(trimmed),
+            "number" => parse_integer(trimmed).or_else(|| {
+                let number = trimmed.parse::<f64>().ok()?;
+                Number::from_f64(number).map(Value::Number)
+            }),
+            "boolean" if trimmed.eq_ignore_ascii_case("true") => Some(Value::Bool(true)),
+            "boolean" if trimmed.eq_ignore_ascii_case("false") => Some(Value::Bool(false)),
+            "string" => return Ok(Value::String(field)),
+            _ => None,
+        };
+        if let Some(value) = value {
+            return Ok(value);
+        }
+    }
+    let expected = types.join(" or ");
+    if trimmed.is_empty() {
+        Err((format!("expected {}, found an empty field", expected), None))
+    } else {
+        Err((format!("expected {}", expected), Some(field)))
+    }
+}
+
+fn parse_integer(field: &str) -> Option<Value> {
+    match field.parse::<i64>() {
+        Ok(integer) => Some(Value::from(integer)),
+        Err(_) => field.parse::<u64>().ok().map(Value::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_input_is_converted_to_typed_columns() {
+        let schema = serde_json::json!({
+            "properties": {
+                "Column_1": { "type": "array", "items": { "type": "number" } },
+                "count": { "type": "array", "items": { "type": ["integer", "null"] } },
+                "ok": { "type": "array", "items": { "type": "boolean" } }
+            }
+        });
+        let options = CsvOptions::new(b';', b'\'').unwrap();
+        let input = b"Column_1;count;ok;label\r\n8.1;3;true;'a;b'\n\n-2; ;FALSE;'it''s\nmultiline'\n";
+        assert_eq!(
+            to_columns(input, &schema, &options).unwrap(),
+            serde_json::json!({
+                "Column_1": [8.1, -2],
+                "count": [3, null],
+                "ok": [true, false],
+                "label": ["a;b", "it's\nmultiline"]
+            })
+        );
+
+        let errors = to_columns(b"Column_1;ok\n1;yes\nx;true\n2\n", &schema, &options).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "row 2, column `ok`: expected boolean, found `yes`",
+                "row 3, column `Column_1`: expected number, found `x`",
+                "row 4: expected 2 fields, found 1",
+            ]
+        );
+        assert_eq!(errors[0].redacted(), "row 2, column `ok`: expected boolean");
+
+        let errors = to_columns(b"a;a\n", &schema, &options).unwrap_err();
+        assert_eq!(errors[0].to_string(), "row 1, column `a`: column name appears more than once");
+        let errors = to_columns(b"a\n'open\n", &schema, &options).unwrap_err();
+        assert_eq!(errors[0].to_string(), "row 2: quoted field is not closed");
+        assert!(CsvOptions::new(b'"', b'"').is_err());
+    }
+}
@@ -1,35 +1,903 @@
 #![cfg_attr(not(test), no_std)]
 extern crate alloc;
 use wasmi::{
-    self, Error as WasmiError,memory_units::Pages, ExternVal, ImportsBuilder, MemoryInstance, ModuleInstance,
-    NopExternals, RuntimeValue,
+    self, Error as WasmiError,memory_units::{Bytes, Pages, RoundUpTo}, ExternVal, ImportsBuilder, MemoryInstance, ModuleInstance,
+    Externals, FuncRef, HostError, ModuleRef, NopExternals, RuntimeArgs, RuntimeValue, Trap, TrapKind,
 };
 use wasmi::FuncInstance;
 use wasmi::Signature;
 use wasmi::ValueType;
 use wasmi::ModuleImportResolver;
 use wasmi::MemoryRef;
-use alloc::string::ToString;
+use parity_wasm::elements::{self, External, Internal};
+use wasm_instrument::gas_metering::{self, ConstantCostRules};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::cmp;
+use core::fmt;
 use core::convert::TryInto;
 use alloc::vec::Vec;
 use alloc::vec;
-use core::slice;
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+pub mod columnar;
+pub mod csv;
+pub mod schema;
+mod wasi;
 
 static ENTRYPOINT: &str = "exec";
 
+/// Custom section in which guests declare the ABI they were built against.
+static ABI_SECTION: &str = "ntc_abi";
+
+/// Custom section in which guests declare, as a JSON schema, the results they may release.
+static OUTPUT_SCHEMA_SECTION: &str = "ntc_output_schema";
+
+/// Newest `ntc_abi` version this runtime understands.
+pub const ABI_VERSION: u32 = 2;
+
+/// First `ntc_abi` version whose guests prefix their output with its length.
+const LENGTH_PREFIXED_ABI_VERSION: u32 = 2;
+
+/// Size of the little-endian `u32` length at the start of a length-prefixed output region.
+const OUTPUT_LENGTH_PREFIX: usize = 4;
+
+/// Optional guest export `alloc(len: i32) -> i32` used to obtain buffers for the job's inputs and output.
+static ALLOC_EXPORT: &str = "alloc";
+
+/// Optional guest export `dealloc(ptr: i32, len: i32)` used to release buffers obtained from `alloc`.
+static DEALLOC_EXPORT: &str = "dealloc";
+
+/// Function imported from `env` by fuel-metered code to charge for the instructions it is about to run.
+static GAS_IMPORT: &str = "gas";
+
+/// Host function index of the `env.gas` import.
+const GAS_FUNC_INDEX: usize = 0;
+
+/// `env.log(ptr: i32, len: i32)`: appends a message to the job's log.
+static LOG_IMPORT: &str = "log";
+
+/// Host function index of the `env.log` import.
+const LOG_FUNC_INDEX: usize = 1;
+
+/// `env.abort(msg_ptr: i32, msg_len: i32)`: stops the job with a message.
+static ABORT_IMPORT: &str = "abort";
+
+/// Host function index of the `env.abort` import.
+const ABORT_FUNC_INDEX: usize = 2;
+
+/// `env.random(ptr: i32, len: i32)`: fills a guest buffer from the job's deterministic RNG.
+static RANDOM_IMPORT: &str = "random";
+
+/// Host function index of the `env.random` import.
+const RANDOM_FUNC_INDEX: usize = 3;
+
+/// Longest message a single `log` or `abort` call may pass; longer messages are truncated.
+const MAX_MESSAGE_LEN: u32 = 4096;
+
+/// Most bytes a single `random` call may request.
+const MAX_RANDOM_LEN: u32 = 4096;
+
+/// Default cap on the total size of a job's log.
+const DEFAULT_MAX_LOG_BYTES: usize = 64 * 1024;
+
+/// Default size of the region a guest writes its output to, excluding any length prefix.
+const DEFAULT_MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// The largest linear memory a wasm32 module can address (4 GiB).
+const MAX_MEMORY_PAGES: u32 = 65536;
+
+/// Default number of bytes reserved for the guest's own stack, static data and heap.
+const DEFAULT_HEAP_RESERVE: usize = 2 * 1024 * 1024;
+
 #[derive(Debug)]
 pub enum ExecWasmError {
+    /// The binary is not a well-formed WebAssembly module.
+    ModuleDecode(String),
+    /// The module is well-formed but failed validation.
+    ModuleValidation(String),
+    /// The module imports something the runtime does not provide.
+    UnresolvedImport { module: String, field: String },
+    /// The module does not export a function the runtime needs to call.
+    MissingExport { name: String },
+    /// A function the runtime needs to call is exported with an unexpected signature.
+    InvalidExportSignature { name: String, expected: Signature, actual: Signature },
+    /// The entry point's signature does not match any supported calling convention.
+    UnsupportedEntrypoint { name: String, signature: Signature },
+    /// The module's `ntc_abi` section is malformed.
+    InvalidAbiDeclaration(String),
+    /// The module's `ntc_output_schema` section is not a single JSON schema.
+    InvalidOutputSchema(String),
+    /// The module declares an ABI version newer than this runtime supports.
+    UnsupportedAbiVersion { version: u32 },
+    /// The entry point's signature contradicts the output format declared in `ntc_abi`.
+    AbiMismatch { output: OutputFormat, signature: Signature },
+    /// The guest's length prefix claims more output than its output region holds.
+    OutputTooLarge { len: u32, capacity: usize },
+    /// The module's start function failed, for any of the reasons running `exec` could.
+    StartFailed(Box<ExecWasmError>),
+    /// The guest trapped.
+    Trap(TrapKind),
+    /// The guest called `env.abort`.
+    Aborted { message: String },
+    /// The guest called WASI `proc_exit` with a non-zero code.
+    Exited { code: u32 },
+    /// The guest passed a host function more bytes than it accepts in one call.
+    HostCallTooLarge { function: &'static str, len: u32, max: u32 },
+    /// The guest consumed its whole fuel budget before returning.
+    OutOfFuel { limit: u64 },
+    /// A buffer the runtime tried to access lies outside the guest's linear memory.
+    MemoryOutOfBounds { offset: u32, len: usize },
+    /// The linear memory needed for this job is larger than the module or the configured limits allow.
+    MemoryLimitExceeded { required_pages: u32, max_pages: u32 },
+    /// The guest's `alloc` export did not return a usable buffer of `len` bytes.
+    GuestAllocationFailed { len: usize },
+    /// The module could not be instrumented for fuel metering.
+    InstrumentationFailed,
+    /// Any other error reported by wasmi.
     WasmiError(WasmiError),
-    ExecutionError,
-    MemoryError,
 }
 
 impl From<wasmi::Error> for ExecWasmError {
     fn from(err: wasmi::Error) -> Self {
-        Self::WasmiError(err)
+        match err {
+            WasmiError::Validation(message) => Self::ModuleValidation(message),
+            WasmiError::Trap(trap) => Self::Trap(trap.into_kind()),
+            other => Self::WasmiError(other),
+        }
+    }
+}
+
+impl fmt::Display for ExecWasmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ModuleDecode(message) => write!(f, "failed to decode module: {}", message),
+            Self::ModuleValidation(message) => write!(f, "module is invalid: {}", message),
+            Self::UnresolvedImport { module, field } => {
+                write!(f, "module imports `{}.{}`, which is not provided", module, field)
+            }
+            Self::MissingExport { name } => write!(f, "module does not export a function named `{}`", name),
+            Self::InvalidExportSignature { name, expected, actual } => write!(
+                f,
+                "export `{}` has signature {:?}, expected {:?}",
+                name, actual, expected
+            ),
+            Self::UnsupportedEntrypoint { name, signature } => write!(
+                f,
+                "export `{}` has signature {:?}; expected (i32, i32) -> scalar, (i32, i32, i32), (i32, i32, i32, i32) \
+                 or (i32, i32, i32, i32, i32, i32)",
+                name, signature
+            ),
+            Self::InvalidAbiDeclaration(message) => write!(f, "invalid `{}` section: {}", ABI_SECTION, message),
+            Self::InvalidOutputSchema(message) => write!(f, "invalid `{}` section: {}", OUTPUT_SCHEMA_SECTION, message),
+            Self::UnsupportedAbiVersion { version } => write!(
+                f,
+                "module targets ABI version {}, but at most {} is supported",
+                version, ABI_VERSION
+            ),
+            Self::AbiMismatch { output, signature } => write!(
+                f,
+                "module declares {:?} output, but its entry point has signature {:?}",
+                output, signature
+            ),
+            Self::OutputTooLarge { len, capacity } => write!(
+                f,
+                "module claims {} bytes of output, but its output region holds at most {}",
+                len, capacity
+            ),
+            Self::StartFailed(err) => write!(f, "start function failed: {}", err),
+            Self::Trap(kind) => write!(f, "module trapped: {:?}", kind),
+            Self::Aborted { message } => write!(f, "module aborted: {}", message),
+            Self::Exited { code } => write!(f, "module exited with code {}", code),
+            Self::HostCallTooLarge { function, len, max } => write!(
+                f,
+                "module requested {} bytes from `env.{}`, but at most {} are allowed per call",
+                len, function, max
+            ),
+            Self::OutOfFuel { limit } => write!(f, "module ran out of fuel (limit: {})", limit),
+            Self::MemoryOutOfBounds { offset, len } => write!(
+                f,
+                "{} bytes at offset {} are outside the module's memory",
+                len, offset
+            ),
+            Self::MemoryLimitExceeded { required_pages, max_pages } => write!(
+                f,
+                "job needs {} memory pages, but at most {} are allowed",
+                required_pages, max_pages
+            ),
+            Self::GuestAllocationFailed { len } => write!(f, "module failed to allocate {} bytes", len),
+            Self::InstrumentationFailed => write!(f, "failed to instrument module for fuel metering"),
+            Self::WasmiError(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Resource limits applied to a single execution.
+#[derive(Debug, Clone)]
+pub struct ExecLimits {
+    /// Bytes reserved for the guest's stack, static data and heap, in addition to the
+    /// input, schema and output regions.
+    pub heap_reserve: usize,
+    /// Hard cap on the number of 64 KiB linear memory pages, or `None` to only apply the
+    /// module's declared maximum.
+    pub max_memory_pages: Option<u32>,
+    /// Fuel the guest may consume, roughly one unit per executed instruction, or `None` to meter
+    /// without a limit.
+    pub fuel: Option<u64>,
+    /// Bytes of `env.log` output kept per job, and of each of WASI stdout and stderr; later output is dropped.
+    pub max_log_bytes: usize,
+    /// Size of the guest's output region, not counting the length prefix of length-prefixed guests.
+    pub max_output_bytes: usize,
+    /// Seed for the RNG behind `env.random` and WASI `random_get`. The same seed always yields the same bytes.
+    pub rng_seed: [u8; 32],
+}
+
+impl Default for ExecLimits {
+    fn default() -> Self {
+        Self {
+            heap_reserve: DEFAULT_HEAP_RESERVE,
+            max_memory_pages: None,
+            fuel: None,
+            max_log_bytes: DEFAULT_MAX_LOG_BYTES,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+            rng_seed: [0; 32],
+        }
+    }
+}
+
+/// Resources used by a successful execution.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecOutcome {
+    /// Fuel consumed by the guest, including calls to its allocator.
+    pub fuel_used: u64,
+    /// The value returned by `exec`, for guests that return their result as a scalar.
+    pub return_value: Option<RuntimeValue>,
+    /// The output the guest wrote to its output region, exactly as long as it reported; empty for scalar guests.
+    pub output: Vec<u8>,
+    /// Messages passed to `env.log`, one per line.
+    pub log: Vec<u8>,
+    /// Bytes written to WASI stdout.
+    pub stdout: Vec<u8>,
+    /// Bytes written to WASI stderr.
+    pub stderr: Vec<u8>,
+    /// Whether `log`, `stdout` or `stderr` was cut short by `ExecLimits::max_log_bytes`.
+    pub log_truncated: bool,
+}
+
+/// Encoding of the job data passed to the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Json,
+    /// The binary columnar encoding of `ntc_guest::columnar`.
+    Columnar,
+}
+
+/// Encoding of the result produced by the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// JSON written to the output region.
+    Json,
+    /// A single number returned from the entry point.
+    Scalar,
+}
+
+/// ABI a guest declares in its `ntc_abi` custom section.
+///
+/// The section holds one `key=value` pair per line, for example:
+///
+/// ```text
+/// version=2
+/// entry=exec
+/// input=json
+/// output=json
+/// ```
+///
+/// Modules without the section are treated as version 0: the entry point is `exec`, the input is JSON and
+/// the output format follows from the entry point's signature.
+///
+/// From version 2 on, guests writing to an output region start it with the length of their output as a
+/// little-endian `u32`, followed by the output itself. Older guests end their output with a NUL byte, or
+/// fill the whole region.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleAbi {
+    pub version: u32,
+    pub entrypoint: String,
+    pub input: InputFormat,
+    /// `None` for undeclared (version 0) modules.
+    pub output: Option<OutputFormat>,
+}
+
+impl Default for ModuleAbi {
+    fn default() -> Self {
+        Self {
+            version: 0,
+            entrypoint: ENTRYPOINT.to_string(),
+            input: InputFormat::Json,
+            output: None,
+        }
+    }
+}
+
+impl ModuleAbi {
+    /// Reads the ABI declared by `module`, falling back to the version 0 defaults.
+    fn for_module(module: &elements::Module) -> Result<Self, ExecWasmError> {
+        let mut sections = module.custom_sections().filter(|section| section.name() == ABI_SECTION);
+        let payload = match (sections.next(), sections.next()) {
+            (None, _) => return Ok(Self::default()),
+            (Some(section), None) => section.payload(),
+            (Some(_), Some(_)) => {
+                return Err(ExecWasmError::InvalidAbiDeclaration("section appears more than once".to_string()))
+            }
+        };
+        let text = core::str::from_utf8(payload)
+            .map_err(|_| ExecWasmError::InvalidAbiDeclaration("section is not valid UTF-8".to_string()))?;
+
+        let (mut version, mut output) = (None, None);
+        let mut abi = Self::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| ExecWasmError::InvalidAbiDeclaration(format!("expected `key=value`, got `{}`", line)))?;
+            let invalid = || ExecWasmError::InvalidAbiDeclaration(format!("unsupported {} `{}`", key, value));
+            match key {
+                "version" => version = Some(value.parse::<u32>().map_err(|_| invalid())?),
+                "entry" if !value.is_empty() => abi.entrypoint = value.to_string(),
+                "input" => {
+                    abi.input = match value {
+                        "json" => InputFormat::Json,
+                        "columnar" => InputFormat::Columnar,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "output" => {
+                    output = Some(match value {
+                        "json" => OutputFormat::Json,
+                        "scalar" => OutputFormat::Scalar,
+                        _ => return Err(invalid()),
+                    })
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        abi.version = version.ok_or_else(|| ExecWasmError::InvalidAbiDeclaration("missing `version`".to_string()))?;
+        if abi.version == 0 || abi.version > ABI_VERSION {
+            return Err(ExecWasmError::UnsupportedAbiVersion { version: abi.version });
+        }
+        abi.output = Some(output.ok_or_else(|| ExecWasmError::InvalidAbiDeclaration("missing `output`".to_string()))?);
+        Ok(abi)
+    }
+}
+
+/// Reads the ABI declared by the module in `binary` without instantiating it.
+pub fn module_abi(binary: &[u8]) -> Result<ModuleAbi, ExecWasmError> {
+    let parsed: elements::Module = parity_wasm::deserialize_buffer(binary)
+        .map_err(|e: elements::Error| ExecWasmError::ModuleDecode(e.to_string()))?;
+    ModuleAbi::for_module(&parsed)
+}
+
+/// Calling convention of a guest's `exec` export, identified from its signature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CallingConvention {
+    /// `exec(data_ptr, data_len) -> value`: the result is the returned scalar.
+    Scalar,
+    /// `exec(data_ptr, data_len, output_ptr)`: the result is written to the output buffer, whose size the guest
+    /// is not told. Only kept for guests built before `OutputBuffer`.
+    OutputPointer,
+    /// `exec(data_ptr, data_len, output_ptr, output_len)`: the result is written to the output buffer, which
+    /// holds `output_len` bytes.
+    OutputBuffer,
+    /// `exec(data_ptr, data_len, schema_ptr, schema_len, output_ptr, output_len)`: the guest also
+    /// receives the schema and the size of the output buffer.
+    DataAndSchema,
+}
+
+impl CallingConvention {
+    fn for_signature(signature: &Signature) -> Option<Self> {
+        if !signature.params().iter().all(|param| *param == ValueType::I32) {
+            return None;
+        }
+        match (signature.params().len(), signature.return_type()) {
+            (2, Some(_)) => Some(CallingConvention::Scalar),
+            (3, None) => Some(CallingConvention::OutputPointer),
+            (4, None) => Some(CallingConvention::OutputBuffer),
+            (6, None) => Some(CallingConvention::DataAndSchema),
+            _ => None,
+        }
     }
 }
 
+/// Reasons a host function stops the guest.
+#[derive(Debug)]
+enum HostTrap {
+    /// Raised by `env.gas` once the job's fuel is exhausted.
+    OutOfFuel,
+    /// Raised by `env.abort` with the guest's message.
+    Abort(String),
+    /// Raised when a single call asks for more bytes than the host function allows.
+    TooLarge { function: &'static str, len: u32, max: u32 },
+    /// Raised by WASI `proc_exit` with the guest's exit code.
+    Exit(u32),
+}
+
+impl fmt::Display for HostTrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostTrap::OutOfFuel => write!(f, "out of fuel"),
+            HostTrap::Abort(message) => write!(f, "aborted: {}", message),
+            HostTrap::TooLarge { function, len, max } => {
+                write!(f, "`{}` called with {} bytes (max {})", function, len, max)
+            }
+            HostTrap::Exit(code) => write!(f, "exited with code {}", code),
+        }
+    }
+}
+
+impl HostError for HostTrap {}
+
+/// Per-job host state backing the functions the guest imports from `env`.
+struct HostExternals {
+    memory: MemoryRef,
+    fuel_limit: Option<u64>,
+    fuel_used: u64,
+    log: Vec<u8>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    max_log_bytes: usize,
+    log_truncated: bool,
+    rng: ChaCha20Rng,
+}
+
+impl HostExternals {
+    fn new(limits: &ExecLimits, memory: &MemoryRef) -> Self {
+        Self {
+            memory: memory.clone(),
+            fuel_limit: limits.fuel,
+            fuel_used: 0,
+            log: Vec::new(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            max_log_bytes: limits.max_log_bytes,
+            log_truncated: false,
+            rng: ChaCha20Rng::from_seed(limits.rng_seed),
+        }
+    }
+
+    fn is_out_of_fuel(&self) -> bool {
+        self.fuel_limit.map_or(false, |limit| self.fuel_used > limit)
+    }
+
+    /// Returns the reason a host function stopped the guest, if `err` is such a trap.
+    fn host_trap(err: &WasmiError) -> Option<&HostTrap> {
+        match err {
+            WasmiError::Trap(trap) => match trap.kind() {
+                TrapKind::Host(host_error) => host_error.downcast_ref::<HostTrap>(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Completes an invocation, treating a WASI `proc_exit(0)` as a normal return without a value.
+    fn finish(&self, result: Result<Option<RuntimeValue>, WasmiError>) -> Result<Option<RuntimeValue>, ExecWasmError> {
+        match result {
+            Ok(value) => Ok(value),
+            Err(err) if matches!(Self::host_trap(&err), Some(HostTrap::Exit(0))) => Ok(None),
+            Err(err) => Err(self.failure(err)),
+        }
+    }
+
+    /// Returns the error to report for a failed invocation: `OutOfFuel` if the guest ran out of fuel, the
+    /// reason a host function stopped it, or otherwise `err`.
+    fn failure(&self, err: WasmiError) -> ExecWasmError {
+        if let Some(limit) = self.fuel_limit {
+            if self.is_out_of_fuel() {
+                return ExecWasmError::OutOfFuel { limit };
+            }
+        }
+        match Self::host_trap(&err) {
+            Some(HostTrap::Abort(message)) => ExecWasmError::Aborted { message: message.clone() },
+            Some(HostTrap::TooLarge { function, len, max }) => ExecWasmError::HostCallTooLarge {
+                function,
+                len: *len,
+                max: *max,
+            },
+            Some(HostTrap::Exit(code)) => ExecWasmError::Exited { code: *code },
+            _ => err.into(),
+        }
+    }
+
+    fn charge_fuel(&mut self, amount: u64) -> Result<(), Trap> {
+        self.fuel_used = self.fuel_used.saturating_add(amount);
+        if self.is_out_of_fuel() {
+            return Err(Trap::new(TrapKind::Host(Box::new(HostTrap::OutOfFuel))));
+        }
+        Ok(())
+    }
+
+    /// Reads the `(ptr, len)` message passed to `log` or `abort`, keeping at most `MAX_MESSAGE_LEN` bytes.
+    ///
+    /// Like the other host functions, this charges one unit of fuel per byte it touches.
+    fn read_message(&mut self, args: &RuntimeArgs) -> Result<Vec<u8>, Trap> {
+        let ptr: u32 = args.nth_checked(0)?;
+        let len: u32 = args.nth_checked(1)?;
+        let len = cmp::min(len, MAX_MESSAGE_LEN);
+        self.charge_fuel(len.into())?;
+        self.memory
+            .get(ptr, len as usize)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+    }
+
+    /// Appends `message` as one line of the job's log, dropping it once the log is full.
+    fn log(&mut self, message: &[u8]) {
+        let remaining = self.max_log_bytes.saturating_sub(self.log.len());
+        if message.len() + 1 > remaining {
+            self.log_truncated = true;
+            return;
+        }
+        self.log.extend_from_slice(message);
+        self.log.push(b'\n');
+    }
+
+    /// Fills `len` bytes of guest memory at `ptr` from the job's RNG. Callers enforce `MAX_RANDOM_LEN`.
+    fn fill_random(&mut self, ptr: u32, len: u32) -> Result<(), Trap> {
+        self.charge_fuel(len.into())?;
+        let mut bytes = vec![0u8; len as usize];
+        self.rng.fill_bytes(&mut bytes);
+        self.memory
+            .set(ptr, &bytes)
+            .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+    }
+
+    fn outcome(&self) -> ExecOutcome {
+        ExecOutcome {
+            fuel_used: self.fuel_used,
+            return_value: None,
+            output: Vec::new(),
+            log: self.log.clone(),
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+            log_truncated: self.log_truncated,
+        }
+    }
+}
+
+impl Externals for HostExternals {
+    fn invoke_index(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+        match index {
+            GAS_FUNC_INDEX => {
+                let amount: u32 = args.nth_checked(0)?;
+                self.charge_fuel(amount.into())?;
+                Ok(None)
+            }
+            LOG_FUNC_INDEX => {
+                let message = self.read_message(&args)?;
+                self.log(&message);
+                Ok(None)
+            }
+            ABORT_FUNC_INDEX => {
+                let message = self.read_message(&args)?;
+                let message = String::from_utf8_lossy(&message).into_owned();
+                Err(Trap::new(TrapKind::Host(Box::new(HostTrap::Abort(message)))))
+            }
+            RANDOM_FUNC_INDEX => {
+                let ptr: u32 = args.nth_checked(0)?;
+                let len: u32 = args.nth_checked(1)?;
+                if len > MAX_RANDOM_LEN {
+                    return Err(Trap::new(TrapKind::Host(Box::new(HostTrap::TooLarge {
+                        function: RANDOM_IMPORT,
+                        len,
+                        max: MAX_RANDOM_LEN,
+                    }))));
+                }
+                self.fill_random(ptr, len)?;
+                Ok(None)
+            }
+            index if wasi::handles(index) => self.invoke_wasi(index, args),
+            _ => Err(Trap::new(TrapKind::UnexpectedSignature)),
+        }
+    }
+}
+
+/// Returns the `(initial, maximum)` page counts the module declares for its `env.memory` import.
+fn imported_memory_limits(module: &elements::Module) -> Option<(u32, Option<u32>)> {
+    module.import_section()?.entries().iter().find_map(|entry| match entry.external() {
+        External::Memory(memory_type) if entry.module() == "env" && entry.field() == "memory" => {
+            let limits = memory_type.limits();
+            Some((limits.initial(), limits.maximum()))
+        }
+        _ => None,
+    })
+}
+
+/// How the job's buffers are placed in the guest's linear memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// Buffers are packed back to back from offset 0. Used for legacy modules without an allocator.
+    Fixed,
+    /// Buffers are obtained from the guest's `alloc` export, so they never overlap its static data, stack or heap.
+    GuestAllocator,
+}
+
+impl Layout {
+    fn for_module(module: &elements::Module) -> Self {
+        let exports_alloc = module.export_section().map_or(false, |exports| {
+            exports
+                .entries()
+                .iter()
+                .any(|entry| entry.field() == ALLOC_EXPORT && matches!(entry.internal(), Internal::Function(_)))
+        });
+        if exports_alloc {
+            Layout::GuestAllocator
+        } else {
+            Layout::Fixed
+        }
+    }
+}
+
+/// Allocates a linear memory for a job whose buffers take up `payload_len` bytes.
+///
+/// The module's declared minimum is always honoured. The module's declared maximum and
+/// `limits.max_memory_pages` both cap the initial size as well as any later `memory.grow`.
+/// With the `Fixed` layout the memory is sized up front for the payload plus the heap reserve;
+/// with the `GuestAllocator` layout the guest grows it on demand, but the job is still rejected
+/// early if it could never fit.
+fn alloc_memory(
+    module: &elements::Module,
+    payload_len: usize,
+    layout: Layout,
+    limits: &ExecLimits,
+) -> Result<MemoryRef, ExecWasmError> {
+    let (module_initial, module_maximum) = imported_memory_limits(module).unwrap_or((0, None));
+
+    let required_bytes = payload_len.saturating_add(limits.heap_reserve);
+    let Pages(required_pages) = Bytes(required_bytes).round_up_to();
+    let required_pages = cmp::max(required_pages.try_into().unwrap_or(u32::MAX), module_initial);
+
+    let max_pages = [module_maximum, limits.max_memory_pages]
+        .iter()
+        .flatten()
+        .fold(MAX_MEMORY_PAGES, |max, &cap| cmp::min(max, cap));
+    if required_pages > max_pages {
+        return Err(ExecWasmError::MemoryLimitExceeded { required_pages, max_pages });
+    }
+
+    let initial_pages = match layout {
+        Layout::Fixed => required_pages,
+        Layout::GuestAllocator => module_initial,
+    };
+    Ok(MemoryInstance::alloc(
+        Pages(initial_pages as usize),
+        Some(Pages(max_pages as usize)),
+    )?)
+}
+
+/// Hands out regions of guest memory for the job's buffers according to the module's `Layout`.
+struct BufferPlacer<'a> {
+    instance: &'a ModuleRef,
+    memory: &'a MemoryRef,
+    layout: Layout,
+    next_offset: u32,
+    allocations: Vec<(u32, usize)>,
+}
+
+impl<'a> BufferPlacer<'a> {
+    fn new(instance: &'a ModuleRef, memory: &'a MemoryRef, layout: Layout) -> Self {
+        Self {
+            instance,
+            memory,
+            layout,
+            next_offset: 0,
+            allocations: Vec::new(),
+        }
+    }
+
+    /// Reserves `len` bytes of guest memory and returns their offset.
+    fn reserve(&mut self, externals: &mut HostExternals, len: usize) -> Result<u32, ExecWasmError> {
+        match self.layout {
+            Layout::Fixed => {
+                let offset = self.next_offset;
+                self.next_offset += len as u32;
+                Ok(offset)
+            }
+            Layout::GuestAllocator => {
+                check_export(self.instance, ALLOC_EXPORT, &[ValueType::I32], Some(ValueType::I32))?;
+                let ptr = match self
+                    .instance
+                    .invoke_export(ALLOC_EXPORT, &[RuntimeValue::I32(len as i32)], externals)
+                    .map_err(|e| externals.failure(e))?
+                {
+                    Some(RuntimeValue::I32(ptr)) if ptr != 0 || len == 0 => ptr as u32,
+                    _ => return Err(ExecWasmError::GuestAllocationFailed { len }),
+                };
+                self.allocations.push((ptr, len));
+                Ok(ptr)
+            }
+        }
+    }
+
+    /// Reserves room for `data`, copies it into guest memory and returns its offset.
+    fn write(&mut self, externals: &mut HostExternals, data: &[u8]) -> Result<u32, ExecWasmError> {
+        let offset = self.reserve(externals, data.len())?;
+        self.memory
+            .set(offset, data)
+            .map_err(|_| ExecWasmError::MemoryOutOfBounds { offset, len: data.len() })?;
+        Ok(offset)
+    }
+
+    /// Returns buffers obtained from the guest allocator, if the guest exports `dealloc`.
+    fn release(self, externals: &mut HostExternals) -> Result<(), ExecWasmError> {
+        if self.instance.export_by_name(DEALLOC_EXPORT).is_none() {
+            return Ok(());
+        }
+        check_export(self.instance, DEALLOC_EXPORT, &[ValueType::I32, ValueType::I32], None)?;
+        for (ptr, len) in self.allocations {
+            self.instance
+                .invoke_export(
+                    DEALLOC_EXPORT,
+                    &[RuntimeValue::I32(ptr as i32), RuntimeValue::I32(len as i32)],
+                    externals,
+                )
+                .map_err(|e| externals.failure(e))?;
+        }
+        Ok(())
+    }
+}
+
+/// A module that has been decoded, validated and instrumented, ready to be executed any number of times.
+pub struct PreparedModule {
+    parsed: elements::Module,
+    abi: ModuleAbi,
+    output_schema: Option<serde_json::Value>,
+    module: wasmi::Module,
+}
+
+impl PreparedModule {
+    /// The ABI the module declares.
+    pub fn abi(&self) -> &ModuleAbi {
+        &self.abi
+    }
+
+    /// The schema every result of the module must match, if it declares one.
+    pub fn output_schema(&self) -> Option<&serde_json::Value> {
+        self.output_schema.as_ref()
+    }
+}
+
+/// Reads the JSON schema in the module's `ntc_output_schema` section, if it has one.
+fn output_schema(module: &elements::Module) -> Result<Option<serde_json::Value>, ExecWasmError> {
+    let mut sections = module.custom_sections().filter(|section| section.name() == OUTPUT_SCHEMA_SECTION);
+    let payload = match (sections.next(), sections.next()) {
+        (None, _) => return Ok(None),
+        (Some(section), None) => section.payload(),
+        (Some(_), Some(_)) => {
+            return Err(ExecWasmError::InvalidOutputSchema("section appears more than once".to_string()))
+        }
+    };
+    match serde_json::from_slice(payload) {
+        Ok(schema @ serde_json::Value::Object(_)) | Ok(schema @ serde_json::Value::Bool(_)) => Ok(Some(schema)),
+        Ok(_) => Err(ExecWasmError::InvalidOutputSchema("schema must be an object or a boolean".to_string())),
+        Err(e) => Err(ExecWasmError::InvalidOutputSchema(e.to_string())),
+    }
+}
+
+/// Deserializes `binary`, reads its declared ABI and output schema, instruments it for fuel metering and converts it into a
+/// `wasmi::Module`, keeping the parsed module around for inspection.
+///
+/// Every module is metered so that fuel use can be reported even when no limit is configured.
+pub fn prepare_module(binary: &[u8]) -> Result<PreparedModule, ExecWasmError> {
+    let parsed: elements::Module = parity_wasm::deserialize_buffer(binary)
+        .map_err(|e: elements::Error| ExecWasmError::ModuleDecode(e.to_string()))?;
+    let abi = ModuleAbi::for_module(&parsed)?;
+    let output_schema = output_schema(&parsed)?;
+    check_imports(&parsed)?;
+    let metered = gas_metering::inject(parsed, &ConstantCostRules::default(), "env")
+        .map_err(|_| ExecWasmError::InstrumentationFailed)?;
+    let module = wasmi::Module::from_parity_wasm_module(metered.clone())?;
+    Ok(PreparedModule {
+        parsed: metered,
+        abi,
+        output_schema,
+        module,
+    })
+}
+
+/// Checks that every import of `module` is provided by `CustomImportResolver` or `wasi::WasiResolver`.
+fn check_imports(module: &elements::Module) -> Result<(), ExecWasmError> {
+    let entries = match module.import_section() {
+        Some(section) => section.entries(),
+        None => return Ok(()),
+    };
+    for entry in entries {
+        let provided = match (entry.module(), entry.external()) {
+            ("env", External::Memory(_)) => entry.field() == "memory",
+            ("env", External::Function(_)) => host_function(entry.field()).is_some(),
+            (module_name, External::Function(type_index)) if module_name == wasi::MODULE => {
+                let signature = module
+                    .type_section()
+                    .and_then(|types| types.types().get(*type_index as usize));
+                let returns_i32 = match signature {
+                    Some(elements::Type::Function(function)) => function.results() == [elements::ValueType::I32],
+                    None => false,
+                };
+                wasi::is_provided(entry.field(), returns_i32)
+            }
+            _ => false,
+        };
+        if !provided {
+            return Err(ExecWasmError::UnresolvedImport {
+                module: entry.module().to_string(),
+                field: entry.field().to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Looks up the function exported as `name`.
+fn exported_func(instance: &ModuleRef, name: &str) -> Result<FuncRef, ExecWasmError> {
+    instance
+        .export_by_name(name)
+        .and_then(|export| export.as_func().cloned())
+        .ok_or_else(|| ExecWasmError::MissingExport { name: name.to_string() })
+}
+
+/// Looks up the export `name` and checks that it is a function with the given signature.
+fn check_export(
+    instance: &ModuleRef,
+    name: &str,
+    params: &'static [ValueType],
+    return_type: Option<ValueType>,
+) -> Result<(), ExecWasmError> {
+    let func = exported_func(instance, name)?;
+    let expected = Signature::new(params, return_type);
+    if *func.signature() != expected {
+        return Err(ExecWasmError::InvalidExportSignature {
+            name: name.to_string(),
+            expected,
+            actual: func.signature().clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Identifies the calling convention of the module's entry point before anything is invoked, checking it
+/// against the declared output format.
+fn entrypoint_convention(instance: &ModuleRef, abi: &ModuleAbi) -> Result<CallingConvention, ExecWasmError> {
+    let func = exported_func(instance, &abi.entrypoint)?;
+    let signature = func.signature();
+    let convention = CallingConvention::for_signature(signature).ok_or_else(|| {
+        ExecWasmError::UnsupportedEntrypoint {
+            name: abi.entrypoint.clone(),
+            signature: signature.clone(),
+        }
+    })?;
+    match abi.output {
+        Some(output @ OutputFormat::Scalar) if convention != CallingConvention::Scalar => {
+            Err(ExecWasmError::AbiMismatch { output, signature: signature.clone() })
+        }
+        Some(output @ OutputFormat::Json) if convention == CallingConvention::Scalar => {
+            Err(ExecWasmError::AbiMismatch { output, signature: signature.clone() })
+        }
+        _ => Ok(convention),
+    }
+}
+
+/// Returns the signature and host function index of the function `field_name` in the `env` module.
+fn host_function(field_name: &str) -> Option<(Signature, usize)> {
+    const PTR_LEN: &[ValueType] = &[ValueType::I32, ValueType::I32];
+    let (params, index): (&'static [ValueType], usize) = match field_name {
+        _ if field_name == GAS_IMPORT => (&[ValueType::I32], GAS_FUNC_INDEX),
+        _ if field_name == LOG_IMPORT => (PTR_LEN, LOG_FUNC_INDEX),
+        _ if field_name == ABORT_IMPORT => (PTR_LEN, ABORT_FUNC_INDEX),
+        _ if field_name == RANDOM_IMPORT => (PTR_LEN, RANDOM_FUNC_INDEX),
+        _ => return None,
+    };
+    Some((Signature::new(params, None), index))
+}
+
 // This struct will serve as our custom import resolver.
 struct CustomImportResolver {
     memory: MemoryRef,
@@ -48,119 +916,156 @@ impl ModuleImportResolver for CustomImportResolver {
             )
         }
     }
-}
-
-pub fn exec_wasm_with_data(
-    binary: &[u8],
-    data: &[u8],
-    result_buffer: &mut [u8],
-) -> Result<(), ExecWasmError> {
-    let module = wasmi::Module::from_buffer(binary)?;
-
-    // TODO: Calculate the memory size always be larger than `data`
-    let mem_instance = MemoryInstance::alloc(Pages(100), None)?;
-
-    // TODO: Error Handling
-    // mem_instance.set(0, data).map_err(|_| ExecWasmError::MemoryError)?;
-    // Adjust the offset for input and output pointers.
-    let input_offset = 0; // Starting point for the input in memory.
-    let result_buffer_offset = data.len() as u32 + 4; // Reserve space after input data; ensure it does not overlap.
-
-    // Write input data to memory.
-    mem_instance.set(input_offset, data)?;
-
-    // let externals = FuncInstance::alloc_host(
-    //     Signature::new(&[ValueType::I32][..], Some(ValueType::I32)),
-    //     0,
-    // );
-
-    let resolver = CustomImportResolver {
-        memory: mem_instance.clone(),
-    };
-
-    // let imports = [ExternVal::Memory(mem_instance)];
-
-    let instance = ModuleInstance::new(&module, &ImportsBuilder::new().with_resolver("env", &resolver))
-        .map_err(|_| ExecWasmError::ExecutionError)?
-        .assert_no_start();
-
-    let params = [
-        RuntimeValue::I32(input_offset as i32),
-        RuntimeValue::I32(data.len() as i32),
-        RuntimeValue::I32(result_buffer_offset as i32),
-       // RuntimeValue::I32(output_buffer_size as i32)
-    ];
 
-    // Call the `exec` function.
-    instance.invoke_export(ENTRYPOINT, &params, &mut NopExternals)
-        .map_err(|_| ExecWasmError::ExecutionError)?;
-
-    // Read the result from memory.
-    mem_instance.get_into((result_buffer_offset as usize).try_into().unwrap(), result_buffer)
-        .map_err(|_| ExecWasmError::MemoryError)?;
+    fn resolve_func(&self, field_name: &str, _signature: &Signature) -> Result<FuncRef, wasmi::Error> {
+        match host_function(field_name) {
+            Some((signature, index)) => Ok(FuncInstance::alloc_host(signature, index)),
+            None => Err(wasmi::Error::Instantiation(format!("No such function: {}", field_name))),
+        }
+    }
+}
 
-    Ok(())
+/// Runs `binary` against `data` without a schema. See `exec_wasm_with_data_and_schema`.
+pub fn exec_wasm_with_data(binary: &[u8], data: &[u8], limits: &ExecLimits) -> Result<ExecOutcome, ExecWasmError> {
+    exec_wasm_with_data_and_schema(binary, data, &[], limits)
 }
 
 pub fn exec_wasm(binary: &[u8]) -> Result<Option<RuntimeValue>, ExecWasmError> {
     let module = wasmi::Module::from_buffer(binary)?;
-    // TODO: This panics. We probably want to run start functions
-    let instance = ModuleInstance::new(&module, &ImportsBuilder::default())?.assert_no_start();
+    let instance = ModuleInstance::new(&module, &ImportsBuilder::default())?
+        .run_start(&mut NopExternals)
+        .map_err(|trap| ExecWasmError::StartFailed(Box::new(WasmiError::Trap(trap).into())))?;
     Ok(instance.invoke_export(ENTRYPOINT, &[], &mut NopExternals)?)
 }
 
+/// Runs `binary` against `data` and `schema`, returning the guest's result in `ExecOutcome`.
+///
+/// The entry point is `exec` unless the module's `ntc_abi` section names another one, and the arguments
+/// passed to it depend on its signature:
+/// - `(i32, i32) -> scalar` receives the data only and returns its result in `ExecOutcome::return_value`;
+/// - `(i32, i32, i32)` receives the data and a pointer to the output region;
+/// - `(i32, i32, i32, i32)` receives the data and the output region with its length;
+/// - `(i32, i32, i32, i32, i32, i32)` receives the data, the schema and the output region with its length.
+///
+/// The output region holds `limits.max_output_bytes`, plus the length prefix for guests that write one (see
+/// `ModuleAbi`); their output is returned in `ExecOutcome::output`.
+///
+/// Any other signature, or one contradicting the declared output format, is rejected before the guest runs.
 pub fn exec_wasm_with_data_and_schema(
     binary: &[u8],
-    input_data_ptr: *const u8,
-    input_data_len: usize,
-    input_schema_ptr: *const u8,
-    input_schema_len: usize,
-    output_ptr: *mut u8,
-    output_len: usize,
-) -> Result<(), ExecWasmError> {
-    let module = wasmi::Module::from_buffer(binary)?;
-
-    // Allocate memory large enough to accommodate data, schema, and output
-    let mem_instance = MemoryInstance::alloc(Pages(100), None)?;
-
-    // Write input data and schema to WASM module's memory
-    let data_slice = unsafe { slice::from_raw_parts(input_data_ptr, input_data_len) };
-    let schema_slice = unsafe { slice::from_raw_parts(input_schema_ptr, input_schema_len) };
+    data: &[u8],
+    schema: &[u8],
+    limits: &ExecLimits,
+) -> Result<ExecOutcome, ExecWasmError> {
+    exec_prepared_module(&prepare_module(binary)?, data, schema, limits)
+}
 
-    let input_offset = 0;
-    mem_instance.set(input_offset, data_slice)?;
+/// Runs a module returned by `prepare_module`. See `exec_wasm_with_data_and_schema`.
+pub fn exec_prepared_module(
+    prepared: &PreparedModule,
+    data: &[u8],
+    schema: &[u8],
+    limits: &ExecLimits,
+) -> Result<ExecOutcome, ExecWasmError> {
+    let PreparedModule { parsed, abi, module, .. } = prepared;
 
-    let schema_offset = input_data_len as u32; // Adjust offset for schema data
-    mem_instance.set(schema_offset, schema_slice)?;
+    let layout = Layout::for_module(parsed);
+    let length_prefixed = abi.version >= LENGTH_PREFIXED_ABI_VERSION;
+    let output_len = if length_prefixed {
+        limits.max_output_bytes.saturating_add(OUTPUT_LENGTH_PREFIX)
+    } else {
+        limits.max_output_bytes
+    };
 
-    let result_buffer_offset = schema_offset + input_schema_len as u32; // Adjust offset for result buffer
+    // Allocate memory large enough to accommodate data, schema, and output
+    let mem_instance = alloc_memory(parsed, data.len() + schema.len() + output_len, layout, limits)?;
 
     let resolver = CustomImportResolver {
         memory: mem_instance.clone(),
     };
 
-    let instance = ModuleInstance::new(&module, &ImportsBuilder::new().with_resolver("env", &resolver))
-        .map_err(|_| ExecWasmError::ExecutionError)?
-        .assert_no_start();
+    // The start function runs under the job's limits, and its fuel counts towards the job.
+    let mut externals = HostExternals::new(limits, &mem_instance);
+    let imports = ImportsBuilder::new()
+        .with_resolver("env", &resolver)
+        .with_resolver(wasi::MODULE, &wasi::WasiResolver);
+    let instance = ModuleInstance::new(module, &imports)?
+        .run_start(&mut externals)
+        .map_err(|trap| ExecWasmError::StartFailed(Box::new(externals.failure(WasmiError::Trap(trap)))))?;
+    let convention = entrypoint_convention(&instance, abi)?;
 
-    // Adjust parameters to pass to WASM function
-    let params = [
+    // Write input data (and schema, if the guest takes one) to WASM module's memory and reserve the result buffer
+    let mut placer = BufferPlacer::new(&instance, &mem_instance, layout);
+    let input_offset = placer.write(&mut externals, data)?;
+    let mut params = vec![
         RuntimeValue::I32(input_offset as i32),
-        RuntimeValue::I32(input_data_len as i32),
-        RuntimeValue::I32(schema_offset as i32),
-        RuntimeValue::I32(input_schema_len as i32),
-        RuntimeValue::I32(result_buffer_offset as i32),
-        RuntimeValue::I32(output_len as i32),
+        RuntimeValue::I32(data.len() as i32),
     ];
+    if convention == CallingConvention::DataAndSchema {
+        let schema_offset = placer.write(&mut externals, schema)?;
+        params.extend_from_slice(&[
+            RuntimeValue::I32(schema_offset as i32),
+            RuntimeValue::I32(schema.len() as i32),
+        ]);
+    }
+    let result_buffer_offset = match convention {
+        CallingConvention::Scalar => None,
+        CallingConvention::OutputPointer => {
+            let offset = placer.reserve(&mut externals, output_len)?;
+            params.push(RuntimeValue::I32(offset as i32));
+            Some(offset)
+        }
+        CallingConvention::OutputBuffer | CallingConvention::DataAndSchema => {
+            let offset = placer.reserve(&mut externals, output_len)?;
+            params.extend_from_slice(&[RuntimeValue::I32(offset as i32), RuntimeValue::I32(output_len as i32)]);
+            Some(offset)
+        }
+    };
 
-    instance.invoke_export(ENTRYPOINT, &params, &mut NopExternals)
-        .map_err(|_| ExecWasmError::ExecutionError)?;
+    let result = instance.invoke_export(&abi.entrypoint, &params, &mut externals);
+    let return_value = externals.finish(result)?;
 
-    // Read the result from memory into the provided output buffer
-    let output_slice = unsafe { slice::from_raw_parts_mut(output_ptr, output_len) };
-    mem_instance.get_into((result_buffer_offset as usize).try_into().unwrap(), output_slice)
-        .map_err(|_| ExecWasmError::MemoryError)?;
-    Ok(())
+    // Copy the result out of the output region before the guest's buffers are released
+    let output = match result_buffer_offset {
+        Some(offset) => read_output(&mem_instance, offset, output_len, length_prefixed)?,
+        None => Vec::new(),
+    };
+
+    placer.release(&mut externals)?;
+    Ok(ExecOutcome {
+        return_value,
+        output,
+        ..externals.outcome()
+    })
+}
+
+/// Reads the guest's output from the `len`-byte output region at `offset`.
+///
+/// A length-prefixed region yields exactly as many bytes as its prefix claims, which may not exceed the rest of
+/// the region. Otherwise the output ends at the first NUL byte, or with the region.
+fn read_output(memory: &MemoryRef, offset: u32, len: usize, length_prefixed: bool) -> Result<Vec<u8>, ExecWasmError> {
+    if !length_prefixed {
+        let mut output = memory
+            .get(offset, len)
+            .map_err(|_| ExecWasmError::MemoryOutOfBounds { offset, len })?;
+        let end = output.iter().position(|&byte| byte == 0).unwrap_or(output.len());
+        output.truncate(end);
+        return Ok(output);
+    }
+
+    let mut prefix = [0u8; OUTPUT_LENGTH_PREFIX];
+    memory
+        .get_into(offset, &mut prefix)
+        .map_err(|_| ExecWasmError::MemoryOutOfBounds { offset, len: OUTPUT_LENGTH_PREFIX })?;
+    let claimed = u32::from_le_bytes(prefix);
+    let capacity = len - OUTPUT_LENGTH_PREFIX;
+    if claimed as usize > capacity {
+        return Err(ExecWasmError::OutputTooLarge { len: claimed, capacity });
+    }
+    let start = offset.saturating_add(OUTPUT_LENGTH_PREFIX as u32);
+    memory
+        .get(start, claimed as usize)
+        .map_err(|_| ExecWasmError::MemoryOutOfBounds { offset: start, len: claimed as usize })
 }
 
 
@@ -172,83 +1077,478 @@ mod tests {
     use wabt;
 
     #[test]
-    fn exec_wasm_works() {
-        // let wasm_binary = wabt::wat2wasm(
-        //     r#"
-        //     (module
-        //         (import "env" "memory" (memory 1))
-        //         (func $exec (export "exec")
-        //             (param $input_ptr i32) (param $input_len i32) (param $output_ptr i32)
-        //             ;; Example: Write the value 42 to the location pointed by $output_ptr.
-        //             (i32.store (get_local $output_ptr) (i32.const 42))
-        //         )
-        //     )
-        //     "#
-        // ).expect("Failed to compile WAT to WASM");
+    fn exec_wasm_reads_length_prefixed_output() {
+        // Writes the f32s [1.0, 2.0, 3.0] after a length prefix claiming as many bytes as the input's first byte.
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec")
+                    (param $input_ptr i32) (param $input_len i32) (param $output_ptr i32)
+                    (i32.store (get_local $output_ptr) (i32.load8_u (get_local $input_ptr)))
+                    (f32.store (i32.add (get_local $output_ptr) (i32.const 4)) (f32.const 1))
+                    (f32.store (i32.add (get_local $output_ptr) (i32.const 8)) (f32.const 2))
+                    (f32.store (i32.add (get_local $output_ptr) (i32.const 12)) (f32.const 3))
+                )
+            )
+        "#).unwrap();
+        let wasm_binary = with_abi_section(wasm_binary, "version=2\nentry=exec\ninput=json\noutput=json\n");
+        let limits = ExecLimits {
+            max_output_bytes: 12,
+            ..ExecLimits::default()
+        };
+
+        // Output containing NUL bytes comes back whole, and no longer than claimed.
+        let outcome = exec_wasm_with_data(&wasm_binary, &[12], &limits).unwrap();
+        assert_eq!(outcome.output, [0, 0, 128, 63, 0, 0, 0, 64, 0, 0, 64, 64]);
+        let outcome = exec_wasm_with_data(&wasm_binary, &[6], &limits).unwrap();
+        assert_eq!(outcome.output, [0, 0, 128, 63, 0, 0]);
+
+        let err = exec_wasm_with_data(&wasm_binary, &[13], &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::OutputTooLarge { len: 13, capacity: 12 }));
+    }
+
+    #[test]
+    fn exec_wasm_reads_nul_terminated_output_of_legacy_guests() {
+        // Writes "abc", a NUL and then "d"; without an ABI section the output ends at the NUL.
         let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec")
+                    (param $input_ptr i32) (param $input_len i32) (param $output_ptr i32)
+                    (i32.store (get_local $output_ptr) (i32.const 0x00636261))
+                    (i32.store8 (i32.add (get_local $output_ptr) (i32.const 4)) (i32.const 0x64))
+                )
+            )
+        "#).unwrap();
+
+        let outcome = exec_wasm_with_data(&wasm_binary, &[], &ExecLimits::default()).unwrap();
+        assert_eq!(outcome.output, b"abc");
+    }
+
+    /// Copies the first input byte to the output; used to exercise memory sizing.
+    const COPY_FIRST_BYTE_WAT: &str = r#"
         (module
             (import "env" "memory" (memory 1))
             (func $exec (export "exec")
                 (param $input_ptr i32) (param $input_len i32) (param $output_ptr i32)
-                ;; Assuming serialization of [1.0, 2.0, 3.0] f32 values into bytes is done externally
-                ;; and we're directly storing these bytes into memory for demonstration.
-                
-                ;; Example byte representation of 1.0 as f32 in little-endian
-                (i32.store8 (get_local $output_ptr) (i32.const 0x00))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 1)) (i32.const 0x00))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 2)) (i32.const 0x80))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 3)) (i32.const 0x3F))
-                
-                ;; Increment output_ptr by 4 bytes for the next f32 value
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 4)) (i32.const 0x00))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 5)) (i32.const 0x00))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 6)) (i32.const 0x00))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 7)) (i32.const 0x40))
-                
-                ;; Increment output_ptr by 8 bytes for the next f32 value
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 8)) (i32.const 0x00))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 9)) (i32.const 0x00))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 10)) (i32.const 0x40))
-                (i32.store8 (i32.add (get_local $output_ptr) (i32.const 11)) (i32.const 0x40))
+                (i32.store8 (get_local $output_ptr) (i32.load8_u (get_local $input_ptr)))
             )
         )
-    "#).expect("Failed to compile WAT to WASM");
-        // Prepare data and a buffer for the result.
-        let input_data: Vec<u8> = vec![1, 2, 3, 4, 5, 6, 7, 8]; // Example input.
-        let mut result_buffer: Vec<u8> = vec![0; 12]; // Buffer to hold the result.
+    "#;
+
+    #[test]
+    fn exec_wasm_sizes_memory_for_large_inputs() {
+        let wasm_binary = wabt::wat2wasm(COPY_FIRST_BYTE_WAT).unwrap();
+        // Larger than the 100 pages that used to be allocated unconditionally.
+        let input_data = vec![7u8; 8 * 1024 * 1024];
 
-        exec_wasm_with_data(&wasm_binary, &input_data, &mut result_buffer)
+        let outcome = exec_wasm_with_data(&wasm_binary, &input_data, &ExecLimits::default())
             .expect("WASM execution failed");
+        assert_eq!(outcome.output, [7]);
+    }
 
-        println!("result buffer {:?}", &result_buffer);
-        // Verify that the result buffer contains the expected value
-        assert_eq!(&result_buffer, &[0, 0, 128, 63, 0, 0, 0, 64, 0, 0, 64, 64], "Unexpected result in the buffer");
-        // let res = exec_wasm(&binary).unwrap();
-        // assert_eq!(res, Some(RuntimeValue::I32(1337)))
-    }
-
-    // #[test]
-    // fn exec_wasm_with_data_works() {
-    //     let binary = wabt::wat2wasm(
-    //         r#"
-    //         (module
-    //         (type $t0 (func (param i32 i32) (result i32)))
-    //         (import "env" "memory" (memory $env.memory 16))
-    //         (func $exec (export "exec") (type $t0) (param $p0 i32) (param $p1 i32) (result i32)
-    //             (i32.load8_u
-    //             (local.get $p0)))
-    //         (global $g0 (mut i32) (i32.const 1048576))
-    //         (global $__data_end (export "__data_end") i32 (i32.const 1048576))
-    //         (global $__heap_base (export "__heap_base") i32 (i32.const 1048576)))
-    //         "#,
-    //     )
-    //     .unwrap();
-
-    //     let expected_result = 12u8;
-
-    //     let data = [expected_result];
-
-    //     let res = exec_wasm_with_data(&binary, &data).unwrap();
-    //     assert_eq!(res, Some(RuntimeValue::I32(expected_result as i32)))
-    // }
+    #[test]
+    fn exec_wasm_enforces_memory_cap() {
+        let wasm_binary = wabt::wat2wasm(COPY_FIRST_BYTE_WAT).unwrap();
+        let input_data = vec![7u8; 1024 * 1024];
+        let limits = ExecLimits {
+            heap_reserve: 0,
+            max_memory_pages: Some(8),
+            ..ExecLimits::default()
+        };
+
+        let err = exec_wasm_with_data(&wasm_binary, &input_data, &limits).unwrap_err();
+        assert!(matches!(
+            err,
+            ExecWasmError::MemoryLimitExceeded { required_pages: 17, max_pages: 8 }
+        ));
+    }
+
+    #[test]
+    fn exec_wasm_honours_module_memory_limits() {
+        let wasm_binary = wabt::wat2wasm(COPY_FIRST_BYTE_WAT.replace("(memory 1)", "(memory 1 2)")).unwrap();
+        let input_data = vec![7u8; 256 * 1024];
+        let limits = ExecLimits {
+            heap_reserve: 0,
+            max_memory_pages: None,
+            ..ExecLimits::default()
+        };
+
+        let err = exec_wasm_with_data(&wasm_binary, &input_data, &limits).unwrap_err();
+        assert!(matches!(
+            err,
+            ExecWasmError::MemoryLimitExceeded { required_pages: 5, max_pages: 2 }
+        ));
+    }
+
+    #[test]
+    fn exec_wasm_uses_guest_allocator() {
+        // A bump allocator placed after a data segment at offset 0, which the legacy layout would overwrite.
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (data (i32.const 0) "\2a")
+                (global $next (mut i32) (i32.const 1024))
+                (func $alloc (export "alloc") (param $len i32) (result i32)
+                    (local $ptr i32)
+                    (set_local $ptr (get_global $next))
+                    (set_global $next (i32.add (get_global $next) (get_local $len)))
+                    (get_local $ptr)
+                )
+                (func $exec (export "exec")
+                    (param $input_ptr i32) (param $input_len i32) (param $output_ptr i32)
+                    (i32.store8 (get_local $output_ptr) (i32.load8_u (i32.const 0)))
+                    (i32.store8 (i32.add (get_local $output_ptr) (i32.const 1)) (i32.load8_u (get_local $input_ptr)))
+                    (i32.store8 (i32.add (get_local $output_ptr) (i32.const 2)) (i32.lt_u (get_local $input_ptr) (i32.const 1024)))
+                )
+            )
+        "#).unwrap();
+        let input_data = vec![7u8; 16];
+        let limits = ExecLimits {
+            max_output_bytes: 3,
+            ..ExecLimits::default()
+        };
+
+        // The third byte is 0, so this legacy guest's output ends before it.
+        let outcome = exec_wasm_with_data(&wasm_binary, &input_data, &limits).expect("WASM execution failed");
+        assert_eq!(outcome.output, [42, 7]);
+    }
+
+    #[test]
+    fn exec_wasm_reports_fuel_used() {
+        let wasm_binary = wabt::wat2wasm(COPY_FIRST_BYTE_WAT).unwrap();
+
+        let outcome = exec_wasm_with_data(&wasm_binary, &[7], &ExecLimits::default())
+            .expect("WASM execution failed");
+        assert_eq!(outcome.output, [7]);
+        assert!(outcome.fuel_used > 0);
+    }
+
+    #[test]
+    fn exec_wasm_stops_when_out_of_fuel() {
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec")
+                    (param $input_ptr i32) (param $input_len i32) (param $output_ptr i32)
+                    (loop $forever (br $forever))
+                )
+            )
+        "#).unwrap();
+        let limits = ExecLimits {
+            fuel: Some(10_000),
+            ..ExecLimits::default()
+        };
+
+        let err = exec_wasm_with_data(&wasm_binary, &[7], &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::OutOfFuel { limit: 10_000 }));
+    }
+
+    #[test]
+    fn exec_wasm_reports_structured_errors() {
+        let limits = ExecLimits::default();
+
+        let err = exec_wasm_with_data(b"not wasm", &[], &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::ModuleDecode(_)));
+
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "print" (func $print (param i32)))
+                (import "env" "memory" (memory 1))
+            )
+        "#).unwrap();
+        let err = exec_wasm_with_data(&wasm_binary, &[], &limits).unwrap_err();
+        assert!(matches!(
+            err,
+            ExecWasmError::UnresolvedImport { ref module, ref field } if module == "env" && field == "print"
+        ));
+
+        let wasm_binary = wabt::wat2wasm(r#"(module (import "env" "memory" (memory 1)))"#).unwrap();
+        let err = exec_wasm_with_data(&wasm_binary, &[], &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::MissingExport { ref name } if name == "exec"));
+
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec") (param i32 f32) (result f32) (f32.const 0))
+            )
+        "#).unwrap();
+        let err = exec_wasm_with_data(&wasm_binary, &[], &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::UnsupportedEntrypoint { .. }));
+
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec") (param i32 i32 i32) unreachable)
+            )
+        "#).unwrap();
+        let err = exec_wasm_with_data(&wasm_binary, &[], &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::Trap(TrapKind::Unreachable)));
+    }
+
+    #[test]
+    fn exec_wasm_dispatches_on_entrypoint_signature() {
+        let limits = ExecLimits::default();
+
+        // Scalar result, like `get-sd-wasm`: returns the input length as f32.
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec") (param i32 i32) (result f32)
+                    (f32.convert_i32_u (get_local 1))
+                )
+            )
+        "#).unwrap();
+        let outcome = exec_wasm_with_data_and_schema(&wasm_binary, b"abc", b"{}", &limits).unwrap();
+        assert_eq!(outcome.return_value, Some(RuntimeValue::F32(3.0.into())));
+        assert!(outcome.output.is_empty());
+
+        // Data, schema and output, like `get-mean-wasm`: writes the schema length to the output.
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec") (param i32 i32 i32 i32 i32 i32)
+                    (i32.store (get_local 4) (get_local 3))
+                )
+            )
+        "#).unwrap();
+        let outcome = exec_wasm_with_data_and_schema(&wasm_binary, b"abc", b"{}", &limits).unwrap();
+        assert_eq!(outcome.return_value, None);
+        assert_eq!(outcome.output, [2]);
+
+        // Data and an output region with its length, like `get-median-wasm`: writes the region's length.
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec") (param i32 i32 i32 i32)
+                    (i32.store (get_local 2) (get_local 3))
+                )
+            )
+        "#).unwrap();
+        let limits = ExecLimits { max_output_bytes: 300, ..ExecLimits::default() };
+        let outcome = exec_wasm_with_data_and_schema(&wasm_binary, b"abc", b"{}", &limits).unwrap();
+        assert_eq!(outcome.output, [44, 1]);
+    }
+
+    /// Appends a custom section called `name` holding `contents` to `binary`.
+    fn with_custom_section(mut binary: Vec<u8>, name: &str, contents: &str) -> Vec<u8> {
+        let mut section = vec![name.len() as u8];
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(contents.as_bytes());
+        assert!(section.len() < 0x80, "test sections must fit a single-byte LEB128 size");
+        binary.push(0);
+        binary.push(section.len() as u8);
+        binary.extend_from_slice(&section);
+        binary
+    }
+
+    /// Appends an `ntc_abi` custom section holding `declaration` to `binary`.
+    fn with_abi_section(binary: Vec<u8>, declaration: &str) -> Vec<u8> {
+        with_custom_section(binary, ABI_SECTION, declaration)
+    }
+
+    #[test]
+    fn exec_wasm_follows_declared_abi() {
+        let limits = ExecLimits::default();
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $run (export "run") (param i32 i32) (result i32) (get_local 1))
+            )
+        "#).unwrap();
+
+        // Without a declaration the runtime looks for `exec`.
+        let err = exec_wasm_with_data(&wasm_binary, b"abc", &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::MissingExport { ref name } if name == "exec"));
+
+        let declared = with_abi_section(wasm_binary.clone(), "version=1\nentry=run\ninput=json\noutput=scalar\n");
+        assert_eq!(
+            module_abi(&declared).unwrap(),
+            ModuleAbi {
+                version: 1,
+                entrypoint: "run".to_string(),
+                input: InputFormat::Json,
+                output: Some(OutputFormat::Scalar),
+            }
+        );
+        let outcome = exec_wasm_with_data(&declared, b"abc", &limits).unwrap();
+        assert_eq!(outcome.return_value, Some(RuntimeValue::I32(3)));
+
+        let mismatched = with_abi_section(wasm_binary.clone(), "version=1\nentry=run\ninput=json\noutput=json\n");
+        let err = exec_wasm_with_data(&mismatched, b"abc", &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::AbiMismatch { output: OutputFormat::Json, .. }));
+
+        let future = with_abi_section(wasm_binary.clone(), "version=3\nentry=run\ninput=json\noutput=scalar\n");
+        let err = exec_wasm_with_data(&future, b"abc", &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::UnsupportedAbiVersion { version: 3 }));
+
+        let malformed = with_abi_section(wasm_binary, "version=1\ninput=xml\noutput=json\n");
+        let err = exec_wasm_with_data(&malformed, b"abc", &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::InvalidAbiDeclaration(_)));
+    }
+
+    #[test]
+    fn exec_wasm_provides_env_host_functions() {
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (import "env" "log" (func $log (param i32 i32)))
+                (import "env" "abort" (func $abort (param i32 i32)))
+                (import "env" "random" (func $random (param i32 i32)))
+                (data (i32.const 60000) "hello")
+                (func $exec (export "exec") (param $data_ptr i32) (param $data_len i32) (param $output_ptr i32)
+                    (call $log (i32.const 60000) (i32.const 5))
+                    (call $random (get_local $output_ptr) (i32.const 8))
+                    (if (i32.load8_u (get_local $data_ptr))
+                        (then (call $abort (i32.const 60000) (i32.const 5))))
+                )
+            )
+        "#).unwrap();
+        let limits = ExecLimits {
+            rng_seed: [7; 32],
+            ..ExecLimits::default()
+        };
+
+        let first = exec_wasm_with_data(&wasm_binary, &[0], &limits).unwrap();
+        assert_eq!(first.log, b"hello\n");
+        assert!(!first.log_truncated);
+        assert!(!first.output.is_empty());
+
+        // The RNG is deterministic for a given seed.
+        let second = exec_wasm_with_data(&wasm_binary, &[0], &limits).unwrap();
+        assert_eq!(first.output, second.output);
+
+        let limits = ExecLimits {
+            max_log_bytes: 3,
+            ..limits
+        };
+        let outcome = exec_wasm_with_data(&wasm_binary, &[0], &limits).unwrap();
+        assert!(outcome.log.is_empty());
+        assert!(outcome.log_truncated);
+
+        let err = exec_wasm_with_data(&wasm_binary, &[1], &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::Aborted { ref message } if message == "hello"));
+    }
+
+    #[test]
+    fn exec_wasm_runs_wasi_guests() {
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "fd_close" (func $fd_close (param i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (import "env" "memory" (memory 1))
+                ;; An iovec pointing at "hi\n".
+                (data (i32.const 60000) "\68\ea\00\00\03\00\00\00hi\n")
+                (func $exec (export "exec") (param $data_ptr i32) (param $data_len i32) (param $output_ptr i32)
+                    (drop (call $fd_write (i32.const 1) (i32.const 60000) (i32.const 1) (i32.const 60016)))
+                    (drop (call $fd_write (i32.const 2) (i32.const 60000) (i32.const 1) (i32.const 60016)))
+                    (i32.store (get_local $output_ptr) (call $fd_close (i32.const 3)))
+                    (i32.store8 (i32.add (get_local $output_ptr) (i32.const 1))
+                        (call $fd_write (i32.const 1) (i32.const 60000) (i32.const 1025) (i32.const 60016)))
+                    (call $proc_exit (i32.load8_u (get_local $data_ptr)))
+                    (unreachable)
+                )
+            )
+        "#).unwrap();
+        let limits = ExecLimits::default();
+
+        let outcome = exec_wasm_with_data(&wasm_binary, &[0], &limits).unwrap();
+        assert_eq!(outcome.stdout, b"hi\n");
+        assert_eq!(outcome.stderr, b"hi\n");
+        // Unsupported calls such as `fd_close` return ENOSYS, and writes of too many iovecs EINVAL.
+        assert_eq!(outcome.output, [52, 28]);
+
+        let err = exec_wasm_with_data(&wasm_binary, &[3], &limits).unwrap_err();
+        assert!(matches!(err, ExecWasmError::Exited { code: 3 }));
+    }
+
+    #[test]
+    fn exec_wasm_runs_start_functions() {
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (global $ready (mut i32) (i32.const 0))
+                (func $init (global.set $ready (i32.const 1)))
+                (start $init)
+                (func $exec (export "exec") (param i32 i32) (result i32) (global.get $ready))
+            )
+        "#).unwrap();
+        let outcome = exec_wasm_with_data(&wasm_binary, &[], &ExecLimits::default()).unwrap();
+        assert_eq!(outcome.return_value, Some(RuntimeValue::I32(1)));
+
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $init (loop $forever (br $forever)))
+                (start $init)
+                (func $exec (export "exec") (param i32 i32) (result i32) (i32.const 0))
+            )
+        "#).unwrap();
+        let limits = ExecLimits {
+            fuel: Some(10_000),
+            ..ExecLimits::default()
+        };
+        let err = exec_wasm_with_data(&wasm_binary, &[], &limits).unwrap_err();
+        match err {
+            ExecWasmError::StartFailed(err) => assert!(matches!(*err, ExecWasmError::OutOfFuel { limit: 10_000 })),
+            other => panic!("unexpected error: {}", other),
+        }
+
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (func $init unreachable)
+                (start $init)
+                (func $exec (export "exec") (result i32) (i32.const 0))
+            )
+        "#).unwrap();
+        let err = exec_wasm(&wasm_binary).unwrap_err();
+        match err {
+            ExecWasmError::StartFailed(err) => assert!(matches!(*err, ExecWasmError::Trap(TrapKind::Unreachable))),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn exec_wasm_reuses_prepared_modules() {
+        // The enclave shares prepared modules between jobs.
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<PreparedModule>();
+
+        let wasm_binary = wabt::wat2wasm(COPY_FIRST_BYTE_WAT).unwrap();
+        let prepared = prepare_module(&wasm_binary).unwrap();
+        let limits = ExecLimits::default();
+        for byte in [3u8, 5] {
+            let outcome = exec_prepared_module(&prepared, &[byte], &[], &limits).unwrap();
+            assert_eq!(outcome.output, [byte]);
+        }
+    }
+
+    #[test]
+    fn prepare_module_reads_output_schema() {
+        let wasm_binary = wabt::wat2wasm(r#"
+            (module
+                (import "env" "memory" (memory 1))
+                (func $exec (export "exec") (param i32 i32) (result f32) (f32.const 1))
+            )
+        "#).unwrap();
+        assert!(prepare_module(&wasm_binary).unwrap().output_schema().is_none());
+
+        let schema = r#"{"type":"object","additionalProperties":{"type":"number"}}"#;
+        let declared = with_custom_section(wasm_binary.clone(), OUTPUT_SCHEMA_SECTION, schema);
+        let module = prepare_module(&declared).unwrap();
+        let output_schema = module.output_schema().unwrap();
+        schema::validate(&serde_json::json!({ "Column_1": 2.5 }), output_schema).unwrap();
+        let errors = schema::validate(&serde_json::json!({ "Column_1": [1, 2, 3] }), output_schema).unwrap_err();
+        assert_eq!(errors[0].to_string(), "/Column_1: expected number, found array");
+
+        let closed = serde_json::json!({ "properties": { "mean": { "type": "number" } }, "additionalProperties": false });
+        let errors = schema::validate(&serde_json::json!({ "mean": 2, "rows": [] }), &closed).unwrap_err();
+        assert_eq!(errors[0].to_string(), "(root): unexpected property `rows`");
+
+        for invalid in &["[1]", "{\"type\":", ""] {
+            let declared = with_custom_section(wasm_binary.clone(), OUTPUT_SCHEMA_SECTION, invalid);
+            assert!(matches!(prepare_module(&declared), Err(ExecWasmError::InvalidOutputSchema(_))));
+        }
+    }
 }
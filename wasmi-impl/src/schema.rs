@@ -0,0 +1,352 @@
+//! Validation of JSON against the draft-07 subset that dataset and output schemas use.
+//!
+//! Supported keywords are `type`, `properties`, `additionalProperties`, `required`, `items`, `minItems`,
+//! `minimum`, `maximum` and `enum`; others, including `$schema`, are ignored as the specification requires
+//! of unknown keywords.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use serde_json::{Map, Value};
+
+/// Validation stops after this many errors.
+pub const MAX_SCHEMA_ERRORS: usize = 64;
+
+/// A value that does not satisfy its schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaError {
+    /// JSON pointer (RFC 6901) to the offending value; empty for the document itself.
+    pub pointer: String,
+    /// The schema keyword the value fails, if the error concerns one.
+    pub keyword: Option<&'static str>,
+    pub message: String,
+}
+
+impl SchemaError {
+    /// The error without its message, which may quote the offending value: only where the value is and which
+    /// keyword it fails.
+    pub fn redacted(&self) -> String {
+        let pointer = if self.pointer.is_empty() { "(root)" } else { &self.pointer };
+        match self.keyword {
+            Some(keyword) => format!("{}: value fails `{}`", pointer, keyword),
+            None => format!("{}: value is not valid", pointer),
+        }
+    }
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() { "(root)" } else { &self.pointer };
+        write!(f, "{}: {}", pointer, self.message)
+    }
+}
+
+/// Checks `data` against `schema`, returning every violation found (up to `MAX_SCHEMA_ERRORS`).
+pub fn validate(data: &Value, schema: &Value) -> Result<(), Vec<SchemaError>> {
+    let mut validator = Validator { errors: Vec::new() };
+    let mut pointer = String::new();
+    validator.check(data, schema, &mut pointer);
+    if validator.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(validator.errors)
+    }
+}
+
+/// Parses `data` and `schema` and validates one against the other. Input that is not JSON is reported as a
+/// single error at the root.
+pub fn validate_json(data: &[u8], schema: &[u8]) -> Result<(), Vec<SchemaError>> {
+    let parse = |what: &str, bytes: &[u8]| {
+        serde_json::from_slice::<Value>(bytes).map_err(|e| {
+            let message = format!("{} is not valid JSON: {}", what, e);
+            vec![SchemaError { pointer: String::new(), keyword: None, message }]
+        })
+    };
+    let schema = parse("schema", schema)?;
+    let data = parse("data", data)?;
+    validate(&data, &schema)
+}
+
+struct Validator {
+    errors: Vec<SchemaError>,
+}
+
+impl Validator {
+    fn error(&mut self, pointer: &str, keyword: Option<&'static str>, message: String) {
+        if self.errors.len() < MAX_SCHEMA_ERRORS {
+            self.errors.push(SchemaError { pointer: pointer.to_string(), keyword, message });
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.errors.len() >= MAX_SCHEMA_ERRORS
+    }
+
+    fn check(&mut self, value: &Value, schema: &Value, pointer: &mut String) {
+        let schema = match schema {
+            Value::Object(schema) => schema,
+            Value::Bool(true) => return,
+            Value::Bool(false) => return self.error(pointer, None, "no value is allowed here".to_string()),
+            _ => return self.error(pointer, None, "schema must be an object or a boolean".to_string()),
+        };
+
+        if let Some(types) = schema.get("type") {
+            if !self.check_type(value, types, pointer) {
+                // The remaining keywords assume the declared type; checking them would only add noise.
+                return;
+            }
+        }
+        if let Some(allowed) = schema.get("enum") {
+            match allowed {
+                Value::Array(allowed) if allowed.iter().any(|candidate| json_eq(candidate, value)) => {}
+                Value::Array(_) => {
+                    self.error(pointer, Some("enum"), "value is not one of the allowed values".to_string())
+                }
+                _ => self.error(pointer, Some("enum"), "schema keyword `enum` must be an array".to_string()),
+            }
+        }
+        match value {
+            Value::Number(number) => {
+                let number = number.as_f64().unwrap_or(f64::NAN);
+                if let Some(minimum) = self.number_keyword(schema, "minimum", pointer) {
+                    if number < minimum {
+                        let message = format!("{} is less than the minimum of {}", number, minimum);
+                        self.error(pointer, Some("minimum"), message);
+                    }
+                }
+                if let Some(maximum) = self.number_keyword(schema, "maximum", pointer) {
+                    if number > maximum {
+                        let message = format!("{} is greater than the maximum of {}", number, maximum);
+                        self.error(pointer, Some("maximum"), message);
+                    }
+                }
+            }
+            Value::Array(items) => self.check_array(items, schema, pointer),
+            Value::Object(object) => self.check_object(object, schema, pointer),
+            _ => {}
+        }
+    }
+
+    /// Reports `value` if it is not of one of `types`; returns whether it is.
+    fn check_type(&mut self, value: &Value, types: &Value, pointer: &str) -> bool {
+        let matches = match types {
+            Value::String(name) => has_type(value, name),
+            Value::Array(names) => names.iter().any(|name| matches!(name.as_str(), Some(name) if has_type(value, name))),
+            _ => {
+                self.error(pointer, Some("type"), "schema keyword `type` must be a string or an array".to_string());
+                return true;
+            }
+        };
+        if !matches {
+            let expected = match types {
+                Value::String(name) => name.clone(),
+                _ => types.to_string(),
+            };
+            self.error(pointer, Some("type"), format!("expected {}, found {}", expected, type_name(value)));
+        }
+        matches
+    }
+
+    fn check_array(&mut self, items: &[Value], schema: &Map<String, Value>, pointer: &mut String) {
+        if let Some(min_items) = schema.get("minItems") {
+            match min_items.as_u64() {
+                Some(min_items) if (items.len() as u64) < min_items => self.error(
+                    pointer,
+                    Some("minItems"),
+                    format!("expected at least {} items, found {}", min_items, items.len()),
+                ),
+                Some(_) => {}
+                None => {
+                    let message = "schema keyword `minItems` must be a non-negative integer".to_string();
+                    self.error(pointer, Some("minItems"), message)
+                }
+            }
+        }
+        let item_schemas = match schema.get("items") {
+            Some(item_schemas) => item_schemas,
+            None => return,
+        };
+        for (index, item) in items.iter().enumerate() {
+            // An array of schemas validates items by position; items beyond it are unconstrained.
+            let item_schema = match item_schemas {
+                Value::Array(tuple) => match tuple.get(index) {
+                    Some(item_schema) => item_schema,
+                    None => break,
+                },
+                item_schema => item_schema,
+            };
+            if self.is_full() {
+                return;
+            }
+            let len = pointer.len();
+            push_token(pointer, &index.to_string());
+            self.check(item, item_schema, pointer);
+            pointer.truncate(len);
+        }
+    }
+
+    fn check_object(&mut self, object: &Map<String, Value>, schema: &Map<String, Value>, pointer: &mut String) {
+        match schema.get("required") {
+            Some(Value::Array(required)) => {
+                for name in required {
+                    match name.as_str() {
+                        Some(name) if !object.contains_key(name) => {
+                            self.error(pointer, Some("required"), format!("missing required property `{}`", name))
+                        }
+                        Some(_) => {}
+                        None => {
+                            let message = "schema keyword `required` must list strings".to_string();
+                            self.error(pointer, Some("required"), message)
+                        }
+                    }
+                }
+            }
+            Some(_) => self.error(pointer, Some("required"), "schema keyword `required` must be an array".to_string()),
+            None => {}
+        }
+        let properties = match schema.get("properties") {
+            Some(Value::Object(properties)) => Some(properties),
+            Some(_) => {
+                self.error(pointer, Some("properties"), "schema keyword `properties` must be an object".to_string());
+                None
+            }
+            None => None,
+        };
+        // Properties not listed in `properties` are checked against `additionalProperties`, if present.
+        let additional = schema.get("additionalProperties");
+        for (name, property) in object {
+            let property_schema = match properties.and_then(|properties| properties.get(name)) {
+                Some(property_schema) => property_schema,
+                None => match additional {
+                    Some(Value::Bool(false)) => {
+                        self.error(pointer, Some("additionalProperties"), format!("unexpected property `{}`", name));
+                        continue;
+                    }
+                    Some(additional) => additional,
+                    None => continue,
+                },
+            };
+            if self.is_full() {
+                return;
+            }
+            let len = pointer.len();
+            push_token(pointer, name);
+            self.check(property, property_schema, pointer);
+            pointer.truncate(len);
+        }
+    }
+
+    fn number_keyword(&mut self, schema: &Map<String, Value>, keyword: &'static str, pointer: &str) -> Option<f64> {
+        let value = schema.get(keyword)?;
+        let number = value.as_f64();
+        if number.is_none() {
+            self.error(pointer, Some(keyword), format!("schema keyword `{}` must be a number", keyword));
+        }
+        number
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match (name, value) {
+        ("null", Value::Null) | ("boolean", Value::Bool(_)) | ("string", Value::String(_)) => true,
+        ("array", Value::Array(_)) | ("object", Value::Object(_)) | ("number", Value::Number(_)) => true,
+        ("integer", Value::Number(number)) => {
+            number.is_i64() || number.is_u64() || matches!(number.as_f64(), Some(n) if n.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// JSON equality as `enum` defines it: numbers compare by value, so `1` equals `1.0`.
+fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Value::Array(a), Value::Array(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| json_eq(a, b)),
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(key, a)| matches!(b.get(key), Some(b) if json_eq(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+/// Appends `token` to a JSON pointer, escaping `~` and `/` as RFC 6901 requires.
+pub(crate) fn push_token(pointer: &mut String, token: &str) {
+    pointer.push('/');
+    for c in token.chars() {
+        match c {
+            '~' => pointer.push_str("~0"),
+            '/' => pointer.push_str("~1"),
+            c => pointer.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_validation_reports_json_pointers() {
+        let schema = br#"{
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "type": "object",
+            "properties": {
+                "Column_1": { "type": "array", "items": { "type": "number", "minimum": 0, "maximum": 10 }, "minItems": 2 },
+                "unit/name": { "enum": ["cm", "m"] },
+                "count": { "type": "integer" }
+            },
+            "required": ["Column_1", "Column_2"]
+        }"#;
+
+        validate_json(br#"{"Column_1": [1, 2.5], "Column_2": [], "unit/name": "m", "count": 3.0}"#, schema)
+            .unwrap();
+        validate_json(b"[1]", b"{}").unwrap();
+
+        let errors = validate_json(
+            br#"{"Column_1": [1, "two", 11], "unit/name": "km", "count": 1.5}"#,
+            schema,
+        )
+        .unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "(root): missing required property `Column_2`",
+                "/Column_1/1: expected number, found string",
+                "/Column_1/2: 11 is greater than the maximum of 10",
+                "/count: expected integer, found number",
+                "/unit~1name: value is not one of the allowed values",
+            ]
+        );
+        // Redacted errors name neither the value nor the bound it breaks.
+        assert_eq!(errors[2].redacted(), "/Column_1/2: value fails `maximum`");
+
+        let errors = validate_json(br#"{"Column_1": [1]}"#, schema).unwrap_err();
+        assert_eq!(errors[1], SchemaError {
+            pointer: "/Column_1".to_string(),
+            keyword: Some("minItems"),
+            message: "expected at least 2 items, found 1".to_string(),
+        });
+        assert_eq!(errors[1].redacted(), "/Column_1: value fails `minItems`");
+        let errors = validate_json(b"{not json", schema).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].pointer, "");
+
+        let many = Value::Array(vec![Value::Null; 100]);
+        let errors = validate(&many, &serde_json::json!({ "items": { "type": "number" } })).unwrap_err();
+        assert_eq!(errors.len(), MAX_SCHEMA_ERRORS);
+    }
+}
@@ -0,0 +1,197 @@
+//! A minimal subset of WASI preview1 for guests built for `wasm32-wasi`.
+//!
+//! Only what `std` needs to start up and print is implemented, without touching anything outside the
+//! enclave: stdout and stderr are captured into the job's outcome, arguments and environment are empty,
+//! randomness comes from the job's seeded RNG and clocks always read zero. Every other preview1 function,
+//! including all filesystem and socket calls, returns `ENOSYS`.
+
+use crate::{HostExternals, HostTrap, MAX_MESSAGE_LEN, MAX_RANDOM_LEN};
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec;
+use core::cmp;
+use wasmi::{
+    FuncInstance, FuncRef, ModuleImportResolver, RuntimeArgs, RuntimeValue, Signature, Trap, TrapKind,
+    ValueType,
+};
+
+/// Import module name used by `wasm32-wasi` guests.
+pub(crate) static MODULE: &str = "wasi_snapshot_preview1";
+
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_NOSYS: i32 = 52;
+
+/// Most iovecs `fd_write` takes per call, as `IOV_MAX` on Linux.
+const MAX_IOVS: u32 = 1024;
+
+const STDOUT: u32 = 1;
+const STDERR: u32 = 2;
+
+// Host function indices, kept clear of the `env` functions.
+const ARGS_GET_INDEX: usize = 100;
+const ARGS_SIZES_GET_INDEX: usize = 101;
+const ENVIRON_GET_INDEX: usize = 102;
+const ENVIRON_SIZES_GET_INDEX: usize = 103;
+const FD_WRITE_INDEX: usize = 104;
+const RANDOM_GET_INDEX: usize = 105;
+const PROC_EXIT_INDEX: usize = 106;
+const CLOCK_TIME_GET_INDEX: usize = 107;
+const CLOCK_RES_GET_INDEX: usize = 108;
+/// Shared by every unsupported function; it only returns `ENOSYS`, so any signature returning an errno will do.
+const NOSYS_INDEX: usize = 109;
+
+const I32: ValueType = ValueType::I32;
+
+/// Returns the signature and host function index of a supported WASI function.
+fn supported_function(field_name: &str) -> Option<(&'static [ValueType], Option<ValueType>, usize)> {
+    let function = match field_name {
+        "args_get" => (&[I32, I32][..], Some(I32), ARGS_GET_INDEX),
+        "args_sizes_get" => (&[I32, I32][..], Some(I32), ARGS_SIZES_GET_INDEX),
+        "environ_get" => (&[I32, I32][..], Some(I32), ENVIRON_GET_INDEX),
+        "environ_sizes_get" => (&[I32, I32][..], Some(I32), ENVIRON_SIZES_GET_INDEX),
+        "fd_write" => (&[I32, I32, I32, I32][..], Some(I32), FD_WRITE_INDEX),
+        "random_get" => (&[I32, I32][..], Some(I32), RANDOM_GET_INDEX),
+        "proc_exit" => (&[I32][..], None, PROC_EXIT_INDEX),
+        "clock_time_get" => (&[I32, ValueType::I64, I32][..], Some(I32), CLOCK_TIME_GET_INDEX),
+        "clock_res_get" => (&[I32, I32][..], Some(I32), CLOCK_RES_GET_INDEX),
+        _ => return None,
+    };
+    Some(function)
+}
+
+/// Whether the WASI import `field_name` can be provided. Unsupported functions are stubbed as long as
+/// they return an errno, as every preview1 function apart from `proc_exit` does.
+pub(crate) fn is_provided(field_name: &str, returns_i32: bool) -> bool {
+    supported_function(field_name).is_some() || returns_i32
+}
+
+/// Whether `index` is a host function implemented by this module.
+pub(crate) fn handles(index: usize) -> bool {
+    (ARGS_GET_INDEX..=NOSYS_INDEX).contains(&index)
+}
+
+/// Resolves imports from `wasi_snapshot_preview1`.
+pub(crate) struct WasiResolver;
+
+impl ModuleImportResolver for WasiResolver {
+    fn resolve_func(&self, field_name: &str, signature: &Signature) -> Result<FuncRef, wasmi::Error> {
+        match supported_function(field_name) {
+            Some((params, return_type, index)) => {
+                Ok(FuncInstance::alloc_host(Signature::new(params, return_type), index))
+            }
+            None if signature.return_type() == Some(I32) => {
+                Ok(FuncInstance::alloc_host(signature.clone(), NOSYS_INDEX))
+            }
+            None => Err(wasmi::Error::Instantiation(format!("No such function: {}", field_name))),
+        }
+    }
+}
+
+impl HostExternals {
+    /// Runs the WASI host function `index`, returning its errno.
+    pub(crate) fn invoke_wasi(&mut self, index: usize, args: RuntimeArgs) -> Result<Option<RuntimeValue>, Trap> {
+        let errno = match index {
+            ARGS_SIZES_GET_INDEX | ENVIRON_SIZES_GET_INDEX => {
+                // No arguments or environment variables, taking up no bytes.
+                self.write_u32(args.nth_checked(0)?, 0)?;
+                self.write_u32(args.nth_checked(1)?, 0)?;
+                ERRNO_SUCCESS
+            }
+            ARGS_GET_INDEX | ENVIRON_GET_INDEX => ERRNO_SUCCESS,
+            FD_WRITE_INDEX => self.fd_write(&args)?,
+            RANDOM_GET_INDEX => {
+                let ptr: u32 = args.nth_checked(0)?;
+                let len: u32 = args.nth_checked(1)?;
+                if len > MAX_RANDOM_LEN {
+                    ERRNO_INVAL
+                } else {
+                    self.fill_random(ptr, len)?;
+                    ERRNO_SUCCESS
+                }
+            }
+            PROC_EXIT_INDEX => {
+                let code: u32 = args.nth_checked(0)?;
+                return Err(Trap::new(TrapKind::Host(Box::new(HostTrap::Exit(code)))));
+            }
+            CLOCK_TIME_GET_INDEX => {
+                // Time is not observable inside the sandbox: every clock reads zero.
+                let time_ptr: u32 = args.nth_checked(2)?;
+                self.write_bytes(time_ptr, &0u64.to_le_bytes())?;
+                ERRNO_SUCCESS
+            }
+            CLOCK_RES_GET_INDEX => {
+                let resolution_ptr: u32 = args.nth_checked(1)?;
+                self.write_bytes(resolution_ptr, &1u64.to_le_bytes())?;
+                ERRNO_SUCCESS
+            }
+            _ => ERRNO_NOSYS,
+        };
+        Ok(Some(RuntimeValue::I32(errno)))
+    }
+
+    /// `fd_write(fd, iovs, iovs_len, nwritten)`: captures writes to stdout and stderr.
+    ///
+    /// At most `MAX_MESSAGE_LEN` bytes are taken per call; `nwritten` tells the guest to retry with the rest.
+    /// Calls with more than `MAX_IOVS` iovecs fail with `EINVAL`, and every iovec read costs fuel as a byte does,
+    /// so that empty iovecs do not keep the host busy for free.
+    fn fd_write(&mut self, args: &RuntimeArgs) -> Result<i32, Trap> {
+        let fd: u32 = args.nth_checked(0)?;
+        let iovs: u32 = args.nth_checked(1)?;
+        let iovs_len: u32 = args.nth_checked(2)?;
+        let nwritten_ptr: u32 = args.nth_checked(3)?;
+        if fd != STDOUT && fd != STDERR {
+            return Ok(ERRNO_BADF);
+        }
+        if iovs_len > MAX_IOVS {
+            return Ok(ERRNO_INVAL);
+        }
+
+        let mut written = vec![];
+        let mut iovs_read = 0;
+        for i in 0..iovs_len {
+            let remaining = MAX_MESSAGE_LEN as usize - written.len();
+            if remaining == 0 {
+                break;
+            }
+            let iov = iovs.checked_add(i.saturating_mul(8)).ok_or_else(out_of_bounds)?;
+            let buf = self.read_u32(iov)?;
+            let buf_len = self.read_u32(iov.checked_add(4).ok_or_else(out_of_bounds)?)? as usize;
+            let bytes = self
+                .memory
+                .get(buf, cmp::min(buf_len, remaining))
+                .map_err(|_| out_of_bounds())?;
+            written.extend_from_slice(&bytes);
+            iovs_read += 1;
+        }
+        self.charge_fuel(iovs_read + written.len() as u64)?;
+
+        let stream = if fd == STDOUT { &mut self.stdout } else { &mut self.stderr };
+        let kept = cmp::min(written.len(), self.max_log_bytes.saturating_sub(stream.len()));
+        stream.extend_from_slice(&written[..kept]);
+        if kept < written.len() {
+            self.log_truncated = true;
+        }
+        self.write_u32(nwritten_ptr, written.len() as u32)?;
+        Ok(ERRNO_SUCCESS)
+    }
+
+    fn read_u32(&self, ptr: u32) -> Result<u32, Trap> {
+        let mut bytes = [0u8; 4];
+        self.memory.get_into(ptr, &mut bytes).map_err(|_| out_of_bounds())?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_u32(&self, ptr: u32, value: u32) -> Result<(), Trap> {
+        self.write_bytes(ptr, &value.to_le_bytes())
+    }
+
+    fn write_bytes(&self, ptr: u32, bytes: &[u8]) -> Result<(), Trap> {
+        self.memory.set(ptr, bytes).map_err(|_| out_of_bounds())
+    }
+}
+
+fn out_of_bounds() -> Trap {
+    Trap::new(TrapKind::MemoryAccessOutOfBounds)
+}